@@ -0,0 +1,299 @@
+//! Access logging in Common Log Format, Combined Log Format or as JSON lines.
+//!
+//! Entries are formatted on the event loop thread and written by a background thread, so
+//! handling requests never waits for the log file. The writer is buffered and flushed at the
+//! latest `FLUSH_INTERVAL_MS` after an entry was written and when the log is dropped. At most
+//! `QUEUE_SIZE` entries wait for the writer, further entries are dropped until it catches up.
+//!
+//! Wrap the handler in `Chain<LogRequests, H>` and implement `AccessLogs` for the context to
+//! log every request.
+use std::fs::OpenOptions;
+use std::io::{self, BufWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, SyncSender, TrySendError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use time::{self, Tm};
+
+use middleware::Middleware;
+use {Body, Message, Request, Response};
+
+/// Buffered entries are written at the latest after this many milliseconds.
+pub const FLUSH_INTERVAL_MS: u64 = 1000;
+
+/// The number of entries that may wait for the writer thread.
+pub const QUEUE_SIZE: usize = 4096;
+
+/// The layout of log lines.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Format {
+    /// `host ident authuser [date] "request-line" status bytes`
+    Common,
+    /// The common format followed by `"referer" "user-agent"`.
+    Combined,
+    /// One JSON object per line including the latency.
+    Json,
+}
+
+/// The address of the client, attached to requests as an extension.
+///
+/// `server::Server` records it for every request, other servers pass it to
+/// `http1::Client::data_received_from`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct PeerAddr(pub SocketAddr);
+
+/// A record of a request and its response.
+#[derive(Clone, Debug)]
+pub struct Entry {
+    /// The client address, if the server recorded it as a `PeerAddr` extension.
+    pub peer: Option<SocketAddr>,
+    /// When the request was received.
+    pub time: Tm,
+    pub method: String,
+    pub target: String,
+    pub version: String,
+    pub status: u16,
+    /// The size of the response body, unknown for streamed bodies.
+    pub bytes: Option<u64>,
+    pub referer: Option<String>,
+    pub user_agent: Option<String>,
+    /// The time taken to produce the response.
+    pub latency: Duration,
+    /// Measures the latency, unlike `time` it is not affected by changes of the system clock.
+    started: Instant,
+}
+
+impl Entry {
+    /// Records the request, the response is added by `finish`.
+    pub fn new(req: &Request) -> Entry {
+        let header = |name| {
+            req.get_value_header(name).map(|value| String::from_utf8_lossy(value).into_owned())
+        };
+        Entry {
+            peer: req.extensions().get::<PeerAddr>().map(|peer| peer.0),
+            time: time::now(),
+            method: req.method.to_string(),
+            target: req.target().to_owned(),
+            version: req.version.to_string(),
+            status: 0,
+            bytes: None,
+            referer: header("Referer"),
+            user_agent: header("User-Agent"),
+            latency: Duration::from_secs(0),
+            started: Instant::now(),
+        }
+    }
+
+    /// Records the status and body size of the response and the latency.
+    pub fn finish(&mut self, res: &Response) {
+        self.status = res.status.to_u16();
+        self.bytes = match res.body() {
+            Some(&Body::Bytes(ref bytes)) => Some(bytes.len() as u64),
+            Some(&Body::File(ref file)) => Some(file.len()),
+            Some(&Body::Stream(_)) => None,
+            None => Some(0),
+        };
+        self.latency = self.started.elapsed();
+    }
+
+    /// Formats the entry as a line including the line break.
+    pub fn format(&self, format: Format) -> String {
+        let peer = self.peer.map(|peer| peer.ip().to_string());
+        if format == Format::Json {
+            let micros = self.latency.as_secs() * 1_000_000 +
+                         self.latency.subsec_nanos() as u64 / 1000;
+            let optional = |value: Option<&String>| {
+                value.map(|v| json_string(v)).unwrap_or_else(|| "null".to_owned())
+            };
+            return format!("{{\"time\":\"{}\",\"peer\":{},\"method\":{},\"target\":{},\
+                            \"version\":{},\"status\":{},\"bytes\":{},\"referer\":{},\
+                            \"user_agent\":{},\"latency_us\":{}}}\n",
+                           self.time.to_utc().rfc3339(),
+                           optional(peer.as_ref()),
+                           json_string(&self.method),
+                           json_string(&self.target),
+                           json_string(&self.version),
+                           self.status,
+                           self.bytes.map(|b| b.to_string()).unwrap_or_else(|| "null".to_owned()),
+                           optional(self.referer.as_ref()),
+                           optional(self.user_agent.as_ref()),
+                           micros);
+        }
+        let mut line = format!("{} - - [{}] \"{} {} {}\" {} {}",
+                               peer.as_ref().map(|p| &p[..]).unwrap_or("-"),
+                               clf_time(&self.time),
+                               escape(&self.method),
+                               escape(&self.target),
+                               escape(&self.version),
+                               self.status,
+                               match self.bytes {
+                                   Some(0) | None => "-".to_owned(),
+                                   Some(bytes) => bytes.to_string(),
+                               });
+        if format == Format::Combined {
+            let quoted = |value: Option<&String>| {
+                value.map(|v| format!("\"{}\"", escape(v))).unwrap_or_else(|| "\"-\"".to_owned())
+            };
+            line.push_str(&format!(" {} {}",
+                                   quoted(self.referer.as_ref()),
+                                   quoted(self.user_agent.as_ref())));
+        }
+        line.push('\n');
+        line
+    }
+}
+
+/// Formats a time like `10/Oct/2000:13:55:36 -0700`.
+fn clf_time(tm: &Tm) -> String {
+    // `%z` formats UTC as `-0000`, which means an unknown offset.
+    let offset = tm.tm_utcoff.abs() / 60;
+    format!("{} {}{:02}{:02}",
+            tm.strftime("%d/%b/%Y:%H:%M:%S").unwrap(),
+            if tm.tm_utcoff < 0 { '-' } else { '+' },
+            offset / 60,
+            offset % 60)
+}
+
+/// Escapes quotes, backslashes and control characters for the text formats.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            c if (c as u32) < 0x20 || c as u32 == 0x7f => {
+                escaped.push_str(&format!("\\x{:02x}", c as u32))
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 || c as u32 == 0x7f => {
+                escaped.push_str(&format!("\\u{:04x}", c as u32))
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// An access log writing to a sink on a background thread.
+pub struct AccessLog {
+    format: Format,
+    sender: Option<SyncSender<String>>,
+    thread: Option<JoinHandle<()>>,
+    dropped: AtomicUsize,
+}
+
+impl AccessLog {
+    /// Logs to any writer, for example `io::stdout()`.
+    pub fn new<W: Write + Send + 'static>(sink: W, format: Format) -> AccessLog {
+        let (sender, receiver) = mpsc::sync_channel::<String>(QUEUE_SIZE);
+        let thread = thread::spawn(move || {
+            let interval = Duration::from_millis(FLUSH_INTERVAL_MS);
+            let mut sink = BufWriter::new(sink);
+            // The time of the oldest entry that was not flushed yet.
+            let mut unflushed: Option<Instant> = None;
+            // Write errors are ignored, there is nowhere to report them.
+            loop {
+                let received = match unflushed.map(|since| since.elapsed()) {
+                    Some(elapsed) if elapsed >= interval => {
+                        let _ = sink.flush();
+                        unflushed = None;
+                        continue;
+                    }
+                    Some(elapsed) => receiver.recv_timeout(interval - elapsed),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok(line) => {
+                        let _ = sink.write_all(line.as_bytes());
+                        if unflushed.is_none() {
+                            unflushed = Some(Instant::now());
+                        }
+                    }
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            let _ = sink.flush();
+        });
+        AccessLog {
+            format: format,
+            sender: Some(sender),
+            thread: Some(thread),
+            dropped: AtomicUsize::new(0),
+        }
+    }
+
+    /// Appends to a file, creating it if it does not exist.
+    pub fn open<P: AsRef<Path>>(path: P, format: Format) -> io::Result<AccessLog> {
+        let file = try!(OpenOptions::new().append(true).create(true).open(path));
+        Ok(AccessLog::new(file, format))
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// Queues an entry for writing, it is dropped if the queue is full.
+    pub fn log(&self, entry: &Entry) {
+        if let Some(ref sender) = self.sender {
+            if let Err(TrySendError::Full(_)) = sender.try_send(entry.format(self.format)) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// The number of entries dropped because the writer fell behind.
+    pub fn dropped(&self) -> usize {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Drop for AccessLog {
+    /// Writes all queued entries.
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Access to the `AccessLog` in the context.
+pub trait AccessLogs {
+    fn access_log(&self) -> &AccessLog;
+}
+
+/// Middleware logging each request with the `AccessLog` of the context.
+pub struct LogRequests;
+
+impl<C: AccessLogs> Middleware<C> for LogRequests {
+    type State = Entry;
+
+    fn before(req: &mut Request, _: &mut C) -> Result<Entry, Response> {
+        Ok(Entry::new(req))
+    }
+
+    fn after(mut entry: Entry, res: &mut Response, ctx: &mut C) {
+        entry.finish(res);
+        ctx.access_log().log(&entry);
+    }
+}
@@ -0,0 +1,253 @@
+//! HTTP authentication with the `Basic` (RFC 7617) and `Bearer` (RFC 6750) schemes.
+//!
+//! `Credentials::from_request` parses the `Authorization` header. A `Verifier` checks the
+//! credentials and returns the authenticated user. The `Authenticate` middleware stores the
+//! user as an extension of the request and answers requests without valid credentials with
+//! `401 Unauthorized` and a `WWW-Authenticate` challenge.
+use std::any::Any;
+use std::ascii::AsciiExt;
+use std::str;
+
+use middleware::Middleware;
+use {Message, Request, Response, StatusCode};
+
+/// An authentication scheme.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Scheme {
+    Basic,
+    Bearer,
+}
+
+/// Credentials sent by a client.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Credentials {
+    Basic {
+        username: String,
+        password: String,
+    },
+    Bearer(String),
+}
+
+impl Credentials {
+    /// Parses the `Authorization` header.
+    ///
+    /// Returns `None` if the header is missing, malformed or uses another scheme. Basic
+    /// credentials must be UTF-8 encoded.
+    pub fn from_request(req: &Request) -> Option<Credentials> {
+        if let Some(token) = authorization(req, "Basic") {
+            let decoded = match decode_base64(token) {
+                Some(decoded) => decoded,
+                None => return None,
+            };
+            let decoded = match String::from_utf8(decoded) {
+                Ok(decoded) => decoded,
+                Err(_) => return None,
+            };
+            let colon = match decoded.find(':') {
+                Some(colon) => colon,
+                None => return None,
+            };
+            return Some(Credentials::Basic {
+                username: decoded[..colon].to_owned(),
+                password: decoded[colon + 1..].to_owned(),
+            });
+        }
+        authorization(req, "Bearer").and_then(|token| {
+            if is_token68(token) {
+                str::from_utf8(token).ok().map(|token| Credentials::Bearer(token.to_owned()))
+            } else {
+                None
+            }
+        })
+    }
+
+    pub fn scheme(&self) -> Scheme {
+        match *self {
+            Credentials::Basic { .. } => Scheme::Basic,
+            Credentials::Bearer(_) => Scheme::Bearer,
+        }
+    }
+}
+
+/// Returns the parameters of the `Authorization` header if it uses the given scheme.
+pub fn authorization<'a>(req: &'a Request, scheme: &str) -> Option<&'a [u8]> {
+    let value = match req.get_value_header("Authorization") {
+        Some(value) => value,
+        None => return None,
+    };
+    let end = value.iter().position(|&b| b == b' ').unwrap_or(value.len());
+    if !value[..end].eq_ignore_ascii_case(scheme.as_bytes()) {
+        return None;
+    }
+    let params = &value[end..];
+    let start = params.iter().position(|&b| b != b' ').unwrap_or(params.len());
+    let params = &params[start..];
+    if params.is_empty() {
+        None
+    } else {
+        Some(params)
+    }
+}
+
+fn is_token68(value: &[u8]) -> bool {
+    let end = value.iter().position(|&b| b == b'=').unwrap_or(value.len());
+    end > 0 && value[end..].iter().all(|&b| b == b'=') &&
+    value[..end].iter().all(|&b| {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' | b'+' | b'/' => {
+                true
+            }
+            _ => false,
+        }
+    })
+}
+
+/// Decodes standard base64 with optional padding.
+fn decode_base64(value: &[u8]) -> Option<Vec<u8>> {
+    let end = value.iter().position(|&b| b == b'=').unwrap_or(value.len());
+    if value.len() - end > 2 || value[end..].iter().any(|&b| b != b'=') || end % 4 == 1 {
+        return None;
+    }
+    let mut decoded = Vec::with_capacity(end * 3 / 4);
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for &b in &value[..end] {
+        let sextet = match b {
+            b'A'...b'Z' => b - b'A',
+            b'a'...b'z' => b - b'a' + 26,
+            b'0'...b'9' => b - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = buffer << 6 | sextet as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(decoded)
+}
+
+/// Quotes a value for a challenge parameter.
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Checks credentials.
+pub trait Verifier {
+    /// The authenticated user, stored as an extension of the request.
+    type User: Any + Send;
+
+    fn verify(&self, credentials: &Credentials) -> Option<Self::User>;
+}
+
+impl<U: Any + Send, F: Fn(&Credentials) -> Option<U>> Verifier for F {
+    type User = U;
+
+    fn verify(&self, credentials: &Credentials) -> Option<U> {
+        self(credentials)
+    }
+}
+
+/// The accepted schemes, the realm and the verifier of a protected resource.
+pub struct Auth<V> {
+    schemes: Vec<Scheme>,
+    realm: String,
+    verifier: V,
+}
+
+impl<V: Verifier> Auth<V> {
+    /// Accepts credentials of one scheme.
+    pub fn new<R: Into<String>>(scheme: Scheme, realm: R, verifier: V) -> Auth<V> {
+        Auth {
+            schemes: vec![scheme],
+            realm: realm.into(),
+            verifier: verifier,
+        }
+    }
+
+    /// Accepts credentials of another scheme as well.
+    pub fn scheme(mut self, scheme: Scheme) -> Self {
+        if !self.schemes.contains(&scheme) {
+            self.schemes.push(scheme);
+        }
+        self
+    }
+
+    pub fn realm(&self) -> &str {
+        &self.realm
+    }
+
+    /// Returns the user if the request carries valid credentials of an accepted scheme.
+    pub fn authenticate(&self, req: &Request) -> Option<V::User> {
+        Credentials::from_request(req)
+            .and_then(|credentials| {
+                if self.schemes.contains(&credentials.scheme()) {
+                    self.verifier.verify(&credentials)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Creates a `401 Unauthorized` response with a challenge for each accepted scheme.
+    ///
+    /// If the request carried a bearer token it is reported as invalid.
+    pub fn unauthorized(&self, req: &Request) -> Response {
+        let mut res = Response::new(req.version);
+        res.status = StatusCode::Unauthorized;
+        let invalid_token = authorization(req, "Bearer").is_some();
+        for scheme in &self.schemes {
+            let challenge = match *scheme {
+                Scheme::Basic => format!("Basic realm={}, charset=\"UTF-8\"", quote(&self.realm)),
+                Scheme::Bearer if invalid_token => {
+                    format!("Bearer realm={}, error=\"invalid_token\"", quote(&self.realm))
+                }
+                Scheme::Bearer => format!("Bearer realm={}", quote(&self.realm)),
+            };
+            res.headers_mut().append("WWW-Authenticate", challenge.into_bytes());
+        }
+        res.put_body("");
+        res
+    }
+}
+
+/// Access to the `Auth` in the context.
+pub trait Authenticates {
+    type Verifier: Verifier;
+
+    fn auth(&self) -> &Auth<Self::Verifier>;
+}
+
+/// Middleware rejecting requests without valid credentials.
+///
+/// The inner handler finds the user with `req.extensions().get::<V::User>()`.
+pub struct Authenticate;
+
+impl<C: Authenticates> Middleware<C> for Authenticate {
+    type State = ();
+
+    fn before(req: &mut Request, ctx: &mut C) -> Result<(), Response> {
+        match ctx.auth().authenticate(req) {
+            Some(user) => {
+                req.extensions_mut().insert(user);
+                Ok(())
+            }
+            None => Err(ctx.auth().unauthorized(req)),
+        }
+    }
+
+    fn after(_: (), _: &mut Response, _: &mut C) {}
+}
@@ -0,0 +1,352 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Seek, SeekFrom, Write};
+#[cfg(unix)]
+use std::os::unix::io::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::thread;
+
+/// The size of the chunks a streamed body is split into.
+pub const CHUNK_SIZE: usize = 8192;
+
+/// The number of chunks a streamed body is written ahead of the connection.
+pub const MAX_QUEUED_CHUNKS: usize = 4;
+
+/// Produces a response body while it is written.
+///
+/// Implemented for closures taking the writer. Bodies are `Send` so responses can be produced
+/// on other threads.
+pub trait WriteBody: Send {
+    fn write_body(self: Box<Self>, w: &mut Write) -> io::Result<()>;
+}
+
+impl<F: FnOnce(&mut Write) -> io::Result<()> + Send> WriteBody for F {
+    fn write_body(self: Box<Self>, w: &mut Write) -> io::Result<()> {
+        (*self)(w)
+    }
+}
+
+/// The body of a response.
+pub enum Body {
+    /// A body with a known length sent with a `Content-Length`.
+    Bytes(Vec<u8>),
+    /// A body of unknown length.
+    ///
+    /// It is sent in chunked transfer coding to HTTP/1.1 clients and buffered for older ones.
+    /// A server worker writes it on its own thread as a `BodyStream`.
+    Stream(Box<WriteBody>),
+    /// A part of a file sent with a `Content-Length`.
+    File(FileBody),
+}
+
+impl Body {
+    /// Writes a stream to memory and returns the bytes.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        match self {
+            Body::Bytes(bytes) => Ok(bytes),
+            Body::Stream(stream) => {
+                let mut bytes = Vec::new();
+                try!(stream.write_body(&mut bytes));
+                Ok(bytes)
+            }
+            Body::File(file) => {
+                let mut bytes = Vec::with_capacity(file.len() as usize);
+                try!(file.copy_to(&mut bytes));
+                Ok(bytes)
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Body::Bytes(ref bytes) => f.debug_tuple("Bytes").field(bytes).finish(),
+            Body::Stream(_) => f.write_str("Stream(..)"),
+            Body::File(ref file) => f.debug_tuple("File").field(file).finish(),
+        }
+    }
+}
+
+/// A body read from a file.
+///
+/// On Linux the file can be sent to a socket with `sendfile(2)` without copying it to user
+/// space. Writers other than sockets receive buffered copies of the file contents.
+#[derive(Debug)]
+pub struct FileBody {
+    file: File,
+    offset: u64,
+    len: u64,
+}
+
+impl FileBody {
+    /// Sends the whole file.
+    pub fn new(file: File) -> io::Result<FileBody> {
+        let len = try!(file.metadata()).len();
+        Ok(FileBody::range(file, 0, len))
+    }
+
+    /// Sends `len` bytes of the file starting at `offset`.
+    pub fn range(file: File, offset: u64, len: u64) -> FileBody {
+        FileBody {
+            file: file,
+            offset: offset,
+            len: len,
+        }
+    }
+
+    /// The number of bytes that remain to be sent.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Copies the remaining bytes to a writer using buffered reads.
+    pub fn copy_to(&self, w: &mut Write) -> io::Result<()> {
+        let mut file = &self.file;
+        try!(file.seek(SeekFrom::Start(self.offset)));
+        let copied = try!(io::copy(&mut file.take(self.len), w));
+        if copied != self.len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file was truncated"));
+        }
+        Ok(())
+    }
+
+    /// Sends as much of the file to a socket as it accepts without blocking.
+    ///
+    /// Returns the number of bytes sent, the body keeps track of the remaining bytes so it
+    /// can be resumed once the socket is writable again. Uses `sendfile(2)` on Linux and
+    /// falls back to buffered reads on other systems or if the file does not support it.
+    #[cfg(unix)]
+    pub fn send_to<S: AsRawFd + Write>(&mut self, socket: &mut S) -> io::Result<u64> {
+        let mut sent = 0;
+        while self.len > 0 {
+            let result = match self.sendfile(socket) {
+                Err(ref err) if err.raw_os_error() == Some(::libc::EINVAL) ||
+                                err.raw_os_error() == Some(::libc::ENOSYS) => {
+                    self.send_buffered(socket)
+                }
+                result => result,
+            };
+            match result {
+                Ok(0) => {
+                    return Err(io::Error::new(io::ErrorKind::UnexpectedEof,
+                                              "file was truncated"))
+                }
+                Ok(n) => {
+                    sent += n;
+                    self.offset += n;
+                    self.len -= n;
+                }
+                Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(ref err) if err.kind() == io::ErrorKind::WouldBlock && sent > 0 => break,
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(sent)
+    }
+
+    #[cfg(target_os = "linux")]
+    fn sendfile<S: AsRawFd>(&self, socket: &S) -> io::Result<u64> {
+        use std::cmp;
+        let mut offset = self.offset as ::libc::off_t;
+        // Large files are sent in multiple calls, the count is limited by the kernel anyway.
+        let count = cmp::min(self.len, 0x7fff_f000) as usize;
+        let n = unsafe {
+            ::libc::sendfile(socket.as_raw_fd(), self.file.as_raw_fd(), &mut offset, count)
+        };
+        if n < 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(n as u64)
+        }
+    }
+
+    #[cfg(all(unix, not(target_os = "linux")))]
+    fn sendfile<S: AsRawFd>(&self, _socket: &S) -> io::Result<u64> {
+        Err(io::Error::from_raw_os_error(::libc::ENOSYS))
+    }
+
+    #[cfg(unix)]
+    fn send_buffered<W: Write>(&self, w: &mut W) -> io::Result<u64> {
+        let mut buf = [0; 8192];
+        let mut file = &self.file;
+        try!(file.seek(SeekFrom::Start(self.offset)));
+        let n = try!(file.take(self.len).read(&mut buf));
+        w.write(&buf[..n]).map(|n| n as u64)
+    }
+}
+
+/// Writes data in chunked transfer coding.
+///
+/// Empty writes are skipped as an empty chunk marks the end of the body. Call `finish` to
+/// write the last chunk.
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        ChunkedWriter { inner: inner }
+    }
+
+    /// Writes the last chunk and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        try!(self.inner.write_all(b"0\r\n\r\n"));
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        try!(write!(self.inner, "{:x}\r\n", buf.len()));
+        try!(self.inner.write_all(buf));
+        try!(self.inner.write_all(b"\r\n"));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The wake-up registered with `BodyStream::on_ready`.
+struct Signal {
+    /// A chunk was queued or the body ended while no wake-up was registered.
+    ready: bool,
+    wake: Option<Box<Fn() + Send>>,
+}
+
+/// Queues the chunks of a streamed body for the connection.
+///
+/// Every write is sent as one chunk in chunked transfer coding.
+struct Pipe {
+    sender: SyncSender<Option<Vec<u8>>>,
+    signal: Arc<Mutex<Signal>>,
+}
+
+impl Pipe {
+    fn send(&self, chunk: Option<Vec<u8>>) -> io::Result<()> {
+        try!(self.sender
+                 .send(chunk)
+                 .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "connection closed")));
+        let wake = {
+            let mut signal = self.signal.lock().unwrap();
+            signal.ready = signal.wake.is_none();
+            signal.wake.take()
+        };
+        if let Some(wake) = wake {
+            wake();
+        }
+        Ok(())
+    }
+}
+
+impl Write for Pipe {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !buf.is_empty() {
+            let mut chunk = format!("{:x}\r\n", buf.len()).into_bytes();
+            chunk.extend_from_slice(buf);
+            chunk.extend_from_slice(b"\r\n");
+            try!(self.send(Some(chunk)));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A streamed body written in chunked transfer coding on its own thread.
+///
+/// The thread blocks once `MAX_QUEUED_CHUNKS` chunks wait to be sent, so the body is produced
+/// no faster than the connection takes it with `poll_to`.
+pub struct BodyStream {
+    receiver: Receiver<Option<Vec<u8>>>,
+    signal: Arc<Mutex<Signal>>,
+}
+
+impl BodyStream {
+    /// Starts writing the body.
+    pub fn spawn(stream: Box<WriteBody>) -> io::Result<BodyStream> {
+        let (sender, receiver) = mpsc::sync_channel(MAX_QUEUED_CHUNKS);
+        let signal = Arc::new(Mutex::new(Signal {
+            ready: false,
+            wake: None,
+        }));
+        let pipe = Pipe {
+            sender: sender,
+            signal: signal.clone(),
+        };
+        try!(thread::Builder::new().name("kinglet-body".to_owned()).spawn(move || {
+            let mut chunked = BufWriter::with_capacity(CHUNK_SIZE, pipe);
+            let pipe = stream.write_body(&mut chunked)
+                             .and_then(|()| chunked.into_inner().map_err(|err| err.into()));
+            // Without the end of the body the connection is aborted.
+            if let Ok(pipe) = pipe {
+                let _ = pipe.send(Some(b"0\r\n\r\n".to_vec())).and_then(|()| pipe.send(None));
+            }
+        }));
+        Ok(BodyStream {
+            receiver: receiver,
+            signal: signal,
+        })
+    }
+
+    /// Calls `wake` once more of the body is queued, right away if it already is.
+    ///
+    /// `wake` runs on the thread writing the body.
+    pub fn on_ready<W: Fn() + Send + 'static>(&self, wake: W) {
+        let mut signal = self.signal.lock().unwrap();
+        if signal.ready {
+            signal.ready = false;
+            drop(signal);
+            wake();
+        } else {
+            signal.wake = Some(Box::new(wake));
+        }
+    }
+
+    /// Writes the queued chunks without blocking.
+    ///
+    /// Returns `true` once the whole body was written, fails if the body was aborted.
+    pub fn poll_to(&mut self, w: &mut Write) -> io::Result<bool> {
+        loop {
+            match self.receiver.try_recv() {
+                Ok(Some(chunk)) => try!(w.write_all(&chunk)),
+                Ok(None) => return Ok(true),
+                Err(TryRecvError::Empty) => return Ok(false),
+                Err(TryRecvError::Disconnected) => return Err(aborted()),
+            }
+        }
+    }
+
+    /// Writes the rest of the body, blocking until it is complete.
+    pub fn copy_to(self, w: &mut Write) -> io::Result<()> {
+        loop {
+            match self.receiver.recv() {
+                Ok(Some(chunk)) => try!(w.write_all(&chunk)),
+                Ok(None) => return Ok(()),
+                Err(_) => return Err(aborted()),
+            }
+        }
+    }
+}
+
+fn aborted() -> io::Error {
+    io::Error::new(io::ErrorKind::Other, "streamed body aborted")
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("BodyStream(..)")
+    }
+}
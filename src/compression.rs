@@ -0,0 +1,550 @@
+//! Response compression with gzip and deflate, enabled by the `compression` feature.
+//!
+//! The content coding is negotiated with the `Accept-Encoding` header of the request. Only
+//! responses with a compressible `Content-Type` and bodies of at least `MIN_SIZE` bytes are
+//! compressed, streamed bodies are compressed while they are written.
+//!
+//! Wrap a handler in `Compress` to compress all of its responses.
+//!
+//! Request bodies sent with a `Content-Encoding` are decoded by wrapping a handler in
+//! `Decompress`, which inflates them while they are received, or by `decode_body` once they
+//! are buffered.
+use std::ascii::AsciiExt;
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use flate2;
+use flate2::read::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use flate2::write::{self as decoders, GzEncoder, ZlibEncoder};
+
+use Error::{self, InvalidBody, TooLarge, UnsupportedMediaType};
+use {Body, BodyReader, Handler, Message, Reply, Request, Response, StatusCode};
+use negotiation;
+
+/// Bodies smaller than this are not worth compressing.
+pub const MIN_SIZE: usize = 1024;
+
+/// A content coding.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    /// The zlib format, which is called "deflate" in HTTP.
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+}
+
+/// Selects the content coding the client prefers.
+///
+/// Returns `None` if the client prefers an uncompressed response or sends no
+/// `Accept-Encoding` header.
+pub fn preferred_encoding(req: &Request) -> Option<Encoding> {
+    // Any coding would be acceptable, but clients not asking for one rarely decode it.
+    if !req.contains_header("Accept-Encoding") {
+        return None;
+    }
+    match negotiation::encoding(req, &["gzip", "deflate", "identity"]) {
+        Ok("gzip") => Some(Encoding::Gzip),
+        Ok("deflate") => Some(Encoding::Deflate),
+        _ => None,
+    }
+}
+
+/// Checks if a media type is text-based and benefits from compression.
+pub fn is_compressible(media_type: &[u8]) -> bool {
+    fn ends_with_ignore_case(value: &[u8], suffix: &[u8]) -> bool {
+        value.len() >= suffix.len() &&
+        value[value.len() - suffix.len()..].eq_ignore_ascii_case(suffix)
+    }
+    (media_type.len() > 5 && media_type[..5].eq_ignore_ascii_case(b"text/")) ||
+    ends_with_ignore_case(media_type, b"+json") ||
+    ends_with_ignore_case(media_type, b"+xml") ||
+    [&b"application/json"[..], b"application/javascript", b"application/xml"]
+        .iter()
+        .any(|t| t.eq_ignore_ascii_case(media_type))
+}
+
+/// Compresses the response for the request if the client supports it.
+pub fn compress(req: &Request, res: &mut Response) -> io::Result<()> {
+    compress_with(preferred_encoding(req), res)
+}
+
+/// Compresses the response with a previously negotiated content coding.
+///
+/// `None` means the client does not accept a compressed response. The response is still
+/// marked to vary by `Accept-Encoding` if it could have been compressed.
+///
+/// Only `200 OK` responses are compressed, as the offsets of partial content refer to the
+/// uncompressed representation. A strong `ETag` is weakened, the compressed bytes differ from
+/// the representation it was computed for.
+pub fn compress_with(encoding: Option<Encoding>, res: &mut Response) -> io::Result<()> {
+    if res.status != StatusCode::Ok || res.contains_header("Content-Range") ||
+       res.contains_header("Content-Encoding") {
+        return Ok(());
+    }
+    match res.content_type() {
+        Some(ref media_type) if is_compressible(&media_type.value) => (),
+        _ => return Ok(()),
+    }
+    match res.body() {
+        Some(&Body::Bytes(ref bytes)) if bytes.len() >= MIN_SIZE => (),
+        Some(&Body::Stream(_)) => (),
+        Some(&Body::File(ref file)) if file.len() >= MIN_SIZE as u64 => (),
+        _ => return Ok(()),
+    }
+    let varies = res.get_list_header("Vary")
+                    .map(|mut values| {
+                        values.any(|v| v == b"*" || v.eq_ignore_ascii_case(b"Accept-Encoding"))
+                    })
+                    .unwrap_or(false);
+    if !varies {
+        res.headers_mut().append("Vary", b"Accept-Encoding".to_vec());
+    }
+    let encoding = match encoding {
+        Some(encoding) => encoding,
+        None => return Ok(()),
+    };
+    let body = match res.take_body() {
+        Some(Body::Bytes(bytes)) => Body::Bytes(try!(compress_bytes(encoding, &bytes))),
+        Some(Body::Stream(stream)) => {
+            Body::Stream(Box::new(move |w: &mut Write| {
+                match encoding {
+                    Encoding::Gzip => {
+                        let mut encoder = GzEncoder::new(w, flate2::Compression::Default);
+                        try!(stream.write_body(&mut encoder));
+                        encoder.finish().map(|_| ())
+                    }
+                    Encoding::Deflate => {
+                        let mut encoder = ZlibEncoder::new(w, flate2::Compression::Default);
+                        try!(stream.write_body(&mut encoder));
+                        encoder.finish().map(|_| ())
+                    }
+                }
+            }))
+        }
+        // Compressed files can not be sent directly from the file.
+        Some(body @ Body::File(_)) => {
+            Body::Bytes(try!(compress_bytes(encoding, &try!(body.into_bytes()))))
+        }
+        None => unreachable!(),
+    };
+    res.set_body(body);
+    let weak_etag = match res.get_value_header("ETag") {
+        Some(etag) if !etag.starts_with(b"W/") => {
+            let mut weak = b"W/".to_vec();
+            weak.extend_from_slice(etag);
+            Some(weak)
+        }
+        _ => None,
+    };
+    if let Some(etag) = weak_etag {
+        res.headers_mut().set("ETag", etag);
+    }
+    res.headers_mut().remove("Content-Length");
+    res.headers_mut().set("Content-Encoding", encoding.as_str().as_bytes().to_vec());
+    Ok(())
+}
+
+fn compress_bytes(encoding: Encoding, bytes: &[u8]) -> io::Result<Vec<u8>> {
+    match encoding {
+        Encoding::Gzip => {
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::Default);
+            try!(encoder.write_all(bytes));
+            encoder.finish()
+        }
+        Encoding::Deflate => {
+            let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::Default);
+            try!(encoder.write_all(bytes));
+            encoder.finish()
+        }
+    }
+}
+
+/// A handler that compresses the responses of the inner handler `H`.
+///
+/// Responses that can not be compressed are passed through unchanged.
+pub struct Compress<H>(PhantomData<H>);
+
+impl<C, H: Handler<C>> Handler<C> for Compress<H> {
+    fn request(req: Request, ctx: &mut C) -> Response {
+        let encoding = preferred_encoding(&req);
+        compress_or_fail(encoding, H::request(req, ctx))
+    }
+
+    fn reply(req: Request, ctx: &mut C) -> Reply<C> {
+        let encoding = preferred_encoding(&req);
+        H::reply(req, ctx).map(ctx, move |res, _| compress_or_fail(encoding, res))
+    }
+
+    fn body_reader(req: &Request, ctx: &mut C) -> Option<Box<BodyReader>> {
+        H::body_reader(req, ctx)
+    }
+}
+
+/// Compresses a response, a failure results in `500 Internal Server Error`.
+fn compress_or_fail(encoding: Option<Encoding>, mut res: Response) -> Response {
+    if let Err(_) = compress_with(encoding, &mut res) {
+        res.put_body("");
+        res.status = StatusCode::InternalServerError;
+    }
+    res
+}
+
+/// The content codings `decode_body` supports, as sent in the `Accept-Encoding` header of a
+/// `415 Unsupported Media Type` response.
+pub const SUPPORTED_ENCODINGS: &'static str = "gzip, deflate";
+
+/// The default limit of a decoded request body.
+pub const MAX_DECODED_SIZE: usize = 8_388_608;
+
+/// Lists the content codings of a request in the order they were applied.
+///
+/// Fails with `Error::UnsupportedMediaType` for unknown codings.
+fn codings(req: &Request) -> ::Result<Vec<Encoding>> {
+    let mut codings = Vec::new();
+    if let Some(values) = req.get_list_header("Content-Encoding") {
+        for coding in values {
+            if coding.eq_ignore_ascii_case(b"gzip") || coding.eq_ignore_ascii_case(b"x-gzip") {
+                codings.push(Encoding::Gzip);
+            } else if coding.eq_ignore_ascii_case(b"deflate") {
+                codings.push(Encoding::Deflate);
+            } else if !coding.eq_ignore_ascii_case(b"identity") {
+                return Err(UnsupportedMediaType);
+            }
+        }
+    }
+    Ok(codings)
+}
+
+/// Marks a request body as decoded.
+fn decoded(req: &mut Request, len: usize) {
+    req.headers_mut().remove("Content-Encoding");
+    if req.contains_header("Content-Length") {
+        req.headers_mut().set("Content-Length", len.to_string().into_bytes());
+    }
+}
+
+/// Decodes a request body sent with a `Content-Encoding`.
+///
+/// All listed content codings are removed and the `Content-Encoding` header is dropped. Fails
+/// with `Error::UnsupportedMediaType` for unknown codings, with `Error::TooLarge` if the
+/// decoded body would exceed `max_size` bytes and with `Error::InvalidBody` if the body is
+/// corrupt.
+pub fn decode_body(req: &mut Request, max_size: usize) -> ::Result<()> {
+    // Codings are listed in the order they were applied.
+    for coding in try!(codings(req)).into_iter().rev() {
+        req.body = try!(decode_bytes(coding, &req.body, max_size));
+    }
+    let len = req.body.len();
+    decoded(req, len);
+    Ok(())
+}
+
+fn decode_bytes(encoding: Encoding, bytes: &[u8], max_size: usize) -> ::Result<Vec<u8>> {
+    fn read_limited<R: Read>(reader: R, max_size: usize) -> ::Result<Vec<u8>> {
+        let mut decoded = Vec::new();
+        // Read one byte more than allowed to detect bodies that are too large.
+        try!(reader.take(max_size as u64 + 1)
+                   .read_to_end(&mut decoded)
+                   .map_err(|_| InvalidBody));
+        if decoded.len() > max_size {
+            return Err(TooLarge);
+        }
+        Ok(decoded)
+    }
+    match encoding {
+        Encoding::Gzip => {
+            read_limited(try!(GzDecoder::new(bytes).map_err(|_| InvalidBody)), max_size)
+        }
+        Encoding::Deflate if is_zlib(bytes) => read_limited(ZlibDecoder::new(bytes), max_size),
+        Encoding::Deflate => read_limited(DeflateDecoder::new(bytes), max_size),
+    }
+}
+
+/// Checks for a zlib header, some clients send raw deflate data instead of the zlib format.
+fn is_zlib(bytes: &[u8]) -> bool {
+    bytes.len() >= 2 && bytes[0] & 0x0f == 8 &&
+    (bytes[0] as u16 * 256 + bytes[1] as u16) % 31 == 0
+}
+
+/// A layer of a decoder, writing the decoded data to the next layer.
+trait Layer: Write + Send {
+    /// Decodes the buffered rest of the data.
+    fn finish_layer(&mut self) -> io::Result<()>;
+}
+
+impl Layer for Box<Layer> {
+    fn finish_layer(&mut self) -> io::Result<()> {
+        (**self).finish_layer()
+    }
+}
+
+macro_rules! decoder_layer {
+    ($decoder:ident) => {
+        impl<W: Layer> Layer for decoders::$decoder<W> {
+            fn finish_layer(&mut self) -> io::Result<()> {
+                try!(self.try_finish());
+                self.get_mut().finish_layer()
+            }
+        }
+    }
+}
+
+decoder_layer!(GzDecoder);
+decoder_layer!(ZlibDecoder);
+decoder_layer!(DeflateDecoder);
+
+/// Decodes the "deflate" coding, the format is detected from the first two bytes.
+struct Deflate {
+    start: Vec<u8>,
+    next: Option<Box<Layer>>,
+    decoder: Option<Box<Layer>>,
+}
+
+impl Deflate {
+    fn decoder(&mut self) -> io::Result<&mut Box<Layer>> {
+        if self.decoder.is_none() {
+            let next = self.next.take().expect("the next layer");
+            let mut decoder: Box<Layer> = if is_zlib(&self.start) {
+                Box::new(decoders::ZlibDecoder::new(next))
+            } else {
+                Box::new(decoders::DeflateDecoder::new(next))
+            };
+            try!(decoder.write_all(&self.start));
+            self.decoder = Some(decoder);
+        }
+        Ok(self.decoder.as_mut().unwrap())
+    }
+}
+
+impl Write for Deflate {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.decoder.is_none() && self.start.len() + buf.len() < 2 {
+            self.start.extend_from_slice(buf);
+            return Ok(buf.len());
+        }
+        if self.decoder.is_none() {
+            let len = 2 - self.start.len();
+            self.start.extend_from_slice(&buf[..len]);
+            try!(self.decoder());
+            return Ok(len);
+        }
+        try!(self.decoder()).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Layer for Deflate {
+    fn finish_layer(&mut self) -> io::Result<()> {
+        try!(self.decoder()).finish_layer()
+    }
+}
+
+/// The decoded data waiting to be passed on.
+struct Decoded {
+    data: Vec<u8>,
+    len: usize,
+    max_size: usize,
+    too_large: bool,
+}
+
+/// The last layer of a decoder, collecting the decoded data.
+struct Output(Arc<Mutex<Decoded>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut decoded = self.0.lock().unwrap();
+        decoded.len += buf.len();
+        if decoded.len > decoded.max_size {
+            decoded.too_large = true;
+            return Err(io::Error::new(io::ErrorKind::Other, "decoded body too large"));
+        }
+        decoded.data.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Layer for Output {
+    fn finish_layer(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Answers a request whose body can not be decoded with the status of the error.
+fn error_response(req: &Request, err: Error) -> Response {
+    let mut res = Response::new(req.version);
+    res.status = err.status();
+    if err == UnsupportedMediaType {
+        res.headers_mut().set("Accept-Encoding", SUPPORTED_ENCODINGS.as_bytes().to_vec());
+    }
+    res.put_body("");
+    res
+}
+
+/// Decodes a request body sent with a `Content-Encoding` while it is received.
+///
+/// The decoded body is passed to another reader or stored in `Request::body`. Once it is
+/// complete the `Content-Encoding` header is dropped like by `decode_body`. Bodies that can not
+/// be decoded are answered with the status of the error.
+pub struct Decoder {
+    layers: Box<Layer>,
+    decoded: Arc<Mutex<Decoded>>,
+    inner: Option<Box<BodyReader>>,
+}
+
+impl Decoder {
+    /// Creates a decoder limiting the decoded body to `max_size` bytes.
+    ///
+    /// Fails with `Error::UnsupportedMediaType` for unknown codings.
+    pub fn new(req: &Request,
+               max_size: usize,
+               inner: Option<Box<BodyReader>>)
+               -> ::Result<Decoder> {
+        let decoded = Arc::new(Mutex::new(Decoded {
+            data: Vec::new(),
+            len: 0,
+            max_size: max_size,
+            too_large: false,
+        }));
+        // The first coding was applied first, so its layer comes last.
+        let mut layers: Box<Layer> = Box::new(Output(decoded.clone()));
+        for coding in try!(codings(req)) {
+            layers = match coding {
+                Encoding::Gzip => Box::new(decoders::GzDecoder::new(layers)),
+                Encoding::Deflate => {
+                    Box::new(Deflate {
+                        start: Vec::new(),
+                        next: Some(layers),
+                        decoder: None,
+                    })
+                }
+            };
+        }
+        Ok(Decoder {
+            layers: layers,
+            decoded: decoded,
+            inner: inner,
+        })
+    }
+
+    /// Tells why the body could not be decoded.
+    fn error(&self) -> Error {
+        if self.decoded.lock().unwrap().too_large {
+            TooLarge
+        } else {
+            InvalidBody
+        }
+    }
+
+    /// Passes the decoded data on.
+    fn forward(&mut self, req: &mut Request) -> Result<(), Response> {
+        let data = mem::replace(&mut self.decoded.lock().unwrap().data, Vec::new());
+        match self.inner {
+            Some(ref mut inner) => inner.data(req, &data),
+            None => {
+                req.body.extend_from_slice(&data);
+                Ok(())
+            }
+        }
+    }
+}
+
+impl BodyReader for Decoder {
+    fn data(&mut self, req: &mut Request, data: &[u8]) -> Result<(), Response> {
+        if self.layers.write_all(data).is_err() {
+            return Err(error_response(req, self.error()));
+        }
+        self.forward(req)
+    }
+
+    fn finish(&mut self, req: &mut Request) -> Result<(), Response> {
+        if self.layers.finish_layer().is_err() {
+            return Err(error_response(req, self.error()));
+        }
+        try!(self.forward(req));
+        let len = self.decoded.lock().unwrap().len;
+        decoded(req, len);
+        match self.inner {
+            Some(ref mut inner) => inner.finish(req),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Access to the limit of decoded request bodies in the context.
+pub trait Decoding {
+    /// The maximum size of a decoded request body.
+    fn max_decoded_size(&self) -> usize {
+        MAX_DECODED_SIZE
+    }
+}
+
+/// A handler that decodes compressed request bodies before passing them to `H`.
+///
+/// Bodies are decoded with a `Decoder` while they are received and passed to the reader of
+/// `H`. Bodies that were buffered anyway, for example by a handler calling `request` directly,
+/// are decoded with `decode_body`. Decoded bodies are limited to the size given by the context,
+/// requests that can not be decoded are answered with the status of the error.
+pub struct Decompress<H>(PhantomData<H>);
+
+impl<C: Decoding, H: Handler<C>> Handler<C> for Decompress<H> {
+    fn request(mut req: Request, ctx: &mut C) -> Response {
+        if !req.contains_header("Content-Encoding") {
+            return H::request(req, ctx);
+        }
+        match decode_body(&mut req, ctx.max_decoded_size()) {
+            Ok(()) => H::request(req, ctx),
+            Err(err) => error_response(&req, err),
+        }
+    }
+
+    fn reply(mut req: Request, ctx: &mut C) -> Reply<C> {
+        if !req.contains_header("Content-Encoding") {
+            return H::reply(req, ctx);
+        }
+        match decode_body(&mut req, ctx.max_decoded_size()) {
+            Ok(()) => H::reply(req, ctx),
+            Err(err) => Reply::Ready(error_response(&req, err)),
+        }
+    }
+
+    fn body_reader(req: &Request, ctx: &mut C) -> Option<Box<BodyReader>> {
+        let inner = H::body_reader(req, ctx);
+        if !req.contains_header("Content-Encoding") {
+            return inner;
+        }
+        match Decoder::new(req, ctx.max_decoded_size(), inner) {
+            Ok(decoder) => Some(Box::new(decoder)),
+            Err(err) => Some(Box::new(Rejected(err))),
+        }
+    }
+}
+
+/// Rejects a body with unsupported content codings.
+struct Rejected(Error);
+
+impl BodyReader for Rejected {
+    fn data(&mut self, req: &mut Request, _: &[u8]) -> Result<(), Response> {
+        Err(error_response(req, self.0))
+    }
+
+    fn finish(&mut self, req: &mut Request) -> Result<(), Response> {
+        Err(error_response(req, self.0))
+    }
+}
@@ -0,0 +1,233 @@
+//! Conditional requests as defined in RFC 7232.
+//!
+//! The preconditions of a request are evaluated against the `ETag` and `Last-Modified`
+//! validators of the selected representation. A failed precondition turns the response into
+//! `304 Not Modified` or `412 Precondition Failed`.
+use std::marker::PhantomData;
+use std::time::SystemTime;
+
+use date;
+use {BodyReader, Handler, Message, Method, Reply, Request, Response, StatusCode};
+
+/// An entity tag.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct EntityTag<'a> {
+    pub weak: bool,
+    /// The opaque tag including the double quotes.
+    pub tag: &'a [u8],
+}
+
+impl<'a> EntityTag<'a> {
+    /// Parses a single entity tag like `"xyzzy"` or `W/"xyzzy"`.
+    pub fn parse(value: &'a [u8]) -> Option<EntityTag<'a>> {
+        match parse_tags(value) {
+            Some((ref tags, false)) if tags.len() == 1 => Some(tags[0]),
+            _ => None,
+        }
+    }
+
+    /// Both tags are strong and identical.
+    pub fn strong_eq(&self, other: &EntityTag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    /// Both tags are identical if the weakness indicator is ignored.
+    pub fn weak_eq(&self, other: &EntityTag) -> bool {
+        self.tag == other.tag
+    }
+}
+
+/// Parses a comma separated list of entity tags.
+///
+/// The flag is set if the list contains `*`. Returns `None` if the list is invalid.
+fn parse_tags(value: &[u8]) -> Option<(Vec<EntityTag>, bool)> {
+    let mut tags = Vec::new();
+    let mut any = false;
+    let mut i = 0;
+    loop {
+        while i < value.len() && (value[i] == b' ' || value[i] == b'\t' || value[i] == b',') {
+            i += 1;
+        }
+        if i == value.len() {
+            return Some((tags, any));
+        }
+        if value[i] == b'*' {
+            any = true;
+            i += 1;
+            continue;
+        }
+        let weak = value[i..].starts_with(b"W/");
+        if weak {
+            i += 2;
+        }
+        if i == value.len() || value[i] != b'"' {
+            return None;
+        }
+        let end = match value[i + 1..].iter().position(|b| *b == b'"') {
+            Some(end) => i + end + 2,
+            None => return None,
+        };
+        tags.push(EntityTag {
+            weak: weak,
+            tag: &value[i..end],
+        });
+        i = end;
+    }
+}
+
+/// Checks if any value of a header field matches the entity tag.
+///
+/// `*` matches any current representation. The weak comparison ignores the weakness
+/// indicator.
+fn matches(values: &[Vec<u8>], etag: Option<&EntityTag>, weak: bool) -> bool {
+    values.iter().any(|value| {
+        match (parse_tags(value), etag) {
+            (Some((_, true)), _) => true,
+            (Some((ref tags, false)), Some(etag)) => {
+                tags.iter().any(|tag| if weak { tag.weak_eq(etag) } else { tag.strong_eq(etag) })
+            }
+            _ => false,
+        }
+    })
+}
+
+/// The preconditions of a request.
+///
+/// They are copied from the request so they can be evaluated after the request has been
+/// passed to a handler.
+#[derive(Clone, Debug)]
+pub struct Preconditions {
+    method: Method,
+    if_match: Option<Vec<Vec<u8>>>,
+    if_none_match: Option<Vec<Vec<u8>>>,
+    if_modified_since: Option<SystemTime>,
+    if_unmodified_since: Option<SystemTime>,
+}
+
+impl Preconditions {
+    /// Collects the preconditions of a request. Invalid dates are ignored.
+    pub fn new(req: &Request) -> Preconditions {
+        Preconditions {
+            method: req.method.clone(),
+            if_match: req.get_header("If-Match").cloned(),
+            if_none_match: req.get_header("If-None-Match").cloned(),
+            if_modified_since: req.get_value_header("If-Modified-Since").and_then(date::parse),
+            if_unmodified_since: req.get_value_header("If-Unmodified-Since")
+                                    .and_then(date::parse),
+        }
+    }
+
+    /// Evaluates the preconditions in the order given by RFC 7232, section 6.
+    ///
+    /// Returns the status code of the response if a precondition fails. `If-Range` is handled
+    /// by the `range` module.
+    pub fn evaluate(&self,
+                    etag: Option<&[u8]>,
+                    last_modified: Option<SystemTime>)
+                    -> Option<StatusCode> {
+        let etag = etag.and_then(EntityTag::parse);
+        let etag = etag.as_ref();
+        if let Some(ref if_match) = self.if_match {
+            if !matches(if_match, etag, false) {
+                return Some(StatusCode::PreconditionFailed);
+            }
+        } else if let Some(since) = self.if_unmodified_since {
+            match last_modified {
+                Some(last_modified) if last_modified <= since => (),
+                _ => return Some(StatusCode::PreconditionFailed),
+            }
+        }
+        let safe = self.method == Method::Get || self.method == Method::Head;
+        if let Some(ref if_none_match) = self.if_none_match {
+            if matches(if_none_match, etag, true) {
+                return Some(if safe {
+                    StatusCode::NotModified
+                } else {
+                    StatusCode::PreconditionFailed
+                });
+            }
+        } else if let (true, Some(since)) = (safe, self.if_modified_since) {
+            match last_modified {
+                Some(last_modified) if last_modified <= since => {
+                    return Some(StatusCode::NotModified)
+                }
+                _ => (),
+            }
+        }
+        None
+    }
+
+    /// Evaluates the preconditions with the `ETag` and `Last-Modified` headers of a
+    /// successful response.
+    ///
+    /// If a precondition fails the status is changed, the body is removed and `true` is
+    /// returned. A `304 Not Modified` response keeps the validators but drops the content
+    /// headers.
+    pub fn apply(&self, res: &mut Response) -> bool {
+        if !res.status.is_success() {
+            return false;
+        }
+        let status = {
+            let last_modified = res.get_value_header("Last-Modified").and_then(date::parse);
+            self.evaluate(res.get_value_header("ETag"), last_modified)
+        };
+        match status {
+            Some(StatusCode::NotModified) => {
+                res.status = StatusCode::NotModified;
+                res.take_body();
+                for name in &["Content-Type", "Content-Length", "Content-Language",
+                              "Content-Range"] {
+                    res.headers_mut().remove(name);
+                }
+                true
+            }
+            Some(status) => {
+                res.status = status;
+                res.headers_mut().remove("Content-Length");
+                res.put_body("");
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Evaluates the preconditions of a request against the validators of a representation.
+pub fn evaluate(req: &Request,
+                etag: Option<&[u8]>,
+                last_modified: Option<SystemTime>)
+                -> Option<StatusCode> {
+    Preconditions::new(req).evaluate(etag, last_modified)
+}
+
+/// Evaluates the preconditions of a request against the headers of the response.
+pub fn apply(req: &Request, res: &mut Response) -> bool {
+    Preconditions::new(req).apply(res)
+}
+
+/// A handler that evaluates the preconditions of requests against the responses of `H`.
+///
+/// The whole response is still produced by `H`, a handler that can check the validators
+/// without creating the body should call `evaluate` itself.
+pub struct Conditional<H>(PhantomData<H>);
+
+impl<C, H: Handler<C>> Handler<C> for Conditional<H> {
+    fn request(req: Request, ctx: &mut C) -> Response {
+        let preconditions = Preconditions::new(&req);
+        let mut res = H::request(req, ctx);
+        preconditions.apply(&mut res);
+        res
+    }
+
+    fn reply(req: Request, ctx: &mut C) -> Reply<C> {
+        let preconditions = Preconditions::new(&req);
+        H::reply(req, ctx).map(ctx, move |mut res, _| {
+            preconditions.apply(&mut res);
+            res
+        })
+    }
+
+    fn body_reader(req: &Request, ctx: &mut C) -> Option<Box<BodyReader>> {
+        H::body_reader(req, ctx)
+    }
+}
@@ -0,0 +1,372 @@
+//! Cookies as defined in RFC 6265.
+//!
+//! The `Cookie` header of a request is parsed into a `CookieJar`, cookies are set with a
+//! `SetCookie` passed to `Response::set_cookie`.
+//!
+//! With the `secure-cookies` feature cookie values can be signed or encrypted with a `Key`.
+//! Signed cookies can be read but not modified by the client, encrypted cookies can be
+//! neither read nor modified.
+use std::fmt;
+use std::slice;
+use std::str;
+
+use time::Tm;
+
+use Error::InvalidHeaderValue;
+use is_token;
+
+/// The cookies sent by a client.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CookieJar {
+    cookies: Vec<(String, String)>,
+}
+
+impl CookieJar {
+    pub fn new() -> Self {
+        CookieJar { cookies: Vec::new() }
+    }
+
+    /// Parses the values of `Cookie` header fields.
+    ///
+    /// Malformed pairs are skipped.
+    pub fn from_header(values: &[Vec<u8>]) -> Self {
+        let mut jar = CookieJar::new();
+        for value in values {
+            for pair in value.split(|b| *b == b';') {
+                let pair = match str::from_utf8(pair) {
+                    Ok(pair) => pair.trim(),
+                    Err(_) => continue,
+                };
+                if let Some(i) = pair.find('=') {
+                    let name = pair[..i].trim();
+                    let mut value = pair[i + 1..].trim();
+                    if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                        value = &value[1..value.len() - 1];
+                    }
+                    if is_token(name.as_bytes()) {
+                        jar.cookies.push((name.to_owned(), value.to_owned()));
+                    }
+                }
+            }
+        }
+        jar
+    }
+
+    /// Returns the value of the first cookie with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.cookies.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| &v[..])
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cookies.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cookies.is_empty()
+    }
+
+    /// Iterates over name value pairs in the order the client sent them.
+    pub fn iter(&self) -> slice::Iter<(String, String)> {
+        self.cookies.iter()
+    }
+}
+
+/// The `SameSite` attribute of a cookie.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SameSite {
+    Strict,
+    Lax,
+    None,
+}
+
+impl fmt::Display for SameSite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        })
+    }
+}
+
+/// A builder for a `Set-Cookie` header field.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetCookie {
+    name: String,
+    value: String,
+    expires: Option<Tm>,
+    max_age: Option<i64>,
+    domain: Option<String>,
+    path: Option<String>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl SetCookie {
+    pub fn new<N: Into<String>, V: Into<String>>(name: N, value: V) -> Self {
+        SetCookie {
+            name: name.into(),
+            value: value.into(),
+            expires: None,
+            max_age: None,
+            domain: None,
+            path: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Creates a cookie that removes the cookie with the given name from the client.
+    pub fn removal<N: Into<String>>(name: N) -> Self {
+        SetCookie::new(name, "").max_age(0).expires(::time::at_utc(::time::Timespec::new(0, 0)))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    pub fn expires(mut self, expires: Tm) -> Self {
+        self.expires = Some(expires);
+        self
+    }
+
+    /// Sets the lifetime of the cookie in seconds.
+    pub fn max_age(mut self, seconds: i64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn domain<D: Into<String>>(mut self, domain: D) -> Self {
+        self.domain = Some(domain.into());
+        self
+    }
+
+    pub fn path<P: Into<String>>(mut self, path: P) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn secure(mut self, secure: bool) -> Self {
+        self.secure = secure;
+        self
+    }
+
+    pub fn http_only(mut self, http_only: bool) -> Self {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Self {
+        self.same_site = Some(same_site);
+        self
+    }
+
+    /// Checks that the name is a token and the value and attributes do not contain characters
+    /// that would end the cookie or the header field.
+    pub fn validate(&self) -> ::Result<()> {
+        fn is_cookie_value(value: &str) -> bool {
+            value.bytes().all(|b| {
+                b == 0x21 || (b >= 0x23 && b <= 0x2b) || (b >= 0x2d && b <= 0x3a) ||
+                (b >= 0x3c && b <= 0x5b) || (b >= 0x5d && b <= 0x7e)
+            })
+        }
+        fn is_attribute_value(value: &str) -> bool {
+            value.bytes().all(|b| b >= 0x20 && b < 0x7f && b != b';')
+        }
+        if !is_token(self.name.as_bytes()) || !is_cookie_value(&self.value) {
+            return Err(InvalidHeaderValue);
+        }
+        for attribute in self.domain.iter().chain(self.path.iter()) {
+            if !is_attribute_value(attribute) {
+                return Err(InvalidHeaderValue);
+            }
+        }
+        Ok(())
+    }
+
+    /// Signs the value so the client can not modify it.
+    #[cfg(feature = "secure-cookies")]
+    pub fn signed(mut self, key: &Key) -> Self {
+        self.value = key.sign(&self.name, &self.value);
+        self
+    }
+
+    /// Encrypts the value so the client can neither read nor modify it.
+    #[cfg(feature = "secure-cookies")]
+    pub fn encrypted(mut self, key: &Key) -> Self {
+        self.value = key.encrypt(&self.name, &self.value);
+        self
+    }
+}
+
+impl fmt::Display for SetCookie {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{}={}", self.name, self.value));
+        if let Some(ref expires) = self.expires {
+            try!(write!(f, "; Expires={}", expires.to_utc().rfc822()));
+        }
+        if let Some(max_age) = self.max_age {
+            try!(write!(f, "; Max-Age={}", max_age));
+        }
+        if let Some(ref domain) = self.domain {
+            try!(write!(f, "; Domain={}", domain));
+        }
+        if let Some(ref path) = self.path {
+            try!(write!(f, "; Path={}", path));
+        }
+        if self.secure {
+            try!(f.write_str("; Secure"));
+        }
+        if self.http_only {
+            try!(f.write_str("; HttpOnly"));
+        }
+        if let Some(same_site) = self.same_site {
+            try!(write!(f, "; SameSite={}", same_site));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "secure-cookies")]
+pub use self::secure::Key;
+
+#[cfg(feature = "secure-cookies")]
+mod secure {
+    use crypto::aead::{AeadDecryptor, AeadEncryptor};
+    use crypto::aes::KeySize;
+    use crypto::aes_gcm::AesGcm;
+    use crypto::hmac::Hmac;
+    use crypto::mac::{Mac, MacResult};
+    use crypto::sha2::Sha256;
+    use rand::{OsRng, Rng};
+    use rustc_serialize::base64::{FromBase64, ToBase64, URL_SAFE};
+
+    use super::CookieJar;
+
+    const KEY_LEN: usize = 32;
+    const NONCE_LEN: usize = 12;
+    const TAG_LEN: usize = 16;
+    /// Length of a base64 encoded SHA-256 HMAC without padding.
+    const SIGNATURE_LEN: usize = 43;
+
+    /// Keys to sign and encrypt cookies.
+    #[derive(Clone)]
+    pub struct Key {
+        signing: [u8; KEY_LEN],
+        encryption: [u8; KEY_LEN],
+    }
+
+    impl Key {
+        /// Creates a key from at least 64 bytes of secret data.
+        ///
+        /// The first 32 bytes are used for signing, the next 32 for encryption.
+        ///
+        /// # Panics
+        /// If the master key is shorter than 64 bytes.
+        pub fn from_master(master: &[u8]) -> Key {
+            assert!(master.len() >= 2 * KEY_LEN, "master key too short");
+            let mut key = Key {
+                signing: [0; KEY_LEN],
+                encryption: [0; KEY_LEN],
+            };
+            key.signing.copy_from_slice(&master[..KEY_LEN]);
+            key.encryption.copy_from_slice(&master[KEY_LEN..2 * KEY_LEN]);
+            key
+        }
+
+        /// Creates a random key.
+        pub fn generate() -> Key {
+            let mut master = [0; 2 * KEY_LEN];
+            OsRng::new().expect("operating system random number generator").fill_bytes(&mut master);
+            Key::from_master(&master)
+        }
+
+        fn mac(&self, name: &str, value: &str) -> MacResult {
+            let mut hmac = Hmac::new(Sha256::new(), &self.signing);
+            hmac.input(name.as_bytes());
+            hmac.input(b"=");
+            hmac.input(value.as_bytes());
+            hmac.result()
+        }
+
+        pub fn sign(&self, name: &str, value: &str) -> String {
+            let mut signed = self.mac(name, value).code().to_base64(URL_SAFE);
+            signed.push_str(value);
+            signed
+        }
+
+        pub fn verify(&self, name: &str, signed: &str) -> Option<String> {
+            if signed.len() < SIGNATURE_LEN || !signed.is_char_boundary(SIGNATURE_LEN) {
+                return None;
+            }
+            let (signature, value) = signed.split_at(SIGNATURE_LEN);
+            let signature = match signature.from_base64() {
+                Ok(signature) => signature,
+                Err(_) => return None,
+            };
+            if self.mac(name, value) == MacResult::new(&signature) {
+                Some(value.to_owned())
+            } else {
+                None
+            }
+        }
+
+        pub fn encrypt(&self, name: &str, value: &str) -> String {
+            let mut sealed = vec![0; NONCE_LEN + value.len() + TAG_LEN];
+            {
+                let (nonce, rest) = sealed.split_at_mut(NONCE_LEN);
+                let (ciphertext, tag) = rest.split_at_mut(value.len());
+                OsRng::new().expect("operating system random number generator").fill_bytes(nonce);
+                let mut cipher = AesGcm::new(KeySize::KeySize256,
+                                             &self.encryption,
+                                             nonce,
+                                             name.as_bytes());
+                cipher.encrypt(value.as_bytes(), ciphertext, tag);
+            }
+            sealed.to_base64(URL_SAFE)
+        }
+
+        pub fn decrypt(&self, name: &str, encrypted: &str) -> Option<String> {
+            let sealed = match encrypted.from_base64() {
+                Ok(sealed) => sealed,
+                Err(_) => return None,
+            };
+            if sealed.len() < NONCE_LEN + TAG_LEN {
+                return None;
+            }
+            let (nonce, rest) = sealed.split_at(NONCE_LEN);
+            let (ciphertext, tag) = rest.split_at(rest.len() - TAG_LEN);
+            let mut value = vec![0; ciphertext.len()];
+            let mut cipher = AesGcm::new(KeySize::KeySize256,
+                                         &self.encryption,
+                                         nonce,
+                                         name.as_bytes());
+            if !cipher.decrypt(ciphertext, &mut value, tag) {
+                return None;
+            }
+            String::from_utf8(value).ok()
+        }
+    }
+
+    impl CookieJar {
+        /// Returns the value of a signed cookie if the signature is valid.
+        pub fn get_signed(&self, name: &str, key: &Key) -> Option<String> {
+            self.get(name).and_then(|signed| key.verify(name, signed))
+        }
+
+        /// Returns the decrypted value of an encrypted cookie if it was not modified.
+        pub fn get_encrypted(&self, name: &str, key: &Key) -> Option<String> {
+            self.get(name).and_then(|encrypted| key.decrypt(name, encrypted))
+        }
+    }
+}
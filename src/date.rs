@@ -0,0 +1,50 @@
+//! HTTP dates as defined in RFC 7231, section 7.1.1.1.
+use std::str;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use time;
+
+/// Formats a point in time as IMF-fixdate, for example `Sun, 06 Nov 1994 08:49:37 GMT`.
+///
+/// Times before the UNIX epoch are formatted as the epoch.
+pub fn format(system_time: SystemTime) -> String {
+    let seconds = match system_time.duration_since(UNIX_EPOCH) {
+        Ok(duration) => duration.as_secs() as i64,
+        Err(_) => 0,
+    };
+    time::at_utc(time::Timespec::new(seconds, 0)).rfc822().to_string()
+}
+
+/// Parses an HTTP date in IMF-fixdate, RFC 850 or asctime format.
+///
+/// Two-digit years in the RFC 850 format before 70 are taken to be in the 21st century.
+pub fn parse(value: &[u8]) -> Option<SystemTime> {
+    let value = match str::from_utf8(value) {
+        Ok(value) => value.trim(),
+        Err(_) => return None,
+    };
+    let tm = match time::strptime(value, "%a, %d %b %Y %H:%M:%S GMT") {
+        Ok(tm) => tm,
+        Err(_) => {
+            match time::strptime(value, "%A, %d-%b-%y %H:%M:%S GMT") {
+                Ok(mut tm) => {
+                    if tm.tm_year < 70 {
+                        tm.tm_year += 100;
+                    }
+                    tm
+                }
+                Err(_) => {
+                    match time::strptime(value, "%a %b %e %H:%M:%S %Y") {
+                        Ok(tm) => tm,
+                        Err(_) => return None,
+                    }
+                }
+            }
+        }
+    };
+    let seconds = tm.to_timespec().sec;
+    if seconds < 0 {
+        return None;
+    }
+    Some(UNIX_EPOCH + Duration::from_secs(seconds as u64))
+}
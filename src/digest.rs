@@ -0,0 +1,405 @@
+//! HTTP Digest access authentication as described in RFC 7616.
+//!
+//! `DigestAuth` issues nonces in `WWW-Authenticate` challenges and checks the `Authorization`
+//! header of requests against passwords looked up with `Passwords`. Only `qop=auth` is
+//! supported, with the `MD5` and `SHA-256` algorithms. Nonces expire after a lifetime, a
+//! request with an expired nonce is answered with a challenge marked `stale=true` so the
+//! client retries without asking the user again. The nonce count of each nonce must increase
+//! with every request, replayed requests are rejected.
+//!
+//! Nonces carry their expiry time and an HMAC over it with a random key, so issuing a
+//! challenge stores nothing. Only nonces that authenticated a request are remembered with their
+//! nonce count, at most `MAX_NONCES` at a time. While that many unexpired nonces are in use,
+//! requests with further nonces are answered with a stale challenge.
+//!
+//! Clones share the key and the used nonces, so one `DigestAuth` can protect all workers of a
+//! server.
+use std::ascii::AsciiExt;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crypto::digest::Digest;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::md5::Md5;
+use crypto::sha2::Sha256;
+use rand::{OsRng, Rng};
+
+use auth::{authorization, quote};
+use middleware::Middleware;
+use {Request, Response, StatusCode};
+
+/// The default lifetime of a nonce in seconds.
+pub const NONCE_LIFETIME_SECS: u64 = 300;
+
+/// The default number of used nonces that are remembered.
+pub const MAX_NONCES: usize = 4096;
+
+/// The length of a nonce: the expiry time, random data and the HMAC, all hex encoded.
+const NONCE_LEN: usize = 16 + 32 + 64;
+
+/// A hash algorithm.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+impl Algorithm {
+    pub fn name(&self) -> &'static str {
+        match *self {
+            Algorithm::Md5 => "MD5",
+            Algorithm::Sha256 => "SHA-256",
+        }
+    }
+
+    /// Hashes the data and returns the lowercase hex digest.
+    pub fn hash(&self, data: &str) -> String {
+        let mut digest: Box<Digest> = match *self {
+            Algorithm::Md5 => Box::new(Md5::new()),
+            Algorithm::Sha256 => Box::new(Sha256::new()),
+        };
+        digest.input_str(data);
+        digest.result_str()
+    }
+}
+
+/// Looks up the password of a user.
+pub trait Passwords {
+    fn password(&self, username: &str) -> Option<String>;
+}
+
+impl<F: Fn(&str) -> Option<String>> Passwords for F {
+    fn password(&self, username: &str) -> Option<String> {
+        self(username)
+    }
+}
+
+/// The name of the authenticated user, stored as an extension of the request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DigestUser(pub String);
+
+/// Why a request was not authenticated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Failure {
+    /// The request has no Digest credentials or they are malformed.
+    Missing,
+    /// The nonce was not issued by this server or has expired.
+    Stale,
+    /// The nonce count was already used.
+    Replayed,
+    /// The user is unknown or the response does not match.
+    Invalid,
+}
+
+/// A successful authentication.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Authorized {
+    pub username: String,
+    /// The value of the `Authentication-Info` header proving that the server knows the
+    /// password as well.
+    pub info: String,
+}
+
+/// A nonce that authenticated a request.
+#[derive(Debug)]
+struct Nonce {
+    /// Milliseconds since the Unix epoch.
+    expires: u64,
+    count: u32,
+}
+
+/// The realm, algorithms and nonces of a protected resource.
+#[derive(Clone)]
+pub struct DigestAuth<P> {
+    realm: String,
+    algorithms: Vec<Algorithm>,
+    lifetime: Duration,
+    opaque: String,
+    /// The HMAC key of the nonces.
+    key: String,
+    max_nonces: usize,
+    passwords: P,
+    nonces: Arc<Mutex<HashMap<String, Nonce>>>,
+}
+
+impl<P: Passwords> DigestAuth<P> {
+    /// Offers `SHA-256` and `MD5`, in this order of preference.
+    pub fn new<R: Into<String>>(realm: R, passwords: P) -> DigestAuth<P> {
+        DigestAuth {
+            realm: realm.into(),
+            algorithms: vec![Algorithm::Sha256, Algorithm::Md5],
+            lifetime: Duration::from_secs(NONCE_LIFETIME_SECS),
+            opaque: random_hex(),
+            key: random_hex(),
+            max_nonces: MAX_NONCES,
+            passwords: passwords,
+            nonces: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Sets the offered algorithms in the order of preference.
+    ///
+    /// # Panics
+    ///
+    /// If no algorithm is given.
+    pub fn algorithms(mut self, algorithms: &[Algorithm]) -> Self {
+        assert!(!algorithms.is_empty(), "digest authentication needs an algorithm");
+        self.algorithms = algorithms.to_vec();
+        self
+    }
+
+    /// Sets how long a nonce can be used.
+    pub fn nonce_lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Sets how many used nonces are remembered.
+    pub fn max_nonces(mut self, max_nonces: usize) -> Self {
+        self.max_nonces = max_nonces;
+        self
+    }
+
+    pub fn realm(&self) -> &str {
+        &self.realm
+    }
+
+    /// Issues a nonce and returns a challenge for each algorithm.
+    ///
+    /// All challenges share the nonce, a client answers only one of them.
+    pub fn challenges(&self, stale: bool) -> Vec<String> {
+        let lifetime = self.lifetime.as_secs() * 1000 +
+                       self.lifetime.subsec_nanos() as u64 / 1_000_000;
+        let data = format!("{:016x}{}", now_ms() + lifetime, random_hex());
+        let nonce = format!("{}{}", data, self.sign(&data));
+        self.algorithms
+            .iter()
+            .map(|algorithm| {
+                let mut challenge = format!("Digest realm={}, qop=\"auth\", algorithm={}, \
+                                             nonce={}, opaque={}",
+                                            quote(&self.realm),
+                                            algorithm.name(),
+                                            quote(&nonce),
+                                            quote(&self.opaque));
+                if stale {
+                    challenge.push_str(", stale=true");
+                }
+                challenge
+            })
+            .collect()
+    }
+
+    /// Checks the `Authorization` header of a request.
+    pub fn verify(&self, req: &Request) -> Result<Authorized, Failure> {
+        let params = match authorization(req, "Digest").and_then(parse_params) {
+            Some(params) => params,
+            None => return Err(Failure::Missing),
+        };
+        let param = |name: &str| {
+            params.iter().find(|&&(ref n, _)| n.eq_ignore_ascii_case(name)).map(|p| &p.1[..])
+        };
+        let (username, realm, nonce, uri, response, cnonce, nc) =
+            match (param("username"),
+                   param("realm"),
+                   param("nonce"),
+                   param("uri"),
+                   param("response"),
+                   param("cnonce"),
+                   param("nc")) {
+                (Some(u), Some(r), Some(n), Some(uri), Some(res), Some(c), Some(nc)) => {
+                    (u, r, n, uri, res, c, nc)
+                }
+                _ => return Err(Failure::Missing),
+            };
+        // Without an algorithm parameter MD5 is used.
+        let name = param("algorithm").unwrap_or("MD5");
+        let algorithm = match self.algorithms.iter().find(|a| a.name().eq_ignore_ascii_case(name)) {
+            Some(&algorithm) => algorithm,
+            None => return Err(Failure::Missing),
+        };
+        let count = match u32::from_str_radix(nc, 16) {
+            Ok(count) if nc.len() == 8 => count,
+            _ => return Err(Failure::Missing),
+        };
+        if param("qop") != Some("auth") || realm != self.realm || uri != req.target() ||
+           param("opaque").map_or(false, |opaque| opaque != self.opaque) ||
+           param("userhash").map_or(false, |userhash| userhash != "false") {
+            return Err(Failure::Missing);
+        }
+        let ha1 = match self.passwords.password(username) {
+            Some(password) => algorithm.hash(&format!("{}:{}:{}", username, realm, password)),
+            None => return Err(Failure::Invalid),
+        };
+        let digest = |method: &str| {
+            let ha2 = algorithm.hash(&format!("{}:{}", method, uri));
+            algorithm.hash(&format!("{}:{}:{}:{}:auth:{}", ha1, nonce, nc, cnonce, ha2))
+        };
+        if !constant_time_eq(digest(req.method.as_ref()).as_bytes(), response.as_bytes()) {
+            return Err(Failure::Invalid);
+        }
+        // The nonce is checked last, so guessing responses does not use up nonce counts.
+        let expires = match self.expires(nonce) {
+            Some(expires) => expires,
+            None => return Err(Failure::Stale),
+        };
+        let now = now_ms();
+        if expires <= now {
+            return Err(Failure::Stale);
+        }
+        {
+            let mut nonces = self.nonces.lock().unwrap();
+            if !nonces.contains_key(nonce) && nonces.len() >= self.max_nonces {
+                nonces.retain(|_, nonce| nonce.expires > now);
+                if nonces.len() >= self.max_nonces {
+                    return Err(Failure::Stale);
+                }
+            }
+            let entry = nonces.entry(nonce.to_owned()).or_insert(Nonce {
+                expires: expires,
+                count: 0,
+            });
+            if count <= entry.count {
+                return Err(Failure::Replayed);
+            }
+            entry.count = count;
+        }
+        Ok(Authorized {
+            username: username.to_owned(),
+            info: format!("qop=auth, rspauth={}, cnonce={}, nc={}",
+                          quote(&digest("")),
+                          quote(cnonce),
+                          nc),
+        })
+    }
+
+    /// Returns the expiry time of a nonce issued by this `DigestAuth`.
+    fn expires(&self, nonce: &str) -> Option<u64> {
+        if nonce.len() != NONCE_LEN || !nonce.is_char_boundary(NONCE_LEN - 64) {
+            return None;
+        }
+        let (data, tag) = nonce.split_at(NONCE_LEN - 64);
+        if !constant_time_eq(self.sign(data).as_bytes(), tag.as_bytes()) {
+            return None;
+        }
+        u64::from_str_radix(&data[..16], 16).ok()
+    }
+
+    fn sign(&self, data: &str) -> String {
+        let mut hmac = Hmac::new(Sha256::new(), self.key.as_bytes());
+        hmac.input(data.as_bytes());
+        hmac.result().code().iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    /// Creates a `401 Unauthorized` response with challenges.
+    pub fn unauthorized(&self, req: &Request, failure: Failure) -> Response {
+        let mut res = Response::new(req.version);
+        res.status = StatusCode::Unauthorized;
+        for challenge in self.challenges(failure == Failure::Stale) {
+            res.headers_mut().append("WWW-Authenticate", challenge.into_bytes());
+        }
+        res.put_body("");
+        res
+    }
+}
+
+fn now_ms() -> u64 {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    now.as_secs() * 1000 + now.subsec_nanos() as u64 / 1_000_000
+}
+
+fn random_hex() -> String {
+    let mut bytes = [0; 16];
+    OsRng::new().expect("operating system random number generator").fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// Parses a comma separated list of `name=token` and `name="quoted string"` parameters.
+fn parse_params(value: &[u8]) -> Option<Vec<(String, String)>> {
+    let value = match ::std::str::from_utf8(value) {
+        Ok(value) => value,
+        Err(_) => return None,
+    };
+    let mut params = Vec::new();
+    let mut rest = value.trim_left();
+    while !rest.is_empty() {
+        let eq = match rest.find('=') {
+            Some(eq) => eq,
+            None => return None,
+        };
+        let name = rest[..eq].trim();
+        rest = rest[eq + 1..].trim_left();
+        let mut param = String::new();
+        if rest.starts_with('"') {
+            let mut chars = rest.char_indices().skip(1);
+            let mut end = None;
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '"' => {
+                        end = Some(i);
+                        break;
+                    }
+                    '\\' => {
+                        match chars.next() {
+                            Some((_, c)) => param.push(c),
+                            None => return None,
+                        }
+                    }
+                    c => param.push(c),
+                }
+            }
+            match end {
+                Some(end) => rest = &rest[end + 1..],
+                None => return None,
+            }
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            param.push_str(rest[..end].trim_right());
+            rest = &rest[end..];
+        }
+        params.push((name.to_owned(), param));
+        rest = rest.trim_left();
+        if rest.starts_with(',') {
+            rest = rest[1..].trim_left();
+        } else if !rest.is_empty() {
+            return None;
+        }
+    }
+    Some(params)
+}
+
+/// Access to the `DigestAuth` in the context.
+pub trait DigestAuthenticates {
+    type Passwords: Passwords;
+
+    fn digest_auth(&self) -> &DigestAuth<Self::Passwords>;
+}
+
+/// Middleware rejecting requests without valid Digest credentials.
+///
+/// The inner handler finds the user as a `DigestUser` extension. Responses carry an
+/// `Authentication-Info` header.
+pub struct AuthenticateDigest;
+
+impl<C: DigestAuthenticates> Middleware<C> for AuthenticateDigest {
+    type State = String;
+
+    fn before(req: &mut Request, ctx: &mut C) -> Result<String, Response> {
+        match ctx.digest_auth().verify(req) {
+            Ok(authorized) => {
+                req.extensions_mut().insert(DigestUser(authorized.username));
+                Ok(authorized.info)
+            }
+            Err(failure) => Err(ctx.digest_auth().unauthorized(req, failure)),
+        }
+    }
+
+    fn after(info: String, res: &mut Response, _: &mut C) {
+        res.headers_mut().set("Authentication-Info", info.into_bytes());
+    }
+}
@@ -1,9 +1,12 @@
 use std::convert::From;
+use std::io;
 use std::str::Utf8Error;
 use std::num::ParseIntError;
 
 use url::ParseError;
 
+use StatusCode;
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Error {
     InvalidMethod,
@@ -12,12 +15,38 @@ pub enum Error {
     ForbiddenHeader,
     MissingHeader,
     InvalidHeader,
+    InvalidHeaderName,
+    InvalidHeaderValue,
+    InvalidBody,
+    NotAcceptable,
+    UnsupportedMediaType,
+    TooLarge,
+    IoError(io::ErrorKind),
     UrlError(ParseError),
     Utf8Error(Utf8Error),
 }
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+impl Error {
+    /// Returns the status code of a response reporting this error to the client.
+    pub fn status(&self) -> StatusCode {
+        match *self {
+            Error::NotAcceptable => StatusCode::NotAcceptable,
+            Error::UnsupportedMediaType => StatusCode::UnsupportedMediaType,
+            Error::TooLarge => StatusCode::PayloadTooLarge,
+            Error::IoError(_) => StatusCode::InternalServerError,
+            _ => StatusCode::BadRequest,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        Error::IoError(err.kind())
+    }
+}
+
 impl From<ParseError> for Error {
     fn from(err: ParseError) -> Self {
         Error::UrlError(err)
@@ -1,20 +1,69 @@
+use std::ascii::AsciiExt;
 use std::borrow::Cow;
+use std::collections::HashMap;
 use std::mem;
-use std::ops::Deref;
 use std::fmt::Debug;
+use std::vec;
 
 use httparse::Header;
 use multimap::MultiMap;
 use unicase::UniCase;
 
+use Error::{InvalidHeaderName, InvalidHeaderValue};
+
+/// A map of header fields.
+///
+/// Header names are case-insensitive, but the casing of the first occurrence of a name is
+/// preserved. Iteration yields the fields in the order they were inserted.
 #[derive(Debug, PartialEq)]
 pub struct Headers {
     inner: MultiMap<UniCase<Cow<'static, str>>, Vec<u8>>,
+    /// Each field as its name and the index of its value among the values of the name.
+    order: Vec<(UniCase<Cow<'static, str>>, usize)>,
+}
+
+/// Checks if the bytes form a token as defined in RFC 7230, section 3.2.6.
+///
+/// Header field names must be tokens.
+pub fn is_token(value: &[u8]) -> bool {
+    !value.is_empty() &&
+    value.iter().all(|&b| {
+        match b {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'!' | b'#' | b'$' | b'%' | b'&' |
+            b'\'' | b'*' | b'+' | b'-' | b'.' | b'^' | b'_' | b'`' | b'|' | b'~' => true,
+            _ => false,
+        }
+    })
+}
+
+/// Checks if the bytes are a valid header field value.
+///
+/// Visible characters, spaces, horizontal tabs and obsolete text are allowed, other control
+/// characters like CR and LF are not. Obsolete line folding is rejected.
+pub fn is_field_value(value: &[u8]) -> bool {
+    value.iter().all(|&b| b == b'\t' || (b >= 0x20 && b != 0x7f))
+}
+
+fn validate(name: &str, value: &[u8]) -> ::Result<()> {
+    if !is_token(name.as_bytes()) {
+        return Err(InvalidHeaderName);
+    }
+    if !is_field_value(value) {
+        return Err(InvalidHeaderValue);
+    }
+    Ok(())
+}
+
+fn key(name: &str) -> UniCase<Cow<'static, str>> {
+    UniCase(Cow::Borrowed(unsafe { mem::transmute::<&str, &str>(name) }))
 }
 
 impl Headers {
     pub fn new() -> Self {
-        Headers { inner: MultiMap::new() }
+        Headers {
+            inner: MultiMap::new(),
+            order: Vec::new(),
+        }
     }
 
     pub fn from_http1(raw: &[Header]) -> Self {
@@ -29,25 +78,153 @@ impl Headers {
         self.insert(header.name.to_owned(), header.value.to_vec());
     }
 
+    /// Adds a value to a header field, keeping existing values.
+    ///
+    /// This is the same as `append`.
     pub fn insert<K: Into<Cow<'static, str>> + Debug>(&mut self, name: K, value: Vec<u8>) {
-        self.inner.insert(UniCase(name.into()), value)
+        self.append(name, value)
+    }
+
+    /// Adds a value to a header field, keeping existing values.
+    pub fn append<K: Into<Cow<'static, str>> + Debug>(&mut self, name: K, value: Vec<u8>) {
+        let name = UniCase(name.into());
+        let index = self.inner.get_vec(&name).map_or(0, |values| values.len());
+        let first = if index == 0 {
+            name.clone()
+        } else {
+            self.order.iter().find(|&&(ref n, _)| n == &name).unwrap().0.clone()
+        };
+        self.order.push((first, index));
+        self.inner.insert(name, value)
+    }
+
+    /// Adds a value to a header field after validating the name and the value.
+    pub fn try_append<K: Into<Cow<'static, str>> + Debug>(&mut self,
+                                                          name: K,
+                                                          value: Vec<u8>)
+                                                          -> ::Result<()> {
+        let name = name.into();
+        try!(validate(&name, &value));
+        self.append(name, value);
+        Ok(())
+    }
+
+    /// Replaces all values of a header field with a single value.
+    pub fn set<K: Into<Cow<'static, str>> + Debug>(&mut self, name: K, value: Vec<u8>) {
+        let name = name.into();
+        self.remove(&name);
+        self.append(name, value)
+    }
+
+    /// Replaces all values of a header field after validating the name and the value.
+    pub fn try_set<K: Into<Cow<'static, str>> + Debug>(&mut self,
+                                                       name: K,
+                                                       value: Vec<u8>)
+                                                       -> ::Result<()> {
+        let name = name.into();
+        try!(validate(&name, &value));
+        self.set(name, value);
+        Ok(())
+    }
+
+    /// Checks all header fields with `is_token` and `is_field_value`.
+    pub fn validate(&self) -> ::Result<()> {
+        for (name, value) in self.iter() {
+            try!(validate(name, value));
+        }
+        Ok(())
+    }
+
+    /// Removes a header field and returns its values.
+    pub fn remove(&mut self, name: &str) -> Option<Vec<Vec<u8>>> {
+        let name = key(name);
+        self.order.retain(|&(ref n, _)| n != &name);
+        self.inner.remove(&name)
+    }
+
+    /// Returns the first value of a header field.
+    pub fn get(&self, name: &str) -> Option<&[u8]> {
+        self.get_vec(name).and_then(|values| values.first()).map(|value| &value[..])
     }
 
     pub fn get_vec(&self, name: &str) -> Option<&Vec<Vec<u8>>> {
-        self.inner.get_vec(&UniCase(Cow::Borrowed(unsafe { mem::transmute::<&str, &str>(name) })))
+        self.inner.get_vec(&key(name))
     }
 
     pub fn contains_key(&self, name: &str) -> bool {
-        self.inner
-            .contains_key(&UniCase(Cow::Borrowed(unsafe { mem::transmute::<&str, &str>(name) })))
+        self.inner.contains_key(&key(name))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.order.is_empty()
+    }
+
+    /// Iterates over all header fields in insertion order.
+    pub fn iter(&self) -> Iter {
+        Iter {
+            headers: self,
+            field: 0,
+        }
+    }
+
+    /// Removes all header fields and returns them in insertion order.
+    pub fn drain(&mut self) -> Drain {
+        let Headers { mut inner, order } = mem::replace(self, Headers::new());
+        // The values of a name appear in the order of their indices.
+        let mut values = HashMap::new();
+        let mut fields = Vec::with_capacity(order.len());
+        for (name, _) in order {
+            let value = values.entry(name.clone())
+                              .or_insert_with(|| inner.remove(&name).unwrap().into_iter())
+                              .next()
+                              .unwrap();
+            fields.push((name.0, value));
+        }
+        Drain { inner: fields.into_iter() }
+    }
+}
+
+impl<'a> IntoIterator for &'a Headers {
+    type Item = (&'a str, &'a [u8]);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
     }
 }
 
-impl Deref for Headers {
-    type Target = MultiMap<UniCase<Cow<'static, str>>, Vec<u8>>;
+/// Iterator over the header fields in insertion order.
+///
+/// Names are returned with the casing they were first inserted with.
+pub struct Iter<'a> {
+    headers: &'a Headers,
+    field: usize,
+}
+
+impl <'a>Iterator for Iter<'a> {
+    type Item = (&'a str, &'a [u8]);
 
-    fn deref<'a>(&'a self) -> &'a MultiMap<UniCase<Cow<'static, str>>, Vec<u8>> {
-        &self.inner
+    fn next(&mut self) -> Option<(&'a str, &'a [u8])> {
+        let &(ref name, index) = match self.headers.order.get(self.field) {
+            Some(field) => field,
+            None => return None,
+        };
+        self.field += 1;
+        let value = &self.headers.inner.get_vec(name).unwrap()[index];
+        Some((&name[..], &value[..]))
+    }
+}
+
+/// Iterator over the header fields removed by `Headers::drain`.
+pub struct Drain {
+    inner: vec::IntoIter<(Cow<'static, str>, Vec<u8>)>,
+}
+
+impl Iterator for Drain {
+    type Item = (Cow<'static, str>, Vec<u8>);
+
+    fn next(&mut self) -> Option<(Cow<'static, str>, Vec<u8>)> {
+        self.inner.next()
     }
 }
 
@@ -98,3 +275,172 @@ impl <'a>Iterator for IterListHeader<'a> {
         None
     }
 }
+
+/// An element of a list header together with its parameters.
+///
+/// If the element value is a single quoted-string it is unquoted, otherwise the value is kept
+/// as it was sent. Parameter values are always unquoted.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ListItem {
+    pub value: Vec<u8>,
+    pub params: Vec<(Vec<u8>, Vec<u8>)>,
+}
+
+impl ListItem {
+    /// Returns the value of the first parameter with the given name.
+    ///
+    /// Parameter names are compared case-insensitively.
+    pub fn param(&self, name: &str) -> Option<&[u8]> {
+        self.params
+            .iter()
+            .find(|&&(ref n, _)| n.eq_ignore_ascii_case(name.as_bytes()))
+            .map(|&(_, ref v)| &v[..])
+    }
+}
+
+/// Iterates over the elements of a list header as defined in RFC 7230, section 7.
+///
+/// Unlike `IterListHeader` commas and semicolons inside of quoted-strings do not separate
+/// elements and `;name=value` parameters are parsed.
+pub struct IterParamListHeader<'a> {
+    values: &'a Vec<Vec<u8>>,
+    line: usize,
+    column: usize,
+}
+
+impl <'a>IterParamListHeader<'a> {
+    pub fn new(values: &Vec<Vec<u8>>) -> IterParamListHeader {
+        IterParamListHeader {
+            values: values,
+            line: 0,
+            column: 0,
+        }
+    }
+}
+
+fn is_whitespace(byte: u8) -> bool {
+    byte == b' ' || byte == b'\t'
+}
+
+fn skip_whitespace(value: &[u8], mut column: usize) -> usize {
+    while column < value.len() && is_whitespace(value[column]) {
+        column += 1;
+    }
+    column
+}
+
+fn trim_end(value: &[u8]) -> &[u8] {
+    let mut end = value.len();
+    while end > 0 && is_whitespace(value[end - 1]) {
+        end -= 1;
+    }
+    &value[..end]
+}
+
+/// Returns the column after the end of a quoted-string starting at `column`.
+///
+/// An unterminated quoted-string extends to the end of the line.
+fn skip_quoted(value: &[u8], mut column: usize) -> usize {
+    column += 1;
+    while column < value.len() {
+        match value[column] {
+            b'"' => return column + 1,
+            b'\\' => column += 2,
+            _ => column += 1,
+        }
+    }
+    value.len()
+}
+
+/// Removes the surrounding quotes and backslash escapes of a quoted-string.
+fn unquote(quoted: &[u8]) -> Vec<u8> {
+    let mut unquoted = Vec::with_capacity(quoted.len());
+    let mut column = 1;
+    while column < quoted.len() {
+        match quoted[column] {
+            b'"' => break,
+            b'\\' if column + 1 < quoted.len() => {
+                unquoted.push(quoted[column + 1]);
+                column += 2;
+            }
+            byte => {
+                unquoted.push(byte);
+                column += 1;
+            }
+        }
+    }
+    unquoted
+}
+
+/// Scans until the next unquoted `delimiters` byte and returns the end column.
+fn scan_until(value: &[u8], mut column: usize, delimiters: &[u8]) -> usize {
+    while column < value.len() {
+        let byte = value[column];
+        if byte == b'"' {
+            column = skip_quoted(value, column);
+        } else if delimiters.contains(&byte) {
+            break;
+        } else {
+            column += 1;
+        }
+    }
+    column
+}
+
+fn parse_value(raw: &[u8]) -> Vec<u8> {
+    if raw.len() >= 2 && raw[0] == b'"' && skip_quoted(raw, 0) == raw.len() {
+        unquote(raw)
+    } else {
+        raw.to_vec()
+    }
+}
+
+impl <'a>Iterator for IterParamListHeader<'a> {
+    type Item = ListItem;
+    fn next(&mut self) -> Option<ListItem> {
+        while self.line < self.values.len() {
+            let value = &self.values[self.line];
+            let mut column = self.column;
+            while column < value.len() {
+                column = skip_whitespace(value, column);
+                if column < value.len() && value[column] == b',' {
+                    column += 1;
+                    continue;
+                }
+                let start = column;
+                column = scan_until(value, column, b",;");
+                let mut item = ListItem {
+                    value: parse_value(trim_end(&value[start..column])),
+                    params: Vec::new(),
+                };
+                while column < value.len() && value[column] == b';' {
+                    column = skip_whitespace(value, column + 1);
+                    let name_start = column;
+                    column = scan_until(value, column, b",;=");
+                    let name = trim_end(&value[name_start..column]).to_vec();
+                    let mut param_value = Vec::new();
+                    if column < value.len() && value[column] == b'=' {
+                        column = skip_whitespace(value, column + 1);
+                        let value_start = column;
+                        column = scan_until(value, column, b",;");
+                        param_value = parse_value(trim_end(&value[value_start..column]));
+                    }
+                    if !name.is_empty() {
+                        item.params.push((name, param_value));
+                    }
+                }
+                if column < value.len() {
+                    // skip the comma
+                    column += 1;
+                }
+                if !item.value.is_empty() {
+                    self.column = column;
+                    return Some(item);
+                }
+            }
+            self.line += 1;
+            self.column = 0;
+        }
+        None
+    }
+}
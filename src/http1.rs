@@ -1,6 +1,9 @@
+use std::ascii::AsciiExt;
 use std::cmp;
 use std::error::Error;
+use std::fmt;
 use std::marker::PhantomData;
+use std::net::SocketAddr;
 use std::str;
 
 use rotor::transports::stream::{Transport, Protocol};
@@ -8,6 +11,12 @@ use rotor::transports::StreamSocket;
 use rotor::buffer_util::find_substr;
 use rotor::async::Async;
 use httparse;
+use access_log::PeerAddr;
+use body::BodyStream;
+use pool::Pending;
+use Body;
+use FileBody;
+use HttpVersion;
 use Message;
 use Request;
 use Response;
@@ -29,13 +38,118 @@ pub trait Handler<C> {
     /// We don't support POST body yet, so this is only one callback, but will
     /// probably be split into many in future
     fn request(_request: Request, _ctx: &mut C) -> Response;
+
+    /// Dispatched by the connection instead of `request`.
+    ///
+    /// Handlers answering from a `pool::ThreadPool` return the `Pending` response here, the
+    /// connection then waits for it without blocking the event loop. Wrapping handlers forward
+    /// `reply` and process a pending response with `Reply::map`.
+    fn reply(request: Request, ctx: &mut C) -> Reply<C> {
+        Reply::Ready(Self::request(request, ctx))
+    }
+
+    /// Selects a reader for the body of a request, called once the head is parsed.
+    ///
+    /// Without a reader the body is collected in `Request::body`.
+    fn body_reader(_request: &Request, _ctx: &mut C) -> Option<Box<BodyReader>> {
+        None
+    }
+}
+
+/// Consumes a request body while it is received.
+///
+/// The pieces of the body are not stored in `Request::body` unless the reader appends them.
+pub trait BodyReader: Send {
+    /// Called with the next piece of the body.
+    ///
+    /// A returned response answers the request at once and the connection is closed, the
+    /// rest of the body is not read.
+    fn data(&mut self, request: &mut Request, data: &[u8]) -> Result<(), Response>;
+
+    /// Called after the last piece of the body, before the request is dispatched.
+    ///
+    /// A returned response answers the request instead of the handler.
+    fn finish(&mut self, request: &mut Request) -> Result<(), Response>;
+}
+
+/// The body reader of a request, kept in its extensions while the body is read.
+struct Reading(Box<BodyReader>);
+
+/// The answer of a handler to a request.
+#[derive(Debug)]
+pub enum Reply<C> {
+    Ready(Response),
+    /// A response produced on a thread pool.
+    Pending(Pending),
+    /// A pending response that is finished on the event loop once it is ready.
+    Mapped(Pending, Finish<C>),
+}
+
+impl<C> Reply<C> {
+    /// Changes the response, once it is ready if it is pending.
+    ///
+    /// The function runs on the event loop with the context, after the functions of inner
+    /// handlers.
+    pub fn map<F>(self, ctx: &mut C, f: F) -> Reply<C>
+        where F: FnOnce(Response, &mut C) -> Response + 'static
+    {
+        match self {
+            Reply::Ready(res) => Reply::Ready(f(res, ctx)),
+            Reply::Pending(pending) => Reply::Mapped(pending, Finish::new()).map(ctx, f),
+            Reply::Mapped(pending, mut finish) => {
+                finish.0.push(Box::new(f));
+                Reply::Mapped(pending, finish)
+            }
+        }
+    }
+
+    /// Blocks until the response is ready.
+    ///
+    /// Do not call it on the event loop, return the reply from `Handler::reply` instead.
+    pub fn wait(self, ctx: &mut C) -> Response {
+        match self {
+            Reply::Ready(res) => res,
+            Reply::Pending(pending) => pending.wait(),
+            Reply::Mapped(pending, finish) => finish.call(pending.wait(), ctx),
+        }
+    }
+}
+
+/// `Box<FnOnce>` can not be called directly.
+trait FinishBox<C> {
+    fn call_box(self: Box<Self>, res: Response, ctx: &mut C) -> Response;
+}
+
+impl<C, F: FnOnce(Response, &mut C) -> Response> FinishBox<C> for F {
+    fn call_box(self: Box<Self>, res: Response, ctx: &mut C) -> Response {
+        (*self)(res, ctx)
+    }
+}
+
+/// The functions given to `Reply::map` for a pending response, in the order they run.
+pub struct Finish<C>(Vec<Box<FinishBox<C>>>);
+
+impl<C> Finish<C> {
+    fn new() -> Finish<C> {
+        Finish(Vec::new())
+    }
+
+    fn call(self, res: Response, ctx: &mut C) -> Response {
+        self.0.into_iter().fold(res, |res, f| f.call_box(res, ctx))
+    }
+}
+
+impl<C> fmt::Debug for Finish<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Finish({} functions)", self.0.len())
+    }
 }
 
 /// A connection with a client.
 ///
 /// The `Initial`, `KeepAlive` and `ReadHeaders` states are kept separate for
 /// debugging and different timeouts in future eventuallly.
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub enum Client<C, H: Handler<C>> {
     /// The initial state of a connection.
     Initial,
@@ -55,13 +169,122 @@ pub enum Client<C, H: Handler<C>> {
     ReadTrailers(Request),
     /// A complete request.
     Parsed(Request),
+    /// Waiting for a response produced on a thread pool.
+    ///
+    /// The server resumes the connection once `Pending::on_ready` signals the response, which
+    /// is then finished with the context.
+    Waiting(Pending, Finish<C>),
     /// A connection in idle state.
     KeepAlive,
+    /// Sending a file body once the head in the output buffer is flushed.
+    ///
+    /// The server sends the file directly to the socket, the client only waits until it is
+    /// done. The flag tells if the connection is closed afterwards.
+    Sending(FileBody, bool),
+    /// Sending a streamed body in chunked transfer coding.
+    ///
+    /// The server takes the chunks with `BodyStream::poll_to` whenever the socket is writable
+    /// and registers `BodyStream::on_ready` if none are queued. The flag tells if the connection
+    /// is closed afterwards.
+    Streaming(BodyStream, bool),
+    /// A response with `Connection: close` was written, further requests are ignored.
+    ///
+    /// The client stops once the output buffer is flushed.
+    Closing,
 
     #[doc(hidden)]
     __Handler(PhantomData<(C, H)>),
 }
 
+impl<C, H: Handler<C>> PartialEq for Client<C, H> {
+    /// File bodies are compared by the number of bytes that remain to be sent.
+    fn eq(&self, other: &Self) -> bool {
+        use self::Client::*;
+        match (self, other) {
+            (&Initial, &Initial) |
+            (&ReadHeaders, &ReadHeaders) |
+            (&KeepAlive, &KeepAlive) |
+            (&Closing, &Closing) => true,
+            (&ReadFixedSize(ref a, x), &ReadFixedSize(ref b, y)) => a == b && x == y,
+            (&ReadChunked(ref a, x), &ReadChunked(ref b, y)) => a == b && x == y,
+            (&ReadTrailers(ref a), &ReadTrailers(ref b)) |
+            (&Parsed(ref a), &Parsed(ref b)) => a == b,
+            (&Sending(ref a, x), &Sending(ref b, y)) => a.len() == b.len() && x == y,
+            (&Streaming(_, x), &Streaming(_, y)) => x == y,
+            _ => false,
+        }
+    }
+}
+
+/// Writes a response, with `close` it is sent with `Connection: close`.
+///
+/// The head of a file body is followed by the `Sending` state, the head of a streamed body by
+/// the `Streaming` state.
+fn respond<C, H: Handler<C>>(mut res: Response,
+                             transport: &mut Transport,
+                             close: bool)
+                             -> Option<Client<C, H>> {
+    if close {
+        res.headers_mut().set("Connection", b"close".to_vec());
+    }
+    let close = match res.get_list_header("Connection") {
+        Some(mut values) => values.any(|v| v.eq_ignore_ascii_case(b"close")),
+        None => false,
+    };
+    // Older clients receive a streamed body buffered by `serialize_head`.
+    let stream = match res.take_body() {
+        Some(Body::Stream(stream)) if res.version == HttpVersion::Http11 => {
+            res.headers_mut().set("Transfer-Encoding", b"chunked".to_vec());
+            Some(stream)
+        }
+        Some(body) => {
+            res.set_body(body);
+            None
+        }
+        None => None,
+    };
+    match res.serialize_head(transport.output()) {
+        Ok(Some(file)) => Some(Client::Sending(file, close)),
+        Ok(None) => {
+            match stream.map(BodyStream::spawn) {
+                Some(Ok(stream)) => Some(Client::Streaming(stream, close)),
+                Some(Err(_)) => None,
+                None if close => Some(Client::Closing),
+                None => Some(Client::KeepAlive),
+            }
+        }
+        Err(_) => None,
+    }
+}
+
+/// Answers a request whose body was rejected, the connection is closed afterwards.
+fn reject<C, H: Handler<C>>(res: Response, transport: &mut Transport) -> Option<Client<C, H>> {
+    respond(res, transport, true)
+}
+
+/// Passes a piece of the body to the reader of the request or stores it in the body.
+fn read_body(req: &mut Request, data: &[u8]) -> Result<(), Response> {
+    match req.extensions_mut().remove::<Reading>() {
+        Some(Reading(mut reader)) => {
+            try!(reader.data(req, data));
+            req.extensions_mut().insert(Reading(reader));
+            Ok(())
+        }
+        None => {
+            req.body.extend_from_slice(data);
+            Ok(())
+        }
+    }
+}
+
+/// Tells the reader of the request that the body is complete.
+fn finish_body(req: &mut Request) -> Result<(), Response> {
+    match req.extensions_mut().remove::<Reading>() {
+        Some(Reading(mut reader)) => reader.finish(req),
+        None => Ok(()),
+    }
+}
+
 fn parse_headers(transport: &mut Transport) -> Result<Option<Request>, Box<Error + Send + Sync>> {
     let mut buf = transport.input();
     let headers_end = match find_substr(&buf[..], b"\r\n\r\n") {
@@ -116,20 +339,82 @@ fn parse_chunk_size(transport: &mut Transport) -> Result<Option<usize>, ()> {
     Ok(Some(chunk_size))
 }
 
-fn parse_fixed_size(transport: &mut Transport, store: &mut Vec<u8>, mut size: usize) -> usize {
+fn parse_fixed_size(transport: &mut Transport,
+                    req: &mut Request,
+                    mut size: usize)
+                    -> Result<usize, Response> {
     let mut buf = transport.input();
     let size_read = cmp::min(size, buf.len());
     size -= size_read;
-    store.extend(&buf[..size_read]);
+    let read = read_body(req, &buf[..size_read]);
     buf.consume(size_read);
-    size
+    read.map(|()| size)
 }
 
 impl<C, H: Handler<C>> Protocol<C> for Client<C, H> {
     fn accepted<S: StreamSocket>(_conn: &mut S, _context: &mut C) -> Option<Self> {
         Some(Client::Initial)
     }
-    fn data_received(mut self, transport: &mut Transport, ctx: &mut C) -> Async<Self, ()> {
+    /// Handles received data, the socket is not available.
+    ///
+    /// File bodies and streamed bodies are therefore written to the output buffer at once.
+    fn data_received(self, transport: &mut Transport, ctx: &mut C) -> Async<Self, ()> {
+        let mut result = self.data_received_from(transport, ctx, None);
+        loop {
+            let client = match result {
+                Async::Continue(Client::Sending(file, close), ()) => {
+                    if file.copy_to(transport.output()).is_err() {
+                        return Async::Stop;
+                    }
+                    if close { Client::Closing } else { Client::KeepAlive }
+                }
+                Async::Continue(Client::Streaming(stream, close), ()) => {
+                    if stream.copy_to(transport.output()).is_err() {
+                        return Async::Stop;
+                    }
+                    if close { Client::Closing } else { Client::KeepAlive }
+                }
+                result => return result,
+            };
+            if client == Client::KeepAlive && transport.input().empty() {
+                return Async::Continue(client, ());
+            }
+            result = client.data_received_from(transport, ctx, None);
+        }
+    }
+}
+
+impl<C, H: Handler<C>> Client<C, H> {
+    /// Checks if the connection is between requests.
+    pub fn is_idle(&self) -> bool {
+        match *self {
+            Client::Initial | Client::KeepAlive => true,
+            _ => false,
+        }
+    }
+
+    /// Handles received data like `data_received`, except that file and streamed bodies are
+    /// left to the server in the `Sending` and `Streaming` states.
+    ///
+    /// The address of the client is attached to each request as a `PeerAddr` extension.
+    pub fn data_received_from(self,
+                              transport: &mut Transport,
+                              ctx: &mut C,
+                              peer: Option<SocketAddr>)
+                              -> Async<Self, ()> {
+        self.data_received_with(transport, ctx, peer, false)
+    }
+
+    /// Handles received data like `data_received_from`.
+    ///
+    /// With `close` responses are sent with `Connection: close` and the connection is closed
+    /// afterwards, a server does so while it drains.
+    pub fn data_received_with(mut self,
+                              transport: &mut Transport,
+                              ctx: &mut C,
+                              peer: Option<SocketAddr>,
+                              close: bool)
+                              -> Async<Self, ()> {
         use self::Client::*;
         loop {
             self = match self {
@@ -137,7 +422,15 @@ impl<C, H: Handler<C>> Protocol<C> for Client<C, H> {
                     match parse_headers(transport) {
                         Err(_) => return Async::Stop,
                         Ok(None) => return Async::Continue(ReadHeaders, ()),
-                        Ok(Some(req)) => {
+                        Ok(Some(mut req)) => {
+                            if let Some(peer) = peer {
+                                req.extensions_mut().insert(PeerAddr(peer));
+                            }
+                            if req.content_length().is_ok() || req.is_chunked() {
+                                if let Some(reader) = <H as Handler<C>>::body_reader(&req, ctx) {
+                                    req.extensions_mut().insert(Reading(reader));
+                                }
+                            }
                             if let Ok(length) = req.content_length() {
                                 ReadFixedSize(req, length)
                             } else if req.is_chunked() {
@@ -149,9 +442,15 @@ impl<C, H: Handler<C>> Protocol<C> for Client<C, H> {
                     }
                 }
                 ReadFixedSize(mut req, size) => {
-                    match parse_fixed_size(transport, &mut req.body, size) {
-                        0 => Parsed(req),
-                        x => return return Async::Continue(ReadFixedSize(req, x), ()),
+                    match parse_fixed_size(transport, &mut req, size) {
+                        Ok(0) => Parsed(req),
+                        Ok(x) => return Async::Continue(ReadFixedSize(req, x), ()),
+                        Err(res) => {
+                            match reject(res, transport) {
+                                Some(client) => client,
+                                None => return Async::Stop,
+                            }
+                        }
                     }
                 }
                 ReadChunked(req, None) => {
@@ -182,9 +481,16 @@ impl<C, H: Handler<C>> Protocol<C> for Client<C, H> {
                     buf.consume(2);
                     ReadChunked(req, None)
                 }
-                ReadChunked(mut req, Some(mut size)) => {
-                    size = parse_fixed_size(transport, &mut req.body, size);
-                    ReadChunked(req, Some(size))
+                ReadChunked(mut req, Some(size)) => {
+                    match parse_fixed_size(transport, &mut req, size) {
+                        Ok(size) => ReadChunked(req, Some(size)),
+                        Err(res) => {
+                            match reject(res, transport) {
+                                Some(client) => client,
+                                None => return Async::Stop,
+                            }
+                        }
+                    }
                 }
                 ReadTrailers(mut req) => {
                     use httparse::Status::*;
@@ -201,13 +507,52 @@ impl<C, H: Handler<C>> Protocol<C> for Client<C, H> {
                     buf.consume(consumed_len);
                     Parsed(req)
                 }
-                Parsed(req) => {
-                    let res = <H as Handler<C>>::request(req, ctx);
-                    let buf = transport.output();
-                    if let Err(_) = res.serialize(buf) {
+                Parsed(mut req) => {
+                    let res = match finish_body(&mut req) {
+                        Ok(()) => {
+                            match <H as Handler<C>>::reply(req, ctx) {
+                                Reply::Ready(res) => res,
+                                Reply::Pending(pending) => {
+                                    return Async::Continue(Waiting(pending, Finish::new()), ())
+                                }
+                                Reply::Mapped(pending, finish) => {
+                                    return Async::Continue(Waiting(pending, finish), ())
+                                }
+                            }
+                        }
+                        Err(res) => res,
+                    };
+                    match respond(res, transport, close) {
+                        Some(client @ Sending(..)) |
+                        Some(client @ Streaming(..)) => return Async::Continue(client, ()),
+                        Some(client) => client,
+                        None => return Async::Stop,
+                    }
+                }
+                Waiting(pending, finish) => {
+                    let res = match pending.poll() {
+                        Some(res) => finish.call(res, ctx),
+                        None => return Async::Continue(Waiting(pending, finish), ()),
+                    };
+                    match respond(res, transport, close) {
+                        Some(client @ Sending(..)) |
+                        Some(client @ Streaming(..)) => return Async::Continue(client, ()),
+                        Some(client) => client,
+                        None => return Async::Stop,
+                    }
+                }
+                client @ Sending(..) |
+                client @ Streaming(..) => return Async::Continue(client, ()),
+                Closing => {
+                    {
+                        let mut buf = transport.input();
+                        let len = buf.len();
+                        buf.consume(len);
+                    }
+                    if transport.output().empty() {
                         return Async::Stop;
                     }
-                    KeepAlive
+                    return Async::Continue(Closing, ());
                 }
                 _ => unimplemented!(),
             };
@@ -0,0 +1,58 @@
+//! JSON bodies with serde, enabled by the `json` feature.
+use std::ascii::AsciiExt;
+use std::io;
+
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json;
+
+use Error::{InvalidBody, UnsupportedMediaType};
+use {Message, Request, Response};
+
+fn is_json<M: Message>(message: &M) -> bool {
+    match message.content_type() {
+        Some(media_type) => {
+            let value = &media_type.value;
+            value.eq_ignore_ascii_case(b"application/json") ||
+            (value.len() > 5 && value[value.len() - 5..].eq_ignore_ascii_case(b"+json"))
+        }
+        None => false,
+    }
+}
+
+impl Request {
+    /// Deserializes a JSON body.
+    ///
+    /// Fails with `Error::UnsupportedMediaType` if the body is not `application/json` or a
+    /// `+json` media type and with `Error::InvalidBody` if the body can not be deserialized.
+    pub fn json<T: DeserializeOwned>(&self) -> ::Result<T> {
+        if !is_json(self) {
+            return Err(UnsupportedMediaType);
+        }
+        serde_json::from_slice(&self.body).map_err(|_| InvalidBody)
+    }
+}
+
+impl Response {
+    /// Serializes a value as JSON body and sets the `Content-Type`.
+    pub fn json<T: Serialize>(&mut self, value: &T) -> ::Result<()> {
+        let body = try!(serde_json::to_vec(value)
+                            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err)));
+        self.headers_mut().set("Content-Type", b"application/json".to_vec());
+        self.put_body(body);
+        Ok(())
+    }
+
+    /// Serializes a value as JSON while the response is written and sets the `Content-Type`.
+    ///
+    /// No intermediate `Vec` of the whole value is built, a server worker sends the chunks as
+    /// the client receives them. HTTP/1.0 clients receive a body buffered in memory.
+    /// Serialization errors abort the response.
+    pub fn json_stream<T: Serialize + Send + 'static>(&mut self, value: T) {
+        self.headers_mut().set("Content-Type", b"application/json".to_vec());
+        self.put_stream(move |w: &mut io::Write| {
+            serde_json::to_writer(w, &value)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+        });
+    }
+}
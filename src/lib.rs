@@ -1,12 +1,27 @@
 extern crate httparse;
+extern crate libc;
 extern crate hyper;
 extern crate mio;
+extern crate net2;
 extern crate netbuf;
+extern crate num_cpus;
 extern crate rotor;
 extern crate unicase;
 extern crate url;
 extern crate time;
 extern crate multimap;
+#[cfg(any(feature = "secure-cookies", feature = "digest-auth"))]
+extern crate crypto;
+#[cfg(any(feature = "secure-cookies", feature = "digest-auth"))]
+extern crate rand;
+#[cfg(feature = "secure-cookies")]
+extern crate rustc_serialize;
+#[cfg(feature = "compression")]
+extern crate flate2;
+#[cfg(feature = "json")]
+extern crate serde;
+#[cfg(feature = "json")]
+extern crate serde_json;
 
 use rotor::transports::{accept, stream};
 
@@ -18,19 +33,49 @@ pub use mio::tcp::{TcpListener, TcpStream};
 pub use rotor::Handler as EventHandler;
 pub use url::Url;
 
+pub use body::{Body, FileBody, WriteBody};
+pub use cookie::{CookieJar, SetCookie};
 pub use error::{Error, Result};
-pub use headers::{IterListHeader, Headers};
-pub use http1::Handler;
+pub use extensions::Extensions;
+pub use headers::{IterListHeader, IterParamListHeader, Headers, ListItem, is_token, is_field_value};
+pub use http1::{BodyReader, Handler, Reply};
 pub use message::Message;
 pub use request::Request;
 pub use response::Response;
+pub use urlencoded::Params;
 
+pub mod access_log;
+pub mod auth;
+pub mod body;
+#[cfg(feature = "compression")]
+pub mod compression;
+pub mod conditional;
+pub mod cookie;
+pub mod date;
+#[cfg(feature = "digest-auth")]
+pub mod digest;
 mod error;
-mod headers;
+mod extensions;
+pub mod headers;
 pub mod http1;
+#[cfg(feature = "json")]
+mod json;
+pub mod limits;
 mod message;
+pub mod middleware;
+pub mod multipart;
+pub mod negotiation;
+pub mod pool;
+pub mod range;
+pub mod rate_limit;
 mod request;
 mod response;
+pub mod router;
+pub mod server;
+pub mod shutdown;
+pub mod static_files;
+pub mod urlencoded;
+mod worker;
 
 pub type HttpServer<C, R> = accept::Serve<C,
                         TcpListener,
@@ -0,0 +1,203 @@
+//! Limits on concurrent connections and requests.
+//!
+//! A `Limiter` counts open connections, globally and per client IP address, and requests in
+//! flight. Clones share the counters, so one limiter can protect all workers of a server.
+//! Requests over the in-flight limit are shed with `503 Service Unavailable` and a
+//! `Retry-After` header by the `Shed` middleware.
+//!
+//! Connections are counted with guards from `Limiter::connect`. `server::Server::limiter`
+//! holds a guard for every accepted connection: connections over the limit per IP address are
+//! closed right away, and the workers pause accepting until `Limiter::is_saturated` returns
+//! `false`. The workers also count every request from its first byte until its response is
+//! sent, `Shed` needs this count.
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+use middleware::Middleware;
+use {Request, Response, StatusCode};
+
+/// The configuration of a `Limiter`. `None` means unlimited.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    pub max_in_flight: Option<usize>,
+    /// The seconds sent in the `Retry-After` header of shed requests.
+    pub retry_after: u32,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_connections: None,
+            max_connections_per_ip: None,
+            max_in_flight: None,
+            retry_after: 1,
+        }
+    }
+}
+
+/// The limit that was hit.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Exceeded {
+    Connections,
+    ConnectionsPerIp,
+    InFlight,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    connections: usize,
+    per_ip: HashMap<IpAddr, usize>,
+    in_flight: usize,
+}
+
+/// Shared counters enforcing `Limits`.
+#[derive(Clone, Debug)]
+pub struct Limiter {
+    limits: Limits,
+    counters: Arc<Mutex<Counters>>,
+}
+
+impl Limiter {
+    pub fn new(limits: Limits) -> Limiter {
+        Limiter {
+            limits: limits,
+            counters: Arc::new(Mutex::new(Counters::default())),
+        }
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Counts a new connection, the guard releases it when dropped.
+    pub fn connect(&self, ip: Option<IpAddr>) -> Result<ConnectionGuard, Exceeded> {
+        let mut counters = self.counters.lock().unwrap();
+        if self.limits.max_connections.map_or(false, |max| counters.connections >= max) {
+            return Err(Exceeded::Connections);
+        }
+        if let (Some(ip), Some(max)) = (ip, self.limits.max_connections_per_ip) {
+            // Rejected addresses must not leave an entry behind.
+            if counters.per_ip.get(&ip).map_or(0, |count| *count) >= max {
+                return Err(Exceeded::ConnectionsPerIp);
+            }
+            *counters.per_ip.entry(ip).or_insert(0) += 1;
+        }
+        counters.connections += 1;
+        Ok(ConnectionGuard {
+            limiter: self.clone(),
+            ip: ip,
+        })
+    }
+
+    /// Counts a request in flight, the guard releases it when dropped.
+    pub fn begin_request(&self) -> Result<RequestGuard, Exceeded> {
+        let mut counters = self.counters.lock().unwrap();
+        if self.limits.max_in_flight.map_or(false, |max| counters.in_flight >= max) {
+            return Err(Exceeded::InFlight);
+        }
+        counters.in_flight += 1;
+        Ok(RequestGuard { limiter: self.clone() })
+    }
+
+    /// Counts a request in flight even over the limit, the guard releases it when dropped.
+    ///
+    /// The workers of a server count their requests like this.
+    pub fn count_request(&self) -> RequestGuard {
+        self.counters.lock().unwrap().in_flight += 1;
+        RequestGuard { limiter: self.clone() }
+    }
+
+    /// Checks if more requests are in flight than allowed.
+    pub fn is_overloaded(&self) -> bool {
+        let counters = self.counters.lock().unwrap();
+        self.limits.max_in_flight.map_or(false, |max| counters.in_flight > max)
+    }
+
+    /// Checks if no further connections are allowed.
+    pub fn is_saturated(&self) -> bool {
+        let counters = self.counters.lock().unwrap();
+        self.limits.max_connections.map_or(false, |max| counters.connections >= max)
+    }
+
+    pub fn connections(&self) -> usize {
+        self.counters.lock().unwrap().connections
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.counters.lock().unwrap().in_flight
+    }
+
+    /// Creates a `503 Service Unavailable` response with a `Retry-After` header.
+    pub fn unavailable(&self, req: &Request) -> Response {
+        let mut res = Response::new(req.version);
+        res.status = StatusCode::ServiceUnavailable;
+        res.headers_mut().set("Retry-After", self.limits.retry_after.to_string().into_bytes());
+        res.put_body("");
+        res
+    }
+}
+
+/// An open connection counted by a `Limiter`.
+#[derive(Debug)]
+pub struct ConnectionGuard {
+    limiter: Limiter,
+    ip: Option<IpAddr>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut counters = self.limiter.counters.lock().unwrap();
+        counters.connections -= 1;
+        if let Some(ip) = self.ip {
+            let remove = match counters.per_ip.get_mut(&ip) {
+                Some(count) => {
+                    *count -= 1;
+                    *count == 0
+                }
+                None => false,
+            };
+            if remove {
+                counters.per_ip.remove(&ip);
+            }
+        }
+    }
+}
+
+/// A request in flight counted by a `Limiter`.
+#[derive(Debug)]
+pub struct RequestGuard {
+    limiter: Limiter,
+}
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.limiter.counters.lock().unwrap().in_flight -= 1;
+    }
+}
+
+/// Access to the `Limiter` in the context.
+pub trait Limited {
+    fn limiter(&self) -> &Limiter;
+}
+
+/// Middleware shedding requests over the in-flight limit.
+///
+/// The request itself must already be counted, as the workers of a `server::Server` with the
+/// same limiter do. Shed requests stay counted until their response is sent.
+pub struct Shed;
+
+impl<C: Limited> Middleware<C> for Shed {
+    type State = ();
+
+    fn before(req: &mut Request, ctx: &mut C) -> Result<(), Response> {
+        if ctx.limiter().is_overloaded() {
+            return Err(ctx.limiter().unavailable(req));
+        }
+        Ok(())
+    }
+
+    fn after(_: (), _: &mut Response, _: &mut C) {}
+}
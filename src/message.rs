@@ -2,7 +2,7 @@ use std::str;
 use std::str::FromStr;
 use std::ascii::AsciiExt;
 
-use IterListHeader;
+use {IterListHeader, IterParamListHeader, ListItem};
 use Error::{ForbiddenHeader, MissingHeader};
 
 pub trait Message {
@@ -25,6 +25,15 @@ pub trait Message {
         }
     }
 
+    fn get_param_list_header(&self, name: &str) -> Option<IterParamListHeader> {
+        self.get_header(name).map(IterParamListHeader::new)
+    }
+
+    /// Returns the media type and its parameters from the `Content-Type` header.
+    fn content_type(&self) -> Option<ListItem> {
+        self.get_param_list_header("Content-Type").and_then(|mut items| items.next())
+    }
+
     fn content_length(&self) -> ::Result<usize> {
         if self.contains_header("Transfer-Encoding") {
             return Err(ForbiddenHeader);
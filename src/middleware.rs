@@ -0,0 +1,105 @@
+//! Layers around handlers for cross-cutting concerns.
+//!
+//! A `Middleware` sees each request before the inner handler and each response after it. It
+//! may also answer a request itself, the inner handler is skipped then. `Chain<M, H>` wraps a
+//! handler `H` in a middleware `M` and is a handler itself.
+//!
+//! Layers are stacked with tuples: in `Chain<(A, B, C), H>` the request passes through `A`,
+//! `B` and `C` and the response through `C`, `B` and `A`.
+//!
+//! A pending reply of the handler is forwarded, the `after` functions run on the event loop
+//! once its response is ready. Their state is kept until then, so it must be `'static`.
+use std::marker::PhantomData;
+
+use {BodyReader, Handler, Reply, Request, Response};
+
+/// A layer that inspects and modifies requests and responses.
+pub trait Middleware<C> {
+    /// Data the layer keeps from `before` for `after`, for example the start time.
+    type State;
+
+    /// Called with the request before the inner handler.
+    ///
+    /// Returning a response short-circuits the chain: the inner layers and the handler are
+    /// skipped and `after` of this layer is not called, outer layers still see the response.
+    fn before(req: &mut Request, ctx: &mut C) -> Result<Self::State, Response>;
+
+    /// Called with the response of the inner handler.
+    fn after(state: Self::State, res: &mut Response, ctx: &mut C);
+}
+
+/// A handler wrapping the handler `H` in the middleware `M`.
+pub struct Chain<M, H>(PhantomData<(M, H)>);
+
+impl<C, M: Middleware<C>, H: Handler<C>> Handler<C> for Chain<M, H>
+    where M::State: 'static
+{
+    fn request(mut req: Request, ctx: &mut C) -> Response {
+        let state = match M::before(&mut req, ctx) {
+            Ok(state) => state,
+            Err(res) => return res,
+        };
+        let mut res = H::request(req, ctx);
+        M::after(state, &mut res, ctx);
+        res
+    }
+
+    fn reply(mut req: Request, ctx: &mut C) -> Reply<C> {
+        let state = match M::before(&mut req, ctx) {
+            Ok(state) => state,
+            Err(res) => return Reply::Ready(res),
+        };
+        H::reply(req, ctx).map(ctx, move |mut res, ctx| {
+            M::after(state, &mut res, ctx);
+            res
+        })
+    }
+
+    fn body_reader(req: &Request, ctx: &mut C) -> Option<Box<BodyReader>> {
+        H::body_reader(req, ctx)
+    }
+}
+
+impl<C, A: Middleware<C>, B: Middleware<C>> Middleware<C> for (A, B) {
+    type State = (A::State, B::State);
+
+    fn before(req: &mut Request, ctx: &mut C) -> Result<Self::State, Response> {
+        let a = try!(A::before(req, ctx));
+        match B::before(req, ctx) {
+            Ok(b) => Ok((a, b)),
+            Err(mut res) => {
+                A::after(a, &mut res, ctx);
+                Err(res)
+            }
+        }
+    }
+
+    fn after(state: Self::State, res: &mut Response, ctx: &mut C) {
+        B::after(state.1, res, ctx);
+        A::after(state.0, res, ctx);
+    }
+}
+
+// Longer tuples behave like nested pairs: `(A, B, C)` is `(A, (B, C))`.
+macro_rules! tuple_middleware {
+    ($first:ident, $($rest:ident),+) => {
+        impl<Ctx, $first: Middleware<Ctx>, $($rest: Middleware<Ctx>),+> Middleware<Ctx>
+            for ($first, $($rest),+)
+        {
+            type State = <($first, ($($rest),+)) as Middleware<Ctx>>::State;
+
+            fn before(req: &mut Request, ctx: &mut Ctx) -> Result<Self::State, Response> {
+                <($first, ($($rest),+)) as Middleware<Ctx>>::before(req, ctx)
+            }
+
+            fn after(state: Self::State, res: &mut Response, ctx: &mut Ctx) {
+                <($first, ($($rest),+)) as Middleware<Ctx>>::after(state, res, ctx)
+            }
+        }
+    }
+}
+
+tuple_middleware!(A, B, C);
+tuple_middleware!(A, B, C, D);
+tuple_middleware!(A, B, C, D, E);
+tuple_middleware!(A, B, C, D, E, F);
@@ -0,0 +1,447 @@
+//! Streaming parser for `multipart/form-data` bodies as defined in RFC 7578.
+//!
+//! The `Parser` is fed the body in arbitrarily sized pieces and reports the parts to a `Sink`
+//! without buffering more than a part header and a boundary worth of data. The `Collector` is
+//! a sink that keeps form fields in memory and writes file uploads to temporary files.
+//!
+//! Wrap a handler in `Multipart` to parse `multipart/form-data` bodies while they are
+//! received, the handler finds the parts in the `Parts` extension of the request.
+use std::ascii::AsciiExt;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::marker::PhantomData;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+use httparse;
+use time;
+
+use Error::{self, InvalidHeader, InvalidMessage, TooLarge, UnsupportedMediaType};
+use http1::{MAX_HEADERS_NUM, MAX_HEADERS_SIZE};
+use {BodyReader, Handler, Headers, IterParamListHeader, Message, Reply, Request, Response};
+
+/// Size limits enforced while parsing.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Limits {
+    /// The maximum size of the body of a single part.
+    pub max_part_size: usize,
+    /// The maximum size of the whole multipart body.
+    pub max_total_size: usize,
+    /// The maximum number of parts.
+    pub max_parts: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Limits {
+        Limits {
+            max_part_size: 16_777_216,
+            max_total_size: ::http1::MAX_BODY_SIZE,
+            max_parts: 256,
+        }
+    }
+}
+
+/// Extracts the boundary from the `Content-Type` of a `multipart/form-data` message.
+pub fn boundary<M: Message>(message: &M) -> ::Result<Vec<u8>> {
+    let media_type = try!(message.content_type().ok_or(UnsupportedMediaType));
+    if !media_type.value.eq_ignore_ascii_case(b"multipart/form-data") {
+        return Err(UnsupportedMediaType);
+    }
+    match media_type.param("boundary") {
+        Some(boundary) if !boundary.is_empty() && boundary.len() <= 70 => Ok(boundary.to_vec()),
+        _ => Err(InvalidHeader),
+    }
+}
+
+/// Receives the parts found by a `Parser`.
+pub trait Sink {
+    /// Called with the header fields of a new part.
+    fn part_begin(&mut self, headers: Headers) -> ::Result<()>;
+    /// Called with a piece of the body of the current part.
+    fn part_data(&mut self, data: &[u8]) -> ::Result<()>;
+    /// Called after the body of the current part is complete.
+    fn part_end(&mut self) -> ::Result<()>;
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum State {
+    Preamble,
+    AfterBoundary,
+    Headers,
+    Body,
+    Epilogue,
+}
+
+/// An incremental `multipart/form-data` parser.
+pub struct Parser {
+    /// The delimiter between parts including the leading line break.
+    delimiter: Vec<u8>,
+    state: State,
+    /// Tells if no data of the preamble was consumed yet.
+    at_start: bool,
+    buf: Vec<u8>,
+    limits: Limits,
+    total_size: usize,
+    part_size: usize,
+    parts: usize,
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.len() > haystack.len() {
+        return None;
+    }
+    (0..haystack.len() - needle.len() + 1).find(|&i| &haystack[i..i + needle.len()] == needle)
+}
+
+impl Parser {
+    pub fn new(boundary: &[u8], limits: Limits) -> Parser {
+        let mut delimiter = b"\r\n--".to_vec();
+        delimiter.extend(boundary);
+        Parser {
+            delimiter: delimiter,
+            state: State::Preamble,
+            at_start: true,
+            buf: Vec::new(),
+            limits: limits,
+            total_size: 0,
+            part_size: 0,
+            parts: 0,
+        }
+    }
+
+    /// Parses the next piece of the body.
+    pub fn feed<S: Sink>(&mut self, data: &[u8], sink: &mut S) -> ::Result<()> {
+        self.total_size += data.len();
+        if self.total_size > self.limits.max_total_size {
+            return Err(TooLarge);
+        }
+        if self.state == State::Epilogue {
+            return Ok(());
+        }
+        self.buf.extend(data);
+        loop {
+            let consumed = match self.state {
+                State::Preamble => {
+                    // The first delimiter may appear at the very start without a line break,
+                    // elsewhere it must follow one.
+                    let first = &self.delimiter[2..];
+                    if self.at_start && self.buf.len() < first.len() &&
+                       first.starts_with(&self.buf) {
+                        return Ok(());
+                    }
+                    let found = if self.at_start && self.buf.starts_with(first) {
+                        Some(0)
+                    } else {
+                        find(&self.buf, &self.delimiter).map(|i| i + 2)
+                    };
+                    match found {
+                        Some(i) => {
+                            self.state = State::AfterBoundary;
+                            i + first.len()
+                        }
+                        None => {
+                            let consumed = self.keep_tail(self.delimiter.len() - 1);
+                            self.at_start = self.at_start && consumed == 0;
+                            consumed
+                        }
+                    }
+                }
+                State::AfterBoundary => {
+                    if self.buf.len() < 2 {
+                        return Ok(());
+                    }
+                    if &self.buf[..2] == b"--" {
+                        self.state = State::Epilogue;
+                        self.buf.clear();
+                        return Ok(());
+                    } else if &self.buf[..2] == b"\r\n" {
+                        self.parts += 1;
+                        if self.parts > self.limits.max_parts {
+                            return Err(TooLarge);
+                        }
+                        self.state = State::Headers;
+                        2
+                    } else {
+                        return Err(InvalidMessage);
+                    }
+                }
+                State::Headers => {
+                    let mut headers = [httparse::EMPTY_HEADER; MAX_HEADERS_NUM];
+                    match httparse::parse_headers(&self.buf, &mut headers) {
+                        Ok(httparse::Status::Complete((len, headers))) => {
+                            try!(sink.part_begin(Headers::from_http1(headers)));
+                            self.state = State::Body;
+                            self.part_size = 0;
+                            len
+                        }
+                        Ok(httparse::Status::Partial) => {
+                            if self.buf.len() > MAX_HEADERS_SIZE {
+                                return Err(TooLarge);
+                            }
+                            return Ok(());
+                        }
+                        Err(_) => return Err(InvalidMessage),
+                    }
+                }
+                State::Body => {
+                    let (data_len, consumed) = match find(&self.buf, &self.delimiter) {
+                        Some(i) => (i, i + self.delimiter.len()),
+                        None => {
+                            let len = self.keep_tail(self.delimiter.len() - 1);
+                            (len, len)
+                        }
+                    };
+                    self.part_size += data_len;
+                    if self.part_size > self.limits.max_part_size {
+                        return Err(TooLarge);
+                    }
+                    if data_len > 0 {
+                        try!(sink.part_data(&self.buf[..data_len]));
+                    }
+                    if data_len != consumed {
+                        try!(sink.part_end());
+                        self.state = State::AfterBoundary;
+                    }
+                    consumed
+                }
+                State::Epilogue => unreachable!(),
+            };
+            if consumed == 0 {
+                return Ok(());
+            }
+            self.buf.drain(..consumed);
+        }
+    }
+
+    /// Returns how many bytes can be consumed if the last `tail` bytes need to be kept.
+    fn keep_tail(&self, tail: usize) -> usize {
+        self.buf.len().saturating_sub(tail)
+    }
+
+    /// Checks that the closing delimiter was found.
+    pub fn finish(&self) -> ::Result<()> {
+        if self.state == State::Epilogue {
+            Ok(())
+        } else {
+            Err(InvalidMessage)
+        }
+    }
+}
+
+static TEMP_FILE_COUNTER: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// A temporary file that is removed when it is dropped.
+#[derive(Debug)]
+pub struct TempFile {
+    path: Option<PathBuf>,
+}
+
+impl TempFile {
+    /// Creates a new empty file with a unique name in the given directory.
+    pub fn create(dir: &Path) -> ::Result<(TempFile, File)> {
+        let path = dir.join(format!("kinglet-upload-{}-{}",
+                                    time::precise_time_ns(),
+                                    TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)));
+        let file = try!(OpenOptions::new().write(true).create_new(true).open(&path));
+        Ok((TempFile { path: Some(path) }, file))
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_ref().expect("path of a temporary file")
+    }
+
+    /// Moves the file to a permanent location.
+    pub fn persist<P: AsRef<Path>>(mut self, path: P) -> ::Result<()> {
+        try!(fs::rename(self.path(), path));
+        self.path = None;
+        Ok(())
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// The body of a part.
+#[derive(Debug)]
+pub enum PartData {
+    /// The value of a form field.
+    Memory(Vec<u8>),
+    /// An uploaded file.
+    File(TempFile),
+}
+
+/// A part of a `multipart/form-data` body.
+#[derive(Debug)]
+pub struct Part {
+    pub headers: Headers,
+    /// The field name from the `Content-Disposition` header.
+    pub name: Option<String>,
+    /// The original file name from the `Content-Disposition` header.
+    pub filename: Option<String>,
+    pub data: PartData,
+}
+
+impl Message for Part {
+    fn get_header(&self, name: &str) -> Option<&Vec<Vec<u8>>> {
+        self.headers.get_vec(name)
+    }
+
+    fn contains_header(&self, name: &str) -> bool {
+        self.headers.contains_key(name)
+    }
+}
+
+/// A sink that keeps fields in memory and writes parts with a file name to temporary files.
+pub struct Collector {
+    dir: PathBuf,
+    parts: Vec<Part>,
+    current: Option<Part>,
+    file: Option<File>,
+}
+
+impl Collector {
+    /// Creates a collector writing files to the system's temporary directory.
+    pub fn new() -> Collector {
+        Collector::with_dir(env::temp_dir())
+    }
+
+    pub fn with_dir<P: Into<PathBuf>>(dir: P) -> Collector {
+        Collector {
+            dir: dir.into(),
+            parts: Vec::new(),
+            current: None,
+            file: None,
+        }
+    }
+
+    pub fn into_parts(self) -> Vec<Part> {
+        self.parts
+    }
+}
+
+impl Sink for Collector {
+    fn part_begin(&mut self, headers: Headers) -> ::Result<()> {
+        let (name, filename) = match headers.get_vec("Content-Disposition") {
+            Some(values) => {
+                match IterParamListHeader::new(values).next() {
+                    Some(ref disposition) if disposition.value.eq_ignore_ascii_case(b"form-data") => {
+                        let param = |name| {
+                            disposition.param(name)
+                                       .and_then(|v| str::from_utf8(v).ok())
+                                       .map(|v| v.to_owned())
+                        };
+                        (param("name"), param("filename"))
+                    }
+                    _ => return Err(InvalidHeader),
+                }
+            }
+            None => return Err(InvalidHeader),
+        };
+        let data = if filename.is_some() {
+            let (temp_file, file) = try!(TempFile::create(&self.dir));
+            self.file = Some(file);
+            PartData::File(temp_file)
+        } else {
+            PartData::Memory(Vec::new())
+        };
+        self.current = Some(Part {
+            headers: headers,
+            name: name,
+            filename: filename,
+            data: data,
+        });
+        Ok(())
+    }
+
+    fn part_data(&mut self, data: &[u8]) -> ::Result<()> {
+        if let Some(ref mut file) = self.file {
+            try!(file.write_all(data));
+        } else if let Some(Part { data: PartData::Memory(ref mut buf), .. }) = self.current {
+            buf.extend(data);
+        }
+        Ok(())
+    }
+
+    fn part_end(&mut self) -> ::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            try!(file.flush());
+        }
+        if let Some(part) = self.current.take() {
+            self.parts.push(part);
+        }
+        Ok(())
+    }
+}
+
+/// The parts of a body read by a `Reader`, attached to the request as an extension.
+#[derive(Debug)]
+pub struct Parts(pub Vec<Part>);
+
+/// Reads a `multipart/form-data` body into a `Collector` while it is received.
+///
+/// Once the body is complete the parts are attached to the request as `Parts`. Bodies that
+/// can not be parsed are answered with the status of the error.
+pub struct Reader {
+    parser: Parser,
+    collector: Collector,
+}
+
+impl Reader {
+    /// Creates a reader for the body of a request, fails if it is not `multipart/form-data`.
+    pub fn new(req: &Request, limits: Limits, collector: Collector) -> ::Result<Reader> {
+        Ok(Reader {
+            parser: Parser::new(&try!(boundary(req)), limits),
+            collector: collector,
+        })
+    }
+}
+
+fn error_response(req: &Request, err: Error) -> Response {
+    let mut res = Response::new(req.version);
+    res.status = err.status();
+    res.put_body("");
+    res
+}
+
+impl BodyReader for Reader {
+    fn data(&mut self, req: &mut Request, data: &[u8]) -> Result<(), Response> {
+        self.parser.feed(data, &mut self.collector).map_err(|err| error_response(req, err))
+    }
+
+    fn finish(&mut self, req: &mut Request) -> Result<(), Response> {
+        try!(self.parser.finish().map_err(|err| error_response(req, err)));
+        let parts = mem::replace(&mut self.collector.parts, Vec::new());
+        req.extensions_mut().insert(Parts(parts));
+        Ok(())
+    }
+}
+
+/// A handler reading `multipart/form-data` bodies of requests to `H` with the default limits.
+///
+/// Other bodies are read as `H` selects.
+pub struct Multipart<H>(PhantomData<H>);
+
+impl<C, H: Handler<C>> Handler<C> for Multipart<H> {
+    fn request(req: Request, ctx: &mut C) -> Response {
+        H::request(req, ctx)
+    }
+
+    fn reply(req: Request, ctx: &mut C) -> Reply<C> {
+        H::reply(req, ctx)
+    }
+
+    fn body_reader(req: &Request, ctx: &mut C) -> Option<Box<BodyReader>> {
+        match Reader::new(req, Limits::default(), Collector::new()) {
+            Ok(reader) => Some(Box::new(reader)),
+            Err(_) => H::body_reader(req, ctx),
+        }
+    }
+}
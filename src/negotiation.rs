@@ -0,0 +1,179 @@
+//! Proactive content negotiation as described in RFC 7231, section 5.3.
+//!
+//! Each function takes the representations the server is able to produce in the order of the
+//! server's preference and selects the one the client prefers most. If the request lacks the
+//! corresponding header every representation is acceptable and the first one is returned. If
+//! no representation is acceptable `Error::NotAcceptable` is returned, which maps to a
+//! `406 Not Acceptable` response.
+use std::ascii::AsciiExt;
+use std::str;
+
+use Error::NotAcceptable;
+use {IterParamListHeader, ListItem, Message, Request};
+
+/// Selects a media type from the `Accept` header.
+///
+/// Available media types are given as `type/subtype` and may carry parameters like
+/// `text/html; level=1`. The most specific matching media range determines the quality of a
+/// media type.
+pub fn media_type<'a>(req: &Request, available: &[&'a str]) -> ::Result<&'a str> {
+    negotiate(req, "Accept", available, match_media_range, |_| 0.0)
+}
+
+/// Selects a language tag from the `Accept-Language` header.
+///
+/// Language ranges are matched against tags with the "Basic Filtering" scheme of RFC 4647.
+pub fn language<'a>(req: &Request, available: &[&'a str]) -> ::Result<&'a str> {
+    negotiate(req, "Accept-Language", available, match_language_range, |_| 0.0)
+}
+
+/// Selects a content coding from the `Accept-Encoding` header.
+///
+/// The `identity` coding is acceptable unless it is explicitly excluded. An empty header
+/// allows only `identity`.
+pub fn encoding<'a>(req: &Request, available: &[&'a str]) -> ::Result<&'a str> {
+    let empty = match req.get_param_list_header("Accept-Encoding") {
+        Some(mut items) => items.next().is_none(),
+        None => false,
+    };
+    if empty {
+        return available.iter()
+                        .find(|coding| coding.eq_ignore_ascii_case("identity"))
+                        .map(|coding| *coding)
+                        .ok_or(NotAcceptable);
+    }
+    negotiate(req, "Accept-Encoding", available, match_token, |coding| {
+        if coding.eq_ignore_ascii_case("identity") {
+            1.0
+        } else {
+            0.0
+        }
+    })
+}
+
+/// Selects a charset from the `Accept-Charset` header.
+pub fn charset<'a>(req: &Request, available: &[&'a str]) -> ::Result<&'a str> {
+    negotiate(req, "Accept-Charset", available, match_token, |_| 0.0)
+}
+
+/// Returns the quality value of a list element.
+///
+/// Elements without a `q` parameter have a quality of 1, invalid values are treated as 0.
+pub fn quality(item: &ListItem) -> f32 {
+    match item.param("q") {
+        Some(value) => {
+            str::from_utf8(value)
+                .ok()
+                .and_then(|v| v.trim().parse::<f32>().ok())
+                .map(|q| if q > 1.0 { 1.0 } else if q < 0.0 { 0.0 } else { q })
+                .unwrap_or(0.0)
+        }
+        None => 1.0,
+    }
+}
+
+/// Selects the available value with the highest quality.
+///
+/// `matches` returns the specificity of a range if it matches the value. `unmatched` gives the
+/// quality of values no range matches.
+fn negotiate<'a, M, U>(req: &Request,
+                       header: &str,
+                       available: &[&'a str],
+                       matches: M,
+                       unmatched: U)
+                       -> ::Result<&'a str>
+    where M: Fn(&ListItem, &str) -> Option<usize>,
+          U: Fn(&str) -> f32
+{
+    let ranges: Vec<ListItem> = match req.get_param_list_header(header) {
+        Some(iter) => iter.collect(),
+        None => Vec::new(),
+    };
+    if ranges.is_empty() {
+        return available.first().map(|v| *v).ok_or(NotAcceptable);
+    }
+    let mut best: Option<(&'a str, f32)> = None;
+    for value in available {
+        let mut most_specific: Option<(usize, f32)> = None;
+        for range in &ranges {
+            if let Some(specificity) = matches(range, value) {
+                match most_specific {
+                    Some((s, _)) if s >= specificity => (),
+                    _ => most_specific = Some((specificity, quality(range))),
+                }
+            }
+        }
+        let q = match most_specific {
+            Some((_, q)) => q,
+            None => unmatched(value),
+        };
+        match best {
+            Some((_, best_q)) if best_q >= q => (),
+            _ if q > 0.0 => best = Some((value, q)),
+            _ => (),
+        }
+    }
+    best.map(|(value, _)| value).ok_or(NotAcceptable)
+}
+
+fn match_media_range(range: &ListItem, value: &str) -> Option<usize> {
+    let values = vec![value.as_bytes().to_vec()];
+    let media_type = match IterParamListHeader::new(&values).next() {
+        Some(media_type) => media_type,
+        None => return None,
+    };
+    let (range_type, range_subtype) = split_media_type(&range.value);
+    let (value_type, value_subtype) = split_media_type(&media_type.value);
+    let mut specificity = 0;
+    if range_type != b"*" {
+        if !range_type.eq_ignore_ascii_case(value_type) {
+            return None;
+        }
+        specificity += 1;
+    }
+    if range_subtype != b"*" {
+        if !range_subtype.eq_ignore_ascii_case(value_subtype) {
+            return None;
+        }
+        specificity += 1;
+    }
+    // Parameters after the quality value are accept-ext, not media type parameters.
+    for &(ref name, ref param) in range.params.iter().take_while(|&&(ref n, _)| !n.eq_ignore_ascii_case(b"q")) {
+        if media_type.param(str::from_utf8(name).unwrap_or("")) != Some(&param[..]) {
+            return None;
+        }
+        specificity += 1;
+    }
+    Some(specificity)
+}
+
+fn split_media_type(media_type: &[u8]) -> (&[u8], &[u8]) {
+    match media_type.iter().position(|b| *b == b'/') {
+        Some(i) => (&media_type[..i], &media_type[i + 1..]),
+        None => (media_type, b""),
+    }
+}
+
+fn match_language_range(range: &ListItem, value: &str) -> Option<usize> {
+    let range = &range.value[..];
+    let value = value.as_bytes();
+    if range == b"*" {
+        Some(0)
+    } else if range.eq_ignore_ascii_case(value) ||
+       (value.len() > range.len() && value[range.len()] == b'-' &&
+        range.eq_ignore_ascii_case(&value[..range.len()])) {
+        Some(range.len())
+    } else {
+        None
+    }
+}
+
+fn match_token(range: &ListItem, value: &str) -> Option<usize> {
+    if range.value == b"*" {
+        Some(0)
+    } else if range.value.eq_ignore_ascii_case(value.as_bytes()) {
+        Some(1)
+    } else {
+        None
+    }
+}
@@ -0,0 +1,201 @@
+//! A thread pool for handlers that block or need a lot of CPU time.
+//!
+//! `ThreadPool::offload` runs a handler function on a pool thread and returns a `Pending`
+//! response. The queue of waiting jobs is bounded: when it is full the request is answered
+//! with `503 Service Unavailable` right away instead of piling up work.
+//!
+//! A handler returns the `Pending` response from `Handler::reply`. The connection waits
+//! without blocking the event loop: `server::Server` registers a wake-up with
+//! `Pending::on_ready` that notifies the event loop of the worker through its `mio::Sender`
+//! once the response is ready, and the connection then takes it with `Pending::poll`.
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError, TrySendError};
+use std::thread::{self, JoinHandle};
+
+use {HttpVersion, Request, Response, StatusCode};
+
+type Job = Box<FnBox + Send>;
+
+/// `Box<FnOnce()>` can not be called directly.
+trait FnBox {
+    fn call_box(self: Box<Self>);
+}
+
+impl<F: FnOnce()> FnBox for F {
+    fn call_box(self: Box<Self>) {
+        (*self)()
+    }
+}
+
+/// A fixed number of threads executing handlers from a bounded queue.
+pub struct ThreadPool {
+    sender: Option<SyncSender<Job>>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool {
+    /// Starts the threads. At most `queue_size` jobs wait for a free thread.
+    ///
+    /// # Panics
+    ///
+    /// If the number of threads is zero.
+    pub fn new(threads: usize, queue_size: usize) -> ThreadPool {
+        assert!(threads > 0, "a thread pool needs at least one thread");
+        let (sender, receiver) = mpsc::sync_channel::<Job>(queue_size);
+        let receiver = Arc::new(Mutex::new(receiver));
+        let threads = (0..threads)
+                          .map(|i| {
+                              let receiver = receiver.clone();
+                              thread::Builder::new()
+                                  .name(format!("kinglet-pool-{}", i))
+                                  .spawn(move || loop {
+                                      let job = match receiver.lock().unwrap().recv() {
+                                          Ok(job) => job,
+                                          Err(_) => return,
+                                      };
+                                      job.call_box();
+                                  })
+                                  .expect("spawn a pool thread")
+                          })
+                          .collect();
+        ThreadPool {
+            sender: Some(sender),
+            threads: threads,
+        }
+    }
+
+    /// Runs a handler function on the pool.
+    ///
+    /// Returns a `503 Service Unavailable` response if the queue is full.
+    pub fn offload<F>(&self, req: Request, handler: F) -> Result<Pending, Response>
+        where F: FnOnce(Request) -> Response + Send + 'static
+    {
+        self.offload_with(req, handler, || ())
+    }
+
+    /// Runs a handler function on the pool and calls `wake` when the response is ready.
+    pub fn offload_with<F, W>(&self,
+                              req: Request,
+                              handler: F,
+                              wake: W)
+                              -> Result<Pending, Response>
+        where F: FnOnce(Request) -> Response + Send + 'static,
+              W: FnOnce() + Send + 'static
+    {
+        let version = req.version;
+        let (sender, receiver) = mpsc::channel();
+        let wakeup = Arc::new(Mutex::new(Wakeup {
+            ready: false,
+            wake: None,
+        }));
+        let ready = wakeup.clone();
+        let job = Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(move || handler(req)));
+            let _ = sender.send(result);
+            wake();
+            let wake = {
+                let mut ready = ready.lock().unwrap();
+                ready.ready = true;
+                ready.wake.take()
+            };
+            if let Some(wake) = wake {
+                wake.call_box();
+            }
+        });
+        match self.sender.as_ref().expect("a running pool").try_send(job) {
+            Ok(()) => {
+                Ok(Pending {
+                    version: version,
+                    receiver: receiver,
+                    wakeup: wakeup,
+                })
+            }
+            Err(TrySendError::Full(_)) | Err(TrySendError::Disconnected(_)) => {
+                let mut res = Response::new(version);
+                res.status = StatusCode::ServiceUnavailable;
+                res.headers_mut().set("Retry-After", b"1".to_vec());
+                res.put_body("");
+                Err(res)
+            }
+        }
+    }
+}
+
+impl Drop for ThreadPool {
+    /// Finishes the queued jobs and stops the threads.
+    fn drop(&mut self) {
+        self.sender.take();
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// The wake-up registered with `Pending::on_ready`.
+struct Wakeup {
+    ready: bool,
+    wake: Option<Job>,
+}
+
+/// A response produced on the thread pool.
+pub struct Pending {
+    version: HttpVersion,
+    receiver: Receiver<Result<Response, Box<Any + Send>>>,
+    wakeup: Arc<Mutex<Wakeup>>,
+}
+
+impl fmt::Debug for Pending {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Pending").field("version", &self.version).finish()
+    }
+}
+
+impl Pending {
+    /// Calls `wake` once the response is ready, right away if it already is.
+    ///
+    /// `wake` runs on the pool thread and replaces a previously registered function.
+    pub fn on_ready<W: FnOnce() + Send + 'static>(&self, wake: W) {
+        let mut wakeup = self.wakeup.lock().unwrap();
+        if wakeup.ready {
+            drop(wakeup);
+            wake();
+        } else {
+            wakeup.wake = Some(Box::new(wake));
+        }
+    }
+
+    /// Returns the response if it is ready.
+    ///
+    /// A handler that panicked results in a `500 Internal Server Error` response.
+    pub fn poll(&self) -> Option<Response> {
+        match self.receiver.try_recv() {
+            Ok(result) => Some(self.unwrap(result)),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(self.error()),
+        }
+    }
+
+    /// Blocks until the response is ready.
+    ///
+    /// Do not call it on the event loop, return the `Pending` from `Handler::reply` instead.
+    pub fn wait(self) -> Response {
+        match self.receiver.recv() {
+            Ok(result) => self.unwrap(result),
+            Err(_) => self.error(),
+        }
+    }
+
+    fn unwrap(&self, result: Result<Response, Box<Any + Send>>) -> Response {
+        result.unwrap_or_else(|_| self.error())
+    }
+
+    fn error(&self) -> Response {
+        let mut res = Response::new(self.version);
+        res.status = StatusCode::InternalServerError;
+        res.put_body("");
+        res
+    }
+}
@@ -0,0 +1,245 @@
+//! Range requests as defined in RFC 7233.
+//!
+//! Only the `bytes` range unit is supported. A satisfiable range request is answered with
+//! `206 Partial Content`, multiple ranges are sent as `multipart/byteranges`, and a request
+//! without any satisfiable range gets `416 Range Not Satisfiable`. Overlapping and adjacent
+//! ranges are merged first, so a representation is sent at most once per response.
+use std::ascii::AsciiExt;
+use std::cmp;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::str;
+
+use time;
+
+use body::FileBody;
+use {Body, Message, Method, Request, Response, StatusCode};
+
+/// Requests with more ranges are answered with the whole representation.
+pub const MAX_RANGES: usize = 16;
+
+/// A byte range specifier.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ByteRange {
+    /// The bytes from the first to the last position, both inclusive.
+    FromTo(u64, u64),
+    /// All bytes starting at the position.
+    From(u64),
+    /// The given number of bytes at the end.
+    Last(u64),
+}
+
+impl ByteRange {
+    /// Returns the first and the last position of the range in a representation of the given
+    /// length, or `None` if the range is not satisfiable.
+    pub fn resolve(&self, len: u64) -> Option<(u64, u64)> {
+        match *self {
+            ByteRange::FromTo(first, last) if first < len => {
+                Some((first, cmp::min(last, len - 1)))
+            }
+            ByteRange::From(first) if first < len => Some((first, len - 1)),
+            ByteRange::Last(suffix) if suffix > 0 && len > 0 => {
+                Some((len - cmp::min(suffix, len), len - 1))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Parses the value of a `Range` header.
+///
+/// Returns `None` if the header is invalid or uses another unit than bytes, such headers
+/// must be ignored.
+pub fn parse(value: &[u8]) -> Option<Vec<ByteRange>> {
+    let value = match str::from_utf8(value) {
+        Ok(value) => value.trim(),
+        Err(_) => return None,
+    };
+    let set = match value.find('=') {
+        Some(i) if value[..i].trim().eq_ignore_ascii_case("bytes") => &value[i + 1..],
+        _ => return None,
+    };
+    let mut ranges = Vec::new();
+    for spec in set.split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+        let i = match spec.find('-') {
+            Some(i) => i,
+            None => return None,
+        };
+        let (first, last) = (spec[..i].trim(), spec[i + 1..].trim());
+        let range = match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(first), Ok(last)) if first <= last => ByteRange::FromTo(first, last),
+            (Ok(first), Err(_)) if last.is_empty() => ByteRange::From(first),
+            (Err(_), Ok(suffix)) if first.is_empty() => ByteRange::Last(suffix),
+            _ => return None,
+        };
+        ranges.push(range);
+    }
+    if ranges.is_empty() {
+        None
+    } else {
+        Some(ranges)
+    }
+}
+
+/// Checks the `If-Range` precondition against the validators of the representation.
+///
+/// An entity tag must match strongly, a date must match `Last-Modified` exactly. Without an
+/// `If-Range` header the ranges always apply.
+pub fn if_range_matches<M: Message>(req: &M,
+                                    etag: Option<&[u8]>,
+                                    last_modified: Option<&[u8]>)
+                                    -> bool {
+    match req.get_value_header("If-Range") {
+        None => true,
+        Some(if_range) if if_range.starts_with(b"\"") => {
+            match etag {
+                Some(etag) => etag.starts_with(b"\"") && etag == if_range,
+                None => false,
+            }
+        }
+        Some(if_range) if if_range.starts_with(b"W/") => false,
+        Some(if_range) => last_modified == Some(if_range),
+    }
+}
+
+/// A representation that ranges can be read from.
+pub trait RangeSource {
+    fn len(&mut self) -> io::Result<u64>;
+    /// Reads the bytes from the first to the last position, both inclusive.
+    fn read_range(&mut self, first: u64, last: u64) -> io::Result<Vec<u8>>;
+    /// Turns the bytes from the first to the last position into a body.
+    fn into_body(mut self, first: u64, last: u64) -> io::Result<Body>
+        where Self: Sized
+    {
+        self.read_range(first, last).map(Body::Bytes)
+    }
+}
+
+impl<'a> RangeSource for &'a [u8] {
+    fn len(&mut self) -> io::Result<u64> {
+        Ok((**self).len() as u64)
+    }
+
+    fn read_range(&mut self, first: u64, last: u64) -> io::Result<Vec<u8>> {
+        Ok(self[first as usize..last as usize + 1].to_vec())
+    }
+}
+
+impl RangeSource for File {
+    fn len(&mut self) -> io::Result<u64> {
+        self.metadata().map(|metadata| metadata.len())
+    }
+
+    fn read_range(&mut self, first: u64, last: u64) -> io::Result<Vec<u8>> {
+        try!(self.seek(SeekFrom::Start(first)));
+        let mut range = Vec::with_capacity((last - first + 1) as usize);
+        try!(self.by_ref().take(last - first + 1).read_to_end(&mut range));
+        if range.len() as u64 != last - first + 1 {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file was truncated"));
+        }
+        Ok(range)
+    }
+
+    fn into_body(self, first: u64, last: u64) -> io::Result<Body> {
+        Ok(Body::File(FileBody::range(self, first, last - first + 1)))
+    }
+}
+
+/// Sorts resolved ranges and merges those that overlap or are adjacent.
+fn coalesce(mut ranges: Vec<(u64, u64)>) -> Vec<(u64, u64)> {
+    ranges.sort();
+    let mut merged: Vec<(u64, u64)> = Vec::with_capacity(ranges.len());
+    for (first, last) in ranges {
+        if let Some(previous) = merged.last_mut() {
+            if first <= previous.1.saturating_add(1) {
+                previous.1 = cmp::max(previous.1, last);
+                continue;
+            }
+        }
+        merged.push((first, last));
+    }
+    merged
+}
+
+/// Sets the body of a `200 OK` response to the ranges requested by a `GET` request.
+///
+/// The `ETag`, `Last-Modified` and `Content-Type` headers of the response must already be
+/// set. Responses with another status are left unchanged. The whole representation is sent
+/// if the request has no valid `Range` header or the `If-Range` precondition fails.
+pub fn respond<S: RangeSource>(req: &Request,
+                               res: &mut Response,
+                               mut source: S)
+                               -> io::Result<()> {
+    if res.status != StatusCode::Ok {
+        return Ok(());
+    }
+    res.headers_mut().set("Accept-Ranges", b"bytes".to_vec());
+    let ranges = match req.get_value_header("Range").and_then(parse) {
+        Some(ref ranges) if req.method == Method::Get && ranges.len() <= MAX_RANGES &&
+                            if_range_matches(req,
+                                             res.get_value_header("ETag"),
+                                             res.get_value_header("Last-Modified")) => {
+            ranges.clone()
+        }
+        _ => {
+            let body = match try!(source.len()) {
+                0 => Body::Bytes(Vec::new()),
+                len => try!(source.into_body(0, len - 1)),
+            };
+            res.set_body(body);
+            return Ok(());
+        }
+    };
+    let len = try!(source.len());
+    let satisfiable = coalesce(ranges.iter().filter_map(|r| r.resolve(len)).collect());
+    if satisfiable.is_empty() {
+        res.status = StatusCode::RangeNotSatisfiable;
+        res.headers_mut().set("Content-Range", format!("bytes */{}", len).into_bytes());
+        res.put_body("");
+        return Ok(());
+    }
+    res.status = StatusCode::PartialContent;
+    if satisfiable.len() == 1 {
+        let (first, last) = satisfiable[0];
+        res.headers_mut()
+           .set("Content-Range", format!("bytes {}-{}/{}", first, last, len).into_bytes());
+        res.set_body(try!(source.into_body(first, last)));
+        return Ok(());
+    }
+    let boundary = format!("kinglet-byteranges-{:x}", time::precise_time_ns());
+    let content_type = res.headers_mut().remove("Content-Type");
+    let mut body = Vec::new();
+    for (first, last) in satisfiable {
+        body.extend(format!("\r\n--{}\r\n", boundary).as_bytes());
+        if let Some(ref content_type) = content_type {
+            body.extend(b"Content-Type: ");
+            body.extend(&content_type[0][..]);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("Content-Range: bytes {}-{}/{}\r\n\r\n", first, last, len)
+                        .as_bytes());
+        body.extend(try!(source.read_range(first, last)));
+    }
+    body.extend(format!("\r\n--{}--\r\n", boundary).as_bytes());
+    res.headers_mut().set("Content-Type",
+                          format!("multipart/byteranges; boundary={}", boundary).into_bytes());
+    res.set_body(Body::Bytes(body));
+    Ok(())
+}
+
+/// Applies the requested ranges to a response with a fixed size body.
+pub fn apply(req: &Request, res: &mut Response) -> io::Result<()> {
+    match res.take_body() {
+        Some(Body::Bytes(bytes)) => respond(req, res, &bytes[..]),
+        body => {
+            if let Some(body) = body {
+                res.set_body(body);
+            }
+            Ok(())
+        }
+    }
+}
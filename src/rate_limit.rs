@@ -0,0 +1,381 @@
+//! Per-client rate limiting.
+//!
+//! A `RateLimiter` assigns each request a key, by default the client IP address recorded by
+//! `server::Server` as `PeerAddr` extension, and allows a `Quota` of requests per key and time
+//! window. Requests over the quota are answered with `429 Too Many Requests` and a
+//! `Retry-After` header by the `Throttle` middleware. All counted responses carry the
+//! `RateLimit-Limit`, `RateLimit-Remaining` and `RateLimit-Reset` headers.
+//!
+//! Two algorithms are available: a token bucket allows bursts up to the quota and refills
+//! continuously, a sliding window counts requests in the last window, weighting the previous
+//! fixed window by its overlap.
+//!
+//! Requests without a key, for example without an API key header, are handled by the
+//! `Keyless` policy. By default they are counted by their client IP address and rejected
+//! without one, `Keyless::Allow` lets them through uncounted.
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use access_log::PeerAddr;
+use middleware::Middleware;
+use {Message, Request, Response, StatusCode};
+
+/// Idle buckets are removed after this many checks.
+const PRUNE_INTERVAL: usize = 1024;
+
+/// How requests are assigned to buckets.
+#[derive(Clone, Copy)]
+pub enum Key {
+    /// The IP address from the `PeerAddr` extension.
+    PeerIp,
+    /// The value of a header, for example an API key.
+    Header(&'static str),
+    /// A custom function, for example reading an authenticated user.
+    Custom(fn(&Request) -> Option<String>),
+}
+
+impl Key {
+    /// Returns the key of a request.
+    pub fn of(&self, req: &Request) -> Option<String> {
+        match *self {
+            Key::PeerIp => req.extensions().get::<PeerAddr>().map(|peer| peer.0.ip().to_string()),
+            Key::Header(name) => {
+                req.get_value_header(name)
+                   .map(|value| String::from_utf8_lossy(value).into_owned())
+            }
+            Key::Custom(f) => f(req),
+        }
+    }
+}
+
+/// What happens to requests without a key.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Keyless {
+    /// Count them by the IP address from the `PeerAddr` extension, reject them without one.
+    PeerIp,
+    /// Reject them.
+    Reject,
+    /// Let them through without counting them.
+    Allow,
+}
+
+/// The algorithm counting requests.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Algorithm {
+    TokenBucket,
+    SlidingWindow,
+}
+
+/// The number of requests allowed per window.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Quota {
+    pub limit: u32,
+    pub window: Duration,
+}
+
+impl Quota {
+    pub fn per_second(limit: u32) -> Quota {
+        Quota {
+            limit: limit,
+            window: Duration::from_secs(1),
+        }
+    }
+
+    pub fn per_minute(limit: u32) -> Quota {
+        Quota {
+            limit: limit,
+            window: Duration::from_secs(60),
+        }
+    }
+}
+
+/// The outcome of counting a request.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Decision {
+    pub allowed: bool,
+    pub limit: u32,
+    /// The requests left in the current window.
+    pub remaining: u32,
+    /// The time until the quota is fully available again.
+    pub reset: Duration,
+    /// The time until the next request is allowed, zero if it is allowed now.
+    pub retry_after: Duration,
+}
+
+impl Decision {
+    /// Sets the `RateLimit-*` headers, and `Retry-After` if the request was rejected.
+    pub fn set_headers(&self, res: &mut Response) {
+        let headers = res.headers_mut();
+        headers.set("RateLimit-Limit", self.limit.to_string().into_bytes());
+        headers.set("RateLimit-Remaining", self.remaining.to_string().into_bytes());
+        headers.set("RateLimit-Reset", ceil_secs(self.reset).to_string().into_bytes());
+        if !self.allowed {
+            headers.set("Retry-After", ceil_secs(self.retry_after).to_string().into_bytes());
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Bucket {
+    Tokens {
+        tokens: f64,
+        updated: Instant,
+    },
+    Window {
+        start: Instant,
+        previous: u32,
+        current: u32,
+    },
+}
+
+#[derive(Debug)]
+struct Buckets {
+    buckets: HashMap<String, Bucket>,
+    checks: usize,
+}
+
+/// Shared per-key counters enforcing a `Quota`.
+///
+/// Clones share the counters, so one limiter can protect all workers of a server.
+#[derive(Clone)]
+pub struct RateLimiter {
+    key: Key,
+    keyless: Keyless,
+    algorithm: Algorithm,
+    quota: Quota,
+    state: Arc<Mutex<Buckets>>,
+}
+
+impl RateLimiter {
+    /// Creates a token bucket limiter keyed by client IP address.
+    ///
+    /// # Panics
+    ///
+    /// If the limit or the window is zero.
+    pub fn new(quota: Quota) -> RateLimiter {
+        assert!(quota.limit > 0, "a quota needs a limit of at least one request");
+        assert!(quota.window != Duration::from_secs(0),
+                "a quota needs a non-empty window");
+        RateLimiter {
+            key: Key::PeerIp,
+            keyless: Keyless::PeerIp,
+            algorithm: Algorithm::TokenBucket,
+            quota: quota,
+            state: Arc::new(Mutex::new(Buckets {
+                buckets: HashMap::new(),
+                checks: 0,
+            })),
+        }
+    }
+
+    pub fn key(mut self, key: Key) -> Self {
+        self.key = key;
+        self
+    }
+
+    pub fn keyless(mut self, keyless: Keyless) -> Self {
+        self.keyless = keyless;
+        self
+    }
+
+    pub fn algorithm(mut self, algorithm: Algorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn quota(&self) -> &Quota {
+        &self.quota
+    }
+
+    /// Counts a request, returns `None` if the request has no key and `Keyless::Allow` is set.
+    ///
+    /// Rejected requests without a key are never allowed, their decision has no remaining
+    /// requests.
+    pub fn check(&self, req: &Request) -> Option<Decision> {
+        let key = self.key.of(req).or_else(|| {
+            match self.keyless {
+                Keyless::PeerIp => Key::PeerIp.of(req),
+                Keyless::Reject | Keyless::Allow => None,
+            }
+        });
+        match key {
+            Some(key) => Some(self.check_key(&key, Instant::now())),
+            None if self.keyless == Keyless::Allow => None,
+            None => {
+                Some(Decision {
+                    allowed: false,
+                    limit: self.quota.limit,
+                    remaining: 0,
+                    reset: self.quota.window,
+                    retry_after: self.quota.window,
+                })
+            }
+        }
+    }
+
+    /// Counts a request with the given key at the given time.
+    pub fn check_key(&self, key: &str, now: Instant) -> Decision {
+        let mut state = self.state.lock().unwrap();
+        state.checks += 1;
+        if state.checks % PRUNE_INTERVAL == 0 {
+            let quota = self.quota;
+            state.buckets.retain(|_, bucket| !is_idle(bucket, &quota, now));
+        }
+        let algorithm = self.algorithm;
+        let bucket = state.buckets.entry(key.to_owned()).or_insert_with(|| {
+            match algorithm {
+                Algorithm::TokenBucket => {
+                    Bucket::Tokens {
+                        tokens: self.quota.limit as f64,
+                        updated: now,
+                    }
+                }
+                Algorithm::SlidingWindow => {
+                    Bucket::Window {
+                        start: now,
+                        previous: 0,
+                        current: 0,
+                    }
+                }
+            }
+        });
+        match *bucket {
+            Bucket::Tokens { ref mut tokens, ref mut updated } => {
+                take_token(&self.quota, tokens, updated, now)
+            }
+            Bucket::Window { ref mut start, ref mut previous, ref mut current } => {
+                count_in_window(&self.quota, start, previous, current, now)
+            }
+        }
+    }
+
+    /// Creates a `429 Too Many Requests` response for a rejected request.
+    pub fn too_many_requests(&self, req: &Request, decision: &Decision) -> Response {
+        let mut res = Response::new(req.version);
+        res.status = StatusCode::TooManyRequests;
+        decision.set_headers(&mut res);
+        res.put_body("");
+        res
+    }
+}
+
+fn secs(duration: Duration) -> f64 {
+    duration.as_secs() as f64 + duration.subsec_nanos() as f64 / 1e9
+}
+
+fn duration(secs: f64) -> Duration {
+    let secs = secs.max(0.0);
+    Duration::new(secs.trunc() as u64, (secs.fract() * 1e9) as u32)
+}
+
+fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + if duration.subsec_nanos() > 0 { 1 } else { 0 }
+}
+
+fn is_idle(bucket: &Bucket, quota: &Quota, now: Instant) -> bool {
+    match *bucket {
+        Bucket::Tokens { updated, .. } => now.duration_since(updated) >= quota.window,
+        Bucket::Window { start, .. } => now.duration_since(start) >= quota.window * 2,
+    }
+}
+
+fn take_token(quota: &Quota,
+              tokens: &mut f64,
+              updated: &mut Instant,
+              now: Instant)
+              -> Decision {
+    let limit = quota.limit as f64;
+    let rate = limit / secs(quota.window);
+    let elapsed = secs(now.duration_since(*updated));
+    *tokens = (*tokens + elapsed * rate).min(limit);
+    *updated = now;
+    let allowed = *tokens >= 1.0;
+    if allowed {
+        *tokens -= 1.0;
+    }
+    Decision {
+        allowed: allowed,
+        limit: quota.limit,
+        remaining: *tokens as u32,
+        reset: duration((limit - *tokens) / rate),
+        retry_after: if allowed {
+            Duration::from_secs(0)
+        } else {
+            duration((1.0 - *tokens) / rate)
+        },
+    }
+}
+
+fn count_in_window(quota: &Quota,
+                   start: &mut Instant,
+                   previous: &mut u32,
+                   current: &mut u32,
+                   now: Instant)
+                   -> Decision {
+    let elapsed = now.duration_since(*start);
+    if elapsed >= quota.window * 2 {
+        *start = now;
+        *previous = 0;
+        *current = 0;
+    } else if elapsed >= quota.window {
+        *start += quota.window;
+        *previous = *current;
+        *current = 0;
+    }
+    let window = secs(quota.window);
+    let into_window = secs(now.duration_since(*start));
+    let weight = 1.0 - into_window / window;
+    let count = (*previous as f64 * weight).floor() as u32 + *current;
+    let allowed = count < quota.limit;
+    if allowed {
+        *current += 1;
+    }
+    let used = if allowed { count + 1 } else { count };
+    let retry_after = if allowed {
+        Duration::from_secs(0)
+    } else if *current >= quota.limit || *previous == 0 {
+        // Only the next window starts without the requests of this one.
+        duration(window - into_window)
+    } else {
+        // The weight of the previous window has to drop until a request fits.
+        let fits = (quota.limit - *current) as f64 / *previous as f64;
+        duration(window * (1.0 - fits) - into_window)
+    };
+    Decision {
+        allowed: allowed,
+        limit: quota.limit,
+        remaining: quota.limit.saturating_sub(used),
+        reset: duration(window - into_window),
+        retry_after: retry_after,
+    }
+}
+
+/// Access to the `RateLimiter` in the context.
+pub trait RateLimited {
+    fn rate_limiter(&self) -> &RateLimiter;
+}
+
+/// Middleware rejecting requests over the quota of their key.
+///
+/// Requests let through by `Keyless::Allow` are passed on without `RateLimit-*` headers.
+pub struct Throttle;
+
+impl<C: RateLimited> Middleware<C> for Throttle {
+    type State = Option<Decision>;
+
+    fn before(req: &mut Request, ctx: &mut C) -> Result<Option<Decision>, Response> {
+        match ctx.rate_limiter().check(req) {
+            Some(ref decision) if !decision.allowed => {
+                Err(ctx.rate_limiter().too_many_requests(req, decision))
+            }
+            decision => Ok(decision),
+        }
+    }
+
+    fn after(decision: Option<Decision>, res: &mut Response, _: &mut C) {
+        if let Some(decision) = decision {
+            decision.set_headers(res);
+        }
+    }
+}
@@ -1,15 +1,21 @@
+use std::ascii::AsciiExt;
 use std::str;
 
 use url::{ParseResult, Url};
 
-use Error::{InvalidVersion, InvalidMethod, InvalidMessage};
+use Error::{InvalidVersion, InvalidMethod, InvalidMessage, TooLarge, UnsupportedMediaType};
+use CookieJar;
+use Extensions;
 use Headers;
 use HttpVersion::{self, Http09, Http10, Http11, Http20};
 use Method;
 use Message;
+use Params;
+use multipart::{self, Collector, Limits, Parser, Part};
+use urlencoded::{self, MAX_FIELDS, MAX_FORM_SIZE};
 use httparse::{self, Header};
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Request {
     /// HTTP version used in the request.
     pub version: HttpVersion,
@@ -19,6 +25,8 @@ pub struct Request {
     authority: Option<String>,
     path: String,
     headers: Headers,
+    params: Params,
+    extensions: Extensions,
     pub body: Vec<u8>,
 }
 
@@ -39,10 +47,132 @@ impl Request {
             authority: None,
             path: try!(raw.path.ok_or(InvalidMessage)).to_owned(),
             headers: Headers::from_http1(raw.headers),
+            params: Params::new(),
+            extensions: Extensions::new(),
             body: Vec::new(),
         })
     }
 
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// Data attached to the request by middleware and handlers.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Parses the cookies sent with the request.
+    pub fn cookies(&self) -> CookieJar {
+        match self.get_header("Cookie") {
+            Some(values) => CookieJar::from_header(values),
+            None => CookieJar::new(),
+        }
+    }
+
+    /// Returns the request target as sent by the client.
+    pub fn target(&self) -> &str {
+        &self.path
+    }
+
+    /// Returns the path of the request target without query string and fragment.
+    ///
+    /// For a request target in absolute form the scheme and the authority are removed. The
+    /// path is not percent-decoded.
+    pub fn path(&self) -> &str {
+        let mut path = &self.path[..];
+        if !path.starts_with('/') {
+            if let Some(i) = path.find("://") {
+                let rest = &path[i + 3..];
+                path = match rest.find('/') {
+                    Some(j) => &rest[j..],
+                    None => "/",
+                };
+            }
+        }
+        match path.find(|c| c == '?' || c == '#') {
+            Some(i) => &path[..i],
+            None => path,
+        }
+    }
+
+    /// The parameters extracted from the path by the router.
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    pub fn params_mut(&mut self) -> &mut Params {
+        &mut self.params
+    }
+
+    /// Returns the raw query string without the leading `?`.
+    pub fn query_string(&self) -> Option<&str> {
+        let path = match self.path.find('#') {
+            Some(i) => &self.path[..i],
+            None => &self.path[..],
+        };
+        path.find('?').map(|i| &path[i + 1..])
+    }
+
+    /// Parses the query string into decoded name value pairs.
+    pub fn query(&self) -> ::Result<Params> {
+        match self.query_string() {
+            Some(query) => urlencoded::parse(query.as_bytes(), MAX_FIELDS),
+            None => Ok(Params::new()),
+        }
+    }
+
+    /// Parses an `application/x-www-form-urlencoded` body with the default limits.
+    pub fn form(&self) -> ::Result<Params> {
+        self.form_with_limits(MAX_FORM_SIZE, MAX_FIELDS)
+    }
+
+    /// Parses an `application/x-www-form-urlencoded` body.
+    ///
+    /// Fails with `Error::UnsupportedMediaType` if the body has a different content type and
+    /// with `Error::TooLarge` if the body or the number of fields exceeds the limits.
+    pub fn form_with_limits(&self, max_size: usize, max_fields: usize) -> ::Result<Params> {
+        let is_form = match self.content_type() {
+            Some(media_type) => {
+                media_type.value.eq_ignore_ascii_case(b"application/x-www-form-urlencoded")
+            }
+            None => false,
+        };
+        if !is_form {
+            return Err(UnsupportedMediaType);
+        }
+        if self.body.len() > max_size {
+            return Err(TooLarge);
+        }
+        urlencoded::parse(&self.body, max_fields)
+    }
+
+    /// Parses a `multipart/form-data` body with the default limits.
+    ///
+    /// Uploaded files are written to the system's temporary directory.
+    pub fn multipart(&self) -> ::Result<Vec<Part>> {
+        self.multipart_with(Limits::default(), Collector::new())
+    }
+
+    /// Parses a `multipart/form-data` body into the given collector.
+    ///
+    /// Only a body collected in `Request::body` is parsed. Bodies read by a
+    /// `multipart::Reader` while they were received are found in the `Parts` extension.
+    pub fn multipart_with(&self, limits: Limits, mut collector: Collector) -> ::Result<Vec<Part>> {
+        let mut parser = Parser::new(&try!(multipart::boundary(self)), limits);
+        try!(parser.feed(&self.body, &mut collector));
+        try!(parser.finish());
+        Ok(collector.into_parts())
+    }
+
     pub fn add_http1_headers(&mut self, raw: &[Header]) {
         for header in raw {
             self.headers.insert_http1_header(header);
@@ -71,6 +201,16 @@ impl Request {
     }
 }
 
+impl PartialEq for Request {
+    /// Compares everything but the extensions, their values can not be compared.
+    fn eq(&self, other: &Request) -> bool {
+        self.version == other.version && self.method == other.method &&
+        self.scheme == other.scheme && self.authority == other.authority &&
+        self.path == other.path && self.headers == other.headers &&
+        self.params == other.params && self.body == other.body
+    }
+}
+
 impl Message for Request {
     fn get_header(&self, name: &str) -> Option<&Vec<Vec<u8>>> {
         self.headers.get_vec(name)
@@ -1,6 +1,10 @@
-use std::io::{self, Write};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 
+use Extensions;
 use Headers;
+use body::{Body, CHUNK_SIZE, ChunkedWriter, FileBody, WriteBody};
+use SetCookie;
 use HttpVersion;
 use Message;
 use StatusCode;
@@ -12,7 +16,15 @@ pub struct Response {
     pub status: StatusCode,
     reason: Option<String>,
     headers: Headers,
-    body: Option<Vec<u8>>,
+    body: Option<Body>,
+    extensions: Extensions,
+}
+
+/// How the end of the body is indicated in the head.
+enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
 }
 
 impl Response {
@@ -22,35 +34,152 @@ impl Response {
             status: StatusCode::Ok,
             reason: None,
             headers: Headers::new(),
-            body: None
+            body: None,
+            extensions: Extensions::new(),
         }
     }
 
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    /// Data attached to the response by handlers and middleware, it is not sent.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Adds a `Set-Cookie` header field.
+    pub fn set_cookie(&mut self, cookie: &SetCookie) -> ::Result<()> {
+        try!(cookie.validate());
+        self.headers.append("Set-Cookie", cookie.to_string().into_bytes());
+        Ok(())
+    }
+
     pub fn put_body<B:AsRef<[u8]>>(&mut self, body: B) {
-        self.body = Some(body.as_ref().to_owned());
+        self.body = Some(Body::Bytes(body.as_ref().to_owned()));
+    }
+
+    /// Sets a body that is produced while the response is written.
+    pub fn put_stream<S: WriteBody + 'static>(&mut self, stream: S) {
+        self.body = Some(Body::Stream(Box::new(stream)));
+    }
+
+    pub fn set_body(&mut self, body: Body) {
+        self.body = Some(body);
+    }
+
+    pub fn body(&self) -> Option<&Body> {
+        self.body.as_ref()
+    }
+
+    pub fn take_body(&mut self) -> Option<Body> {
+        self.body.take()
+    }
+
+    /// Sends the contents of a file as the body.
+    pub fn put_file(&mut self, file: File) -> io::Result<()> {
+        self.body = Some(Body::File(try!(FileBody::new(file))));
+        Ok(())
     }
 
-    pub fn serialize<W: Write>(&self, mut w: &mut W) -> io::Result<()> {
+    /// Writes the response in HTTP/1 format.
+    ///
+    /// Fails without writing anything if a header field name or value is invalid, as writing it
+    /// could inject additional header fields or split the response. A streamed body can only be
+    /// written once, responses with one are written by `write_to`.
+    pub fn serialize<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        match self.body {
+            Some(Body::Bytes(ref body)) => {
+                try!(self.write_head(w, Framing::Length(body.len() as u64)));
+                w.write_all(&body[..])
+            }
+            Some(Body::File(ref file)) => {
+                try!(self.write_head(w, Framing::Length(file.len())));
+                file.copy_to(w)
+            }
+            Some(Body::Stream(_)) => {
+                Err(io::Error::new(io::ErrorKind::InvalidInput, "streamed body"))
+            }
+            None => self.write_head(w, Framing::Empty),
+        }
+    }
+
+    /// Writes the response like `serialize`, including a streamed body.
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        match try!(self.serialize_head(w)) {
+            Some(file) => file.copy_to(w),
+            None => Ok(()),
+        }
+    }
+
+    /// Writes the response like `serialize`, except for a file body.
+    ///
+    /// The file body is returned instead, so it can be sent with `FileBody::send_to` directly
+    /// to the socket once the head is flushed.
+    pub fn serialize_head<W: Write>(mut self, mut w: &mut W) -> io::Result<Option<FileBody>> {
+        if let Err(_) = self.headers.validate() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid header field"));
+        }
+        let body = match self.body.take() {
+            // Only HTTP/1.1 supports chunked transfer coding.
+            Some(Body::Stream(stream)) if self.version != HttpVersion::Http11 => {
+                Some(Body::Bytes(try!(Body::Stream(stream).into_bytes())))
+            }
+            body => body,
+        };
+        match body {
+            Some(Body::Bytes(body)) => {
+                try!(self.write_head(w, Framing::Length(body.len() as u64)));
+                try!(w.write_all(&body[..]));
+            }
+            Some(Body::Stream(stream)) => {
+                try!(self.write_head(w, Framing::Chunked));
+                let mut chunked = BufWriter::with_capacity(CHUNK_SIZE, ChunkedWriter::new(&mut *w));
+                try!(stream.write_body(&mut chunked));
+                match chunked.into_inner() {
+                    Ok(chunked) => try!(chunked.finish()),
+                    Err(err) => {
+                        return Err(io::Error::new(err.error().kind(), "failed to write chunk"))
+                    }
+                };
+            }
+            Some(Body::File(file)) => {
+                try!(self.write_head(w, Framing::Length(file.len())));
+                return Ok(Some(file));
+            }
+            None => try!(self.write_head(w, Framing::Empty)),
+        }
+        Ok(None)
+    }
+
+    fn write_head<W: Write>(&self, mut w: &mut W, framing: Framing) -> io::Result<()> {
+        if let Err(_) = self.headers.validate() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid header field"));
+        }
         // TODO: custom status.
         try!(write!(&mut w, "{} {}\r\n", self.version, self.status));
         if !self.contains_header("Date") {
             try!(write!(&mut w, "Date: {}\r\n", time::now().rfc822()));
         }
-        for (name, value) in self.headers.iter_all() {
-            for v in value {
-                try!(write!(&mut w, "{}: ", name));
-                try!(w.write_all(&v[..]));
-                try!(w.write_all(b"\r\n"));
-            }
-        }
-        if let Some(ref body) = self.body {
-            try!(write!(w, "Content-Length: {}\r\n", body.len()));
-            try!(w.write_all(b"\r\n"));
-            try!(w.write_all(&body[..]));
-        } else {
+        for (name, value) in self.headers.iter() {
+            try!(write!(&mut w, "{}: ", name));
+            try!(w.write_all(value));
             try!(w.write_all(b"\r\n"));
         }
-        Ok(())
+        match framing {
+            Framing::Empty => (),
+            Framing::Length(len) => try!(write!(w, "Content-Length: {}\r\n", len)),
+            Framing::Chunked => try!(w.write_all(b"Transfer-Encoding: chunked\r\n")),
+        }
+        w.write_all(b"\r\n")
     }
 }
 
@@ -0,0 +1,318 @@
+//! Dispatching requests by method and path.
+//!
+//! A `Router` maps path patterns to handler functions. Patterns consist of segments separated
+//! by slashes: literal segments, named parameters like `:id` matching a single segment and a
+//! final wildcard like `*path` matching the rest of the path. The decoded values are available
+//! with `Request::params`.
+//!
+//! As handlers have no state the router is kept in the context. Implement `Routing` for the
+//! context to use the router as the handler of the server.
+//!
+//! Routes added with `Router::route_reply` answer with a `Reply`, for example a response
+//! produced on a `pool::ThreadPool`.
+use std::fmt;
+
+use urlencoded::percent_decode;
+use {Body, Handler, Method, Params, Reply, Request, Response, StatusCode};
+
+/// A function handling the requests of a route.
+///
+/// The `request` function of any `Handler<C>` can be used.
+pub type RouteHandler<C> = fn(Request, &mut C) -> Response;
+
+/// A function replying to the requests of a route.
+///
+/// The `reply` function of any `Handler<C>` can be used.
+pub type ReplyHandler<C> = fn(Request, &mut C) -> Reply<C>;
+
+/// The function of a route.
+pub enum Endpoint<C> {
+    Request(RouteHandler<C>),
+    Reply(ReplyHandler<C>),
+}
+
+impl<C> Clone for Endpoint<C> {
+    fn clone(&self) -> Endpoint<C> {
+        *self
+    }
+}
+
+impl<C> Copy for Endpoint<C> {}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Literal(Vec<u8>),
+    Param(String),
+    Wildcard(String),
+}
+
+fn parse_pattern(pattern: &str) -> Vec<Segment> {
+    let mut segments = Vec::new();
+    for segment in pattern.split('/').filter(|s| !s.is_empty()) {
+        if let Some(&Segment::Wildcard(_)) = segments.last() {
+            panic!("a wildcard must be the last segment of pattern {:?}", pattern);
+        }
+        segments.push(if segment.starts_with(':') {
+            Segment::Param(segment[1..].to_owned())
+        } else if segment.starts_with('*') {
+            Segment::Wildcard(segment[1..].to_owned())
+        } else {
+            Segment::Literal(segment.as_bytes().to_vec())
+        });
+    }
+    segments
+}
+
+/// Matches the start of the path segments against a pattern.
+///
+/// Returns the number of matched segments. With `prefix` the path may have more segments.
+fn match_segments(pattern: &[Segment],
+                  path: &[&str],
+                  prefix: bool,
+                  params: &mut Params)
+                  -> Option<usize> {
+    for (i, segment) in pattern.iter().enumerate() {
+        match *segment {
+            Segment::Wildcard(ref name) => {
+                let rest = path[i..].join("/");
+                match String::from_utf8(percent_decode(rest.as_bytes())) {
+                    Ok(value) => params.push(name.clone(), value),
+                    Err(_) => return None,
+                }
+                return Some(path.len());
+            }
+            _ if i == path.len() => return None,
+            Segment::Literal(ref literal) => {
+                if percent_decode(path[i].as_bytes()) != *literal {
+                    return None;
+                }
+            }
+            Segment::Param(ref name) => {
+                match String::from_utf8(percent_decode(path[i].as_bytes())) {
+                    Ok(value) => params.push(name.clone(), value),
+                    Err(_) => return None,
+                }
+            }
+        }
+    }
+    if prefix || pattern.len() == path.len() {
+        Some(pattern.len())
+    } else {
+        None
+    }
+}
+
+struct Route<C> {
+    method: Method,
+    pattern: Vec<Segment>,
+    endpoint: Endpoint<C>,
+}
+
+/// The result of looking up a request in a router.
+pub enum Match<C> {
+    /// The function of the matching route and the path parameters.
+    Found(Endpoint<C>, Params),
+    /// Routes match the path but not the method. Contains the allowed methods.
+    MethodNotAllowed(Vec<Method>),
+    NotFound,
+}
+
+/// Routes requests to handler functions.
+pub struct Router<C> {
+    routes: Vec<Route<C>>,
+    mounts: Vec<(Vec<Segment>, Router<C>)>,
+}
+
+/// Access to the `Router` in the context.
+pub trait Routing: Sized {
+    fn router(&self) -> &Router<Self>;
+}
+
+impl<C> Router<C> {
+    pub fn new() -> Router<C> {
+        Router {
+            routes: Vec::new(),
+            mounts: Vec::new(),
+        }
+    }
+
+    /// Adds a route for a method and a path pattern.
+    ///
+    /// Routes are tried in the order they were added. `GET` routes also answer `HEAD`
+    /// requests without a body.
+    ///
+    /// # Panics
+    ///
+    /// If a wildcard is not the last segment of the pattern.
+    pub fn route(self, method: Method, pattern: &str, handler: RouteHandler<C>) -> Self {
+        self.add(method, pattern, Endpoint::Request(handler))
+    }
+
+    /// Adds a route answering with a `Reply`, like `route`.
+    ///
+    /// # Panics
+    ///
+    /// If a wildcard is not the last segment of the pattern.
+    pub fn route_reply(self, method: Method, pattern: &str, handler: ReplyHandler<C>) -> Self {
+        self.add(method, pattern, Endpoint::Reply(handler))
+    }
+
+    fn add(mut self, method: Method, pattern: &str, endpoint: Endpoint<C>) -> Self {
+        self.routes.push(Route {
+            method: method,
+            pattern: parse_pattern(pattern),
+            endpoint: endpoint,
+        });
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: RouteHandler<C>) -> Self {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: RouteHandler<C>) -> Self {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: RouteHandler<C>) -> Self {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: RouteHandler<C>) -> Self {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// Handles all paths starting with the prefix with another router.
+    ///
+    /// The routes of the sub-router match the rest of the path. The prefix may contain
+    /// parameters but no wildcard. Mounted routers are tried after the routes of this router.
+    ///
+    /// # Panics
+    ///
+    /// If the prefix contains a wildcard.
+    pub fn mount(mut self, prefix: &str, router: Router<C>) -> Self {
+        let prefix = parse_pattern(prefix);
+        if let Some(&Segment::Wildcard(_)) = prefix.last() {
+            panic!("a mount prefix can not contain a wildcard");
+        }
+        self.mounts.push((prefix, router));
+        self
+    }
+
+    /// Looks up the handler for a method and a path.
+    pub fn find(&self, method: &Method, path: &str) -> Match<C> {
+        let segments: Vec<&str> = path.split('/').filter(|s| !s.is_empty()).collect();
+        let mut allowed = Vec::new();
+        match self.find_segments(method, &segments, &mut allowed) {
+            Some((endpoint, params)) => Match::Found(endpoint, params),
+            None if allowed.is_empty() => Match::NotFound,
+            None => Match::MethodNotAllowed(allowed),
+        }
+    }
+
+    fn find_segments(&self,
+                     method: &Method,
+                     path: &[&str],
+                     allowed: &mut Vec<Method>)
+                     -> Option<(Endpoint<C>, Params)> {
+        for route in &self.routes {
+            let mut params = Params::new();
+            if match_segments(&route.pattern, path, false, &mut params).is_none() {
+                continue;
+            }
+            if route.method == *method ||
+               (route.method == Method::Get && *method == Method::Head) {
+                return Some((route.endpoint, params));
+            }
+            let mut methods = vec![route.method.clone()];
+            if route.method == Method::Get {
+                methods.push(Method::Head);
+            }
+            for method in methods {
+                if !allowed.contains(&method) {
+                    allowed.push(method);
+                }
+            }
+        }
+        for &(ref prefix, ref router) in &self.mounts {
+            let mut params = Params::new();
+            if let Some(len) = match_segments(prefix, path, true, &mut params) {
+                let found = router.find_segments(method, &path[len..], allowed);
+                if let Some((endpoint, inner)) = found {
+                    for &(ref name, ref value) in inner.iter() {
+                        params.push(name.clone(), value.clone());
+                    }
+                    return Some((endpoint, params));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<C> fmt::Debug for Router<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let routes: Vec<(&Method, &Vec<Segment>)> = self.routes
+                                                        .iter()
+                                                        .map(|r| (&r.method, &r.pattern))
+                                                        .collect();
+        f.debug_struct("Router")
+         .field("routes", &routes)
+         .field("mounts", &self.mounts)
+         .finish()
+    }
+}
+
+impl<C: Routing> Handler<C> for Router<C> {
+    /// Waits for the response of a route answering with a pending `Reply`.
+    fn request(req: Request, ctx: &mut C) -> Response {
+        Self::reply(req, ctx).wait(ctx)
+    }
+
+    fn reply(mut req: Request, ctx: &mut C) -> Reply<C> {
+        // The function is copied out of the router as it needs the context mutably.
+        let found = ctx.router().find(&req.method, req.path());
+        let res = match found {
+            Match::Found(endpoint, params) => {
+                *req.params_mut() = params;
+                let head = req.method == Method::Head;
+                let reply = match endpoint {
+                    Endpoint::Request(handler) => Reply::Ready(handler(req, ctx)),
+                    Endpoint::Reply(handler) => handler(req, ctx),
+                };
+                if head {
+                    return reply.map(ctx, |mut res, _| {
+                        strip_body(&mut res);
+                        res
+                    });
+                }
+                return reply;
+            }
+            Match::MethodNotAllowed(allowed) => {
+                let mut res = Response::new(req.version);
+                let allowed: Vec<String> = allowed.iter().map(|m| m.to_string()).collect();
+                res.headers_mut().set("Allow", allowed.join(", ").into_bytes());
+                res.status = StatusCode::MethodNotAllowed;
+                res.put_body("");
+                res
+            }
+            Match::NotFound => {
+                let mut res = Response::new(req.version);
+                res.status = StatusCode::NotFound;
+                res.put_body("");
+                res
+            }
+        };
+        Reply::Ready(res)
+    }
+}
+
+/// Removes the body of a response to a `HEAD` request but keeps its length.
+fn strip_body(res: &mut Response) {
+    let len = match res.take_body() {
+        Some(Body::Bytes(bytes)) => bytes.len() as u64,
+        Some(Body::File(file)) => file.len(),
+        _ => return,
+    };
+    res.headers_mut().set("Content-Length", len.to_string().into_bytes());
+}
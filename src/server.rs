@@ -0,0 +1,263 @@
+//! Servers running an event loop on each of several worker threads.
+//!
+//! Every worker has its own listener. With `SO_REUSEPORT` the kernel distributes incoming
+//! connections between the listeners, otherwise the workers share one socket and compete for
+//! connections.
+//!
+//! Handlers take the context by `&mut`, so instead of one context shared by all workers each
+//! worker creates its own context with a factory. Data shared by all workers is put into an
+//! `Arc` that is cloned into every context, `Server::run_shared` does so for a context that is
+//! entirely `Sync`.
+//!
+//! `Server::bind` creates the listeners without starting the workers, for example to learn the
+//! port chosen by the system when binding to port 0.
+//!
+//! If a worker fails the others are stopped without a grace period.
+use std::io;
+use std::net::{self, SocketAddr};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use mio::EventLoop;
+use mio::tcp::TcpListener;
+use net2::TcpBuilder;
+#[cfg(unix)]
+use net2::unix::UnixTcpBuilderExt;
+use num_cpus;
+
+use http1::Handler;
+use limits::Limiter;
+use shutdown::{self, Shutdown};
+use worker::Worker;
+
+/// The length of the queue of pending connections of each listener.
+const BACKLOG: i32 = 1024;
+
+/// Builder for a multi-threaded server.
+#[derive(Clone, Debug)]
+pub struct Server {
+    addr: SocketAddr,
+    workers: usize,
+    reuse_port: bool,
+    shutdown: Option<Shutdown>,
+    limiter: Option<Limiter>,
+}
+
+impl Server {
+    /// Listens on the address with one worker per CPU.
+    ///
+    /// `SO_REUSEPORT` is used on Unix systems.
+    pub fn new(addr: SocketAddr) -> Server {
+        Server {
+            addr: addr,
+            workers: num_cpus::get(),
+            reuse_port: cfg!(unix),
+            shutdown: None,
+            limiter: None,
+        }
+    }
+
+    /// Sets the number of worker threads.
+    ///
+    /// # Panics
+    ///
+    /// If the number is zero.
+    pub fn workers(mut self, workers: usize) -> Self {
+        assert!(workers > 0, "a server needs at least one worker");
+        self.workers = workers;
+        self
+    }
+
+    /// Binds a listener per worker with `SO_REUSEPORT` instead of sharing one listener.
+    pub fn reuse_port(mut self, reuse_port: bool) -> Self {
+        self.reuse_port = reuse_port;
+        self
+    }
+
+    /// Drains the workers when the shutdown begins and stops them once it is finished.
+    pub fn shutdown(mut self, shutdown: Shutdown) -> Self {
+        self.shutdown = Some(shutdown);
+        self
+    }
+
+    /// Counts the connections of all workers with the limiter.
+    ///
+    /// Connections over the limit per IP address are closed right away. While the limiter is
+    /// saturated the workers stop accepting, further connections wait in the backlog.
+    pub fn limiter(mut self, limiter: Limiter) -> Self {
+        self.limiter = Some(limiter);
+        self
+    }
+
+    /// Binds the listeners and runs the server until all workers stop.
+    ///
+    /// `context` is called on each worker thread with the number of the worker to create the
+    /// context of its event loop.
+    pub fn run<C, H, F>(self, context: F) -> io::Result<()>
+        where C: 'static,
+              H: Handler<C> + 'static,
+              F: Fn(usize) -> C + Send + Sync + 'static
+    {
+        try!(self.bind()).run::<C, H, F>(context)
+    }
+
+    /// Binds the listeners and runs the server with one context shared by all workers.
+    ///
+    /// Handlers receive the context in an `Arc`.
+    pub fn run_shared<C, H>(self, context: C) -> io::Result<()>
+        where C: Send + Sync + 'static,
+              H: Handler<Arc<C>> + 'static
+    {
+        try!(self.bind()).run_shared::<C, H>(context)
+    }
+
+    /// Binds a listener for each worker.
+    pub fn bind(self) -> io::Result<Bound> {
+        let mut listeners = Vec::with_capacity(self.workers);
+        let first = try!(bind(&self.addr, self.reuse_port));
+        // Bind the other listeners to the same port if the port was chosen by the system.
+        let addr = try!(first.local_addr());
+        listeners.push(first);
+        for _ in 1..self.workers {
+            let listener = if self.reuse_port {
+                try!(bind(&addr, true))
+            } else {
+                try!(listeners[0].try_clone())
+            };
+            listeners.push(listener);
+        }
+        Ok(Bound {
+            addr: addr,
+            listeners: listeners,
+            shutdown: self.shutdown,
+            limiter: self.limiter,
+        })
+    }
+}
+
+/// A server with bound listeners, created by `Server::bind`.
+#[derive(Debug)]
+pub struct Bound {
+    addr: SocketAddr,
+    listeners: Vec<net::TcpListener>,
+    shutdown: Option<Shutdown>,
+    limiter: Option<Limiter>,
+}
+
+impl Bound {
+    /// The address the listeners are bound to.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Runs the server until all workers stop, like `Server::run`.
+    pub fn run<C, H, F>(self, context: F) -> io::Result<()>
+        where C: 'static,
+              H: Handler<C> + 'static,
+              F: Fn(usize) -> C + Send + Sync + 'static
+    {
+        let listeners = self.listeners;
+        let context = Arc::new(context);
+        // Without a shutdown of the user the workers still need one to stop each other.
+        let shutdown = self.shutdown.unwrap_or_else(Shutdown::new);
+        let mut workers = Vec::with_capacity(listeners.len());
+        let mut result = Ok(());
+        for (i, listener) in listeners.into_iter().enumerate() {
+            let context = context.clone();
+            let shutdown = shutdown.clone();
+            let limiter = self.limiter.clone();
+            let worker = thread::Builder::new()
+                             .name(format!("kinglet-worker-{}", i))
+                             .spawn(move || {
+                                 let _stop = StopOnPanic(shutdown.clone());
+                                 let result = worker::<C, H>(listener,
+                                                             context(i),
+                                                             shutdown.clone(),
+                                                             limiter);
+                                 if result.is_err() {
+                                     shutdown.begin(Duration::from_secs(0));
+                                 }
+                                 result
+                             });
+            match worker {
+                Ok(worker) => workers.push(worker),
+                Err(err) => {
+                    shutdown.begin(Duration::from_secs(0));
+                    result = Err(err);
+                    break;
+                }
+            }
+        }
+        // All workers are joined, the first error is returned.
+        for worker in workers {
+            let joined = match worker.join() {
+                Ok(joined) => joined,
+                Err(_) => Err(io::Error::new(io::ErrorKind::Other, "worker panicked")),
+            };
+            if result.is_ok() {
+                result = joined;
+            }
+        }
+        result
+    }
+
+    /// Runs the server with one context shared by all workers, like `Server::run_shared`.
+    pub fn run_shared<C, H>(self, context: C) -> io::Result<()>
+        where C: Send + Sync + 'static,
+              H: Handler<Arc<C>> + 'static
+    {
+        let context = Arc::new(context);
+        self.run::<Arc<C>, H, _>(move |_| context.clone())
+    }
+}
+
+/// Stops the other workers if a worker panics.
+struct StopOnPanic(Shutdown);
+
+impl Drop for StopOnPanic {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.begin(Duration::from_secs(0));
+        }
+    }
+}
+
+fn bind(addr: &SocketAddr, reuse_port: bool) -> io::Result<net::TcpListener> {
+    let builder = try!(match *addr {
+        SocketAddr::V4(_) => TcpBuilder::new_v4(),
+        SocketAddr::V6(_) => TcpBuilder::new_v6(),
+    });
+    try!(builder.reuse_address(true));
+    if reuse_port {
+        try!(set_reuse_port(&builder));
+    }
+    try!(builder.bind(addr));
+    builder.listen(BACKLOG)
+}
+
+#[cfg(unix)]
+fn set_reuse_port(builder: &TcpBuilder) -> io::Result<()> {
+    builder.reuse_port(true).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn set_reuse_port(_: &TcpBuilder) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Other, "SO_REUSEPORT is not supported"))
+}
+
+fn worker<C, H: Handler<C>>(listener: net::TcpListener,
+                            context: C,
+                            shutdown: Shutdown,
+                            limiter: Option<Limiter>)
+                            -> io::Result<()> {
+    let addr = try!(listener.local_addr());
+    let listener = try!(TcpListener::from_listener(listener, &addr));
+    let mut event_loop = try!(EventLoop::new());
+    let mut worker = try!(Worker::<C, H>::new(listener,
+                                               context,
+                                               Some(shutdown.clone()),
+                                               limiter,
+                                               &mut event_loop));
+    shutdown::run(&mut event_loop, &mut worker, &shutdown)
+}
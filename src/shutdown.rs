@@ -0,0 +1,151 @@
+//! Graceful shutdown of a server.
+//!
+//! A `Shutdown` is triggered programmatically with `Shutdown::begin` or by `SIGTERM` and
+//! `SIGINT` after `Shutdown::handle_signals`. Once a `server::Server` drains, its workers stop
+//! accepting connections and close the idle ones. Connections with a request in progress,
+//! including requests whose body is still uploading, are served until their response is sent
+//! and are closed then. The workers send these responses with `Connection: close`, `Drain`
+//! does the same for handlers run by other servers.
+//!
+//! `run` drives the event loop until no request is in progress or the grace period is over,
+//! the remaining connections are closed when the event loop is dropped.
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering, ATOMIC_BOOL_INIT};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use mio;
+
+use middleware::Middleware;
+use {Request, Response};
+
+/// How often `run` checks the shutdown state.
+const POLL_MS: usize = 100;
+
+#[derive(Debug)]
+struct State {
+    draining: AtomicBool,
+    in_flight: AtomicUsize,
+    deadline: Mutex<Option<Instant>>,
+}
+
+/// A handle to trigger and observe the shutdown of a server.
+///
+/// Clones share the same state, keep one in the context and one to trigger the shutdown.
+#[derive(Clone, Debug)]
+pub struct Shutdown {
+    state: Arc<State>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown {
+            state: Arc::new(State {
+                draining: AtomicBool::new(false),
+                in_flight: AtomicUsize::new(0),
+                deadline: Mutex::new(None),
+            }),
+        }
+    }
+
+    /// Starts draining. Connections are closed at the latest after the grace period.
+    ///
+    /// Calling it again does not extend the deadline.
+    pub fn begin(&self, grace: Duration) {
+        let mut deadline = self.state.deadline.lock().unwrap();
+        if deadline.is_none() {
+            *deadline = Some(Instant::now() + grace);
+            self.state.draining.store(true, Ordering::SeqCst);
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.state.draining.load(Ordering::SeqCst)
+    }
+
+    /// The number of connections with a request in progress.
+    ///
+    /// A request is in progress from its first byte until its response is sent.
+    pub fn in_flight(&self) -> usize {
+        self.state.in_flight.load(Ordering::SeqCst)
+    }
+
+    /// Checks if the server may stop: it is draining and either idle or out of time.
+    pub fn is_finished(&self) -> bool {
+        if !self.is_draining() {
+            return false;
+        }
+        match *self.state.deadline.lock().unwrap() {
+            Some(deadline) if Instant::now() >= deadline => return true,
+            _ => (),
+        }
+        self.in_flight() == 0
+    }
+
+    /// Counts a request in progress, called by the server.
+    pub(crate) fn request_started(&self) {
+        self.state.in_flight.fetch_add(1, Ordering::SeqCst);
+    }
+
+    pub(crate) fn request_finished(&self) {
+        self.state.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+
+    /// Begins the shutdown when the process receives `SIGTERM` or `SIGINT`.
+    ///
+    /// A background thread watches for the signals. The handlers are installed for the whole
+    /// process.
+    #[cfg(unix)]
+    pub fn handle_signals(&self, grace: Duration) {
+        extern "C" fn on_signal(_: ::libc::c_int) {
+            SIGNALED.store(true, Ordering::SeqCst);
+        }
+        unsafe {
+            ::libc::signal(::libc::SIGTERM, on_signal as ::libc::sighandler_t);
+            ::libc::signal(::libc::SIGINT, on_signal as ::libc::sighandler_t);
+        }
+        let shutdown = self.clone();
+        thread::spawn(move || {
+            while !SIGNALED.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(POLL_MS as u64));
+            }
+            shutdown.begin(grace);
+        });
+    }
+}
+
+static SIGNALED: AtomicBool = ATOMIC_BOOL_INIT;
+
+/// Access to the `Shutdown` in the context.
+pub trait Draining {
+    fn shutdown(&self) -> &Shutdown;
+}
+
+/// Middleware announcing that connections are closed while the server drains.
+pub struct Drain;
+
+impl<C: Draining> Middleware<C> for Drain {
+    type State = ();
+
+    fn before(_: &mut Request, _: &mut C) -> Result<(), Response> {
+        Ok(())
+    }
+
+    fn after(_: (), res: &mut Response, ctx: &mut C) {
+        if ctx.shutdown().is_draining() {
+            res.headers_mut().set("Connection", b"close".to_vec());
+        }
+    }
+}
+
+/// Runs the event loop until the shutdown is finished.
+pub fn run<H: mio::Handler>(event_loop: &mut mio::EventLoop<H>,
+                            handler: &mut H,
+                            shutdown: &Shutdown)
+                            -> io::Result<()> {
+    while !shutdown.is_finished() {
+        try!(event_loop.run_once(handler, Some(POLL_MS)));
+    }
+    Ok(())
+}
@@ -0,0 +1,323 @@
+//! Serving files from a directory.
+//!
+//! `StaticFiles` maps request paths to files below a root directory. Put it into the context
+//! and implement `StaticRoot` to use the `ServeFiles` handler, or call `StaticFiles::serve`
+//! from another handler.
+use std::ascii::AsciiExt;
+use std::fs::{self, File};
+use std::io;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+use std::str;
+use std::time::UNIX_EPOCH;
+
+use conditional;
+use date;
+use range;
+use urlencoded::{percent_decode, percent_encode_path};
+use {Handler, Method, Request, Response, StatusCode};
+
+/// How symbolic links below the root directory are treated.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Symlinks {
+    /// Follow all symbolic links.
+    Follow,
+    /// Follow symbolic links if their target is inside of the root directory.
+    WithinRoot,
+    /// Never follow symbolic links.
+    Deny,
+}
+
+/// Configuration for serving files from a root directory.
+#[derive(Clone, Debug)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index_files: Vec<String>,
+    listings: bool,
+    symlinks: Symlinks,
+    hidden: bool,
+}
+
+/// Access to the `StaticFiles` configuration in the context.
+pub trait StaticRoot {
+    fn static_files(&self) -> &StaticFiles;
+}
+
+/// A handler serving files with the `StaticFiles` of the context.
+pub struct ServeFiles<C>(PhantomData<C>);
+
+impl<C: StaticRoot> Handler<C> for ServeFiles<C> {
+    fn request(req: Request, ctx: &mut C) -> Response {
+        ctx.static_files().serve(&req)
+    }
+}
+
+impl StaticFiles {
+    /// Serves files from the root directory.
+    ///
+    /// By default `index.html` is the index file, directory listings and hidden files are
+    /// disabled and only symbolic links within the root directory are followed.
+    pub fn new<P: Into<PathBuf>>(root: P) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index_files: vec!["index.html".to_owned()],
+            listings: false,
+            symlinks: Symlinks::WithinRoot,
+            hidden: false,
+        }
+    }
+
+    /// Sets the file names that are served for a directory, in order of preference.
+    pub fn index_files(mut self, index_files: Vec<String>) -> Self {
+        self.index_files = index_files;
+        self
+    }
+
+    /// Enables listings for directories without an index file.
+    pub fn listings(mut self, listings: bool) -> Self {
+        self.listings = listings;
+        self
+    }
+
+    pub fn symlinks(mut self, symlinks: Symlinks) -> Self {
+        self.symlinks = symlinks;
+        self
+    }
+
+    /// Allows serving files and directories with names starting with a dot.
+    pub fn hidden(mut self, hidden: bool) -> Self {
+        self.hidden = hidden;
+        self
+    }
+
+    /// Maps a request path to a file system path below the root.
+    ///
+    /// Returns `None` if the path is not allowed, for example because it contains a `..`
+    /// segment. The path is percent-decoded.
+    pub fn resolve(&self, request_path: &str) -> Option<PathBuf> {
+        let mut path = self.root.clone();
+        for segment in request_path.split('/') {
+            let segment = percent_decode(segment.as_bytes());
+            if segment.is_empty() || segment == b"." {
+                continue;
+            }
+            if segment == b".." || (segment[0] == b'.' && !self.hidden) ||
+               segment.iter().any(|b| *b == b'/' || *b == b'\\' || *b == 0) {
+                return None;
+            }
+            match str::from_utf8(&segment) {
+                Ok(segment) => path.push(segment),
+                Err(_) => return None,
+            }
+        }
+        Some(path)
+    }
+
+    /// Checks the symbolic links on the way from the root to the path.
+    fn symlinks_allowed(&self, path: &Path) -> bool {
+        match self.symlinks {
+            Symlinks::Follow => true,
+            Symlinks::WithinRoot => {
+                match (fs::canonicalize(&self.root), fs::canonicalize(path)) {
+                    (Ok(root), Ok(path)) => path.starts_with(root),
+                    _ => false,
+                }
+            }
+            Symlinks::Deny => {
+                let relative = match path.strip_prefix(&self.root) {
+                    Ok(relative) => relative,
+                    Err(_) => return false,
+                };
+                let mut current = self.root.clone();
+                for component in relative.components() {
+                    current.push(component.as_os_str());
+                    match fs::symlink_metadata(&current) {
+                        Ok(ref metadata) if !metadata.file_type().is_symlink() => (),
+                        _ => return false,
+                    }
+                }
+                true
+            }
+        }
+    }
+
+    /// Answers a `GET` or `HEAD` request with a file, an index file or a directory listing.
+    pub fn serve(&self, req: &Request) -> Response {
+        let mut res = Response::new(req.version);
+        if req.method != Method::Get && req.method != Method::Head {
+            res.headers_mut().set("Allow", b"GET, HEAD".to_vec());
+            return status(res, StatusCode::MethodNotAllowed);
+        }
+        let request_path = req.path();
+        let mut path = match self.resolve(request_path) {
+            Some(path) => path,
+            None => return status(res, StatusCode::NotFound),
+        };
+        if !path.exists() || !self.symlinks_allowed(&path) {
+            return status(res, StatusCode::NotFound);
+        }
+        if path.is_dir() {
+            if !request_path.ends_with('/') {
+                // Leading slashes are collapsed, `//host/` would redirect to another host.
+                let location = format!("/{}/", request_path.trim_left_matches('/'));
+                res.headers_mut().set("Location", location.into_bytes());
+                return status(res, StatusCode::MovedPermanently);
+            }
+            match self.index_files.iter().map(|name| path.join(name)).find(|p| p.is_file()) {
+                Some(index) => {
+                    if !self.symlinks_allowed(&index) {
+                        return status(res, StatusCode::NotFound);
+                    }
+                    path = index;
+                }
+                None if self.listings => {
+                    return match listing(&path, request_path, self.hidden) {
+                        Ok(html) => {
+                            res.headers_mut()
+                               .set("Content-Type", b"text/html; charset=utf-8".to_vec());
+                            body(res, req, html.into_bytes())
+                        }
+                        Err(_) => status(res, StatusCode::InternalServerError),
+                    };
+                }
+                None => return status(res, StatusCode::NotFound),
+            }
+        }
+        match serve_file(req, &mut res, &path) {
+            Ok(()) => res,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                status(res, StatusCode::NotFound)
+            }
+            Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                status(res, StatusCode::Forbidden)
+            }
+            Err(_) => status(res, StatusCode::InternalServerError),
+        }
+    }
+}
+
+fn status(mut res: Response, status: StatusCode) -> Response {
+    res.status = status;
+    res.put_body("");
+    res
+}
+
+/// Sets the body, or only the `Content-Length` for `HEAD` requests.
+fn body(mut res: Response, req: &Request, contents: Vec<u8>) -> Response {
+    if req.method == Method::Head {
+        res.headers_mut().set("Content-Length", contents.len().to_string().into_bytes());
+    } else {
+        res.put_body(contents);
+    }
+    res
+}
+
+/// Sets the `Content-Type`, `Last-Modified` and `ETag` headers, evaluates the preconditions
+/// and sets the requested ranges of the file as the body.
+fn serve_file(req: &Request, res: &mut Response, path: &Path) -> io::Result<()> {
+    let file = try!(File::open(path));
+    let metadata = try!(file.metadata());
+    res.headers_mut().set("Content-Type", content_type(path).as_bytes().to_vec());
+    if let Ok(modified) = metadata.modified() {
+        res.headers_mut().set("Last-Modified", date::format(modified).into_bytes());
+        if let Ok(duration) = modified.duration_since(UNIX_EPOCH) {
+            let etag = format!("\"{:x}-{:x}\"", duration.as_secs(), metadata.len());
+            res.headers_mut().set("ETag", etag.into_bytes());
+        }
+    }
+    if conditional::apply(req, res) {
+        return Ok(());
+    }
+    if req.method == Method::Head {
+        res.headers_mut().set("Accept-Ranges", b"bytes".to_vec());
+        res.headers_mut().set("Content-Length", metadata.len().to_string().into_bytes());
+        return Ok(());
+    }
+    range::respond(req, res, file)
+}
+
+/// Guesses the media type of a file from its extension.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => extension.to_ascii_lowercase(),
+        None => return "application/octet-stream",
+    };
+    match &extension[..] {
+        "html" | "htm" => "text/html; charset=utf-8",
+        "css" => "text/css; charset=utf-8",
+        "js" => "application/javascript; charset=utf-8",
+        "json" => "application/json",
+        "txt" => "text/plain; charset=utf-8",
+        "md" => "text/markdown; charset=utf-8",
+        "csv" => "text/csv; charset=utf-8",
+        "xml" => "application/xml",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "ico" => "image/x-icon",
+        "pdf" => "application/pdf",
+        "zip" => "application/zip",
+        "gz" => "application/gzip",
+        "wasm" => "application/wasm",
+        "mp3" => "audio/mpeg",
+        "ogg" => "audio/ogg",
+        "mp4" => "video/mp4",
+        "webm" => "video/webm",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        "ttf" => "font/ttf",
+        _ => "application/octet-stream",
+    }
+}
+
+fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Creates an HTML page listing the entries of a directory sorted by name.
+fn listing(dir: &Path, request_path: &str, hidden: bool) -> io::Result<String> {
+    let mut entries = Vec::new();
+    for entry in try!(fs::read_dir(dir)) {
+        let entry = try!(entry);
+        let mut name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if name.starts_with('.') && !hidden {
+            continue;
+        }
+        if try!(entry.file_type()).is_dir() {
+            name.push('/');
+        }
+        entries.push(name);
+    }
+    entries.sort();
+    let title = escape_html(&String::from_utf8_lossy(&percent_decode(request_path.as_bytes())));
+    let mut html = format!("<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\">\
+                            <title>Index of {0}</title></head>\n<body>\n<h1>Index of {0}</h1>\n\
+                            <ul>\n",
+                           title);
+    if request_path != "/" {
+        html.push_str("<li><a href=\"../\">../</a></li>\n");
+    }
+    for name in entries {
+        html.push_str(&format!("<li><a href=\"{}\">{}</a></li>\n",
+                               percent_encode_path(name.as_bytes()),
+                               escape_html(&name)));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    Ok(html)
+}
@@ -0,0 +1,137 @@
+//! Parser for `application/x-www-form-urlencoded` data.
+//!
+//! The format is used for query strings and for the bodies of HTML form submissions.
+use std::slice;
+use std::str;
+
+use Error::TooLarge;
+
+/// The maximum size of a form body in bytes.
+pub const MAX_FORM_SIZE: usize = 2_097_152;
+/// The maximum number of fields in a query string or a form body.
+///
+/// Parsing is linear, but consumers often put the fields into maps.
+pub const MAX_FIELDS: usize = 1024;
+
+/// Multi-valued, percent-decoded name value pairs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Params {
+    pairs: Vec<(String, String)>,
+}
+
+impl Params {
+    pub fn new() -> Self {
+        Params { pairs: Vec::new() }
+    }
+
+    /// Returns the first value of the field with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.pairs.iter().find(|&&(ref n, _)| n == name).map(|&(_, ref v)| &v[..])
+    }
+
+    /// Returns all values of the field with the given name.
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        self.pairs.iter().filter(|&&(ref n, _)| n == name).map(|&(_, ref v)| &v[..]).collect()
+    }
+
+    /// Appends a pair.
+    pub fn push(&mut self, name: String, value: String) {
+        self.pairs.push((name, value));
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Iterates over the pairs in the order they appeared in the input.
+    pub fn iter(&self) -> slice::Iter<(String, String)> {
+        self.pairs.iter()
+    }
+}
+
+/// Parses urlencoded input.
+///
+/// Empty pairs are skipped, a pair without `=` has an empty value. Fails with
+/// `Error::TooLarge` if there are more than `max_fields` pairs or with `Error::Utf8Error` if a
+/// decoded name or value is not valid UTF-8.
+pub fn parse(input: &[u8], max_fields: usize) -> ::Result<Params> {
+    let mut params = Params::new();
+    for pair in input.split(|b| *b == b'&') {
+        if pair.is_empty() {
+            continue;
+        }
+        if params.pairs.len() == max_fields {
+            return Err(TooLarge);
+        }
+        let (name, value) = match pair.iter().position(|b| *b == b'=') {
+            Some(i) => (&pair[..i], &pair[i + 1..]),
+            None => (pair, &b""[..]),
+        };
+        params.pairs.push((try!(decode(name)), try!(decode(value))));
+    }
+    Ok(params)
+}
+
+/// Percent-decodes a name or a value and replaces `+` with a space.
+///
+/// Invalid percent escapes are kept as they are.
+pub fn decode(input: &[u8]) -> ::Result<String> {
+    let plus_decoded: Vec<u8> = input.iter().map(|&b| if b == b'+' { b' ' } else { b }).collect();
+    Ok(try!(str::from_utf8(&percent_decode(&plus_decoded))).to_owned())
+}
+
+/// Decodes percent escapes like `%2F`.
+///
+/// Unlike `decode` a `+` is kept, as it is in URL paths. Invalid escapes are kept as they are.
+pub fn percent_decode(input: &[u8]) -> Vec<u8> {
+    fn hex(byte: u8) -> Option<u8> {
+        match byte {
+            b'0'...b'9' => Some(byte - b'0'),
+            b'a'...b'f' => Some(byte - b'a' + 10),
+            b'A'...b'F' => Some(byte - b'A' + 10),
+            _ => None,
+        }
+    }
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'%' if i + 2 < input.len() => {
+                match (hex(input[i + 1]), hex(input[i + 2])) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high << 4 | low);
+                        i += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    decoded
+}
+
+/// Percent-encodes all bytes except unreserved characters and `/`.
+///
+/// The result can be used as path of a URL.
+pub fn percent_encode_path(input: &[u8]) -> String {
+    let mut encoded = String::with_capacity(input.len());
+    for &byte in input {
+        match byte {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
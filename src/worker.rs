@@ -0,0 +1,438 @@
+//! The event loop of a server worker.
+//!
+//! Rotor's stream transport only passes buffers to the protocol, so a worker drives the
+//! sockets itself: it reads requests into the input buffer of a connection, lets its
+//! `http1::Client` write the responses to the output buffer and flushes it. File bodies are
+//! sent with `FileBody::send_to` once the head is flushed, resuming whenever the socket is
+//! writable again. Streamed bodies are written on their own thread and the worker copies their
+//! chunks to the output buffer as it is flushed. A connection waiting for a response from a
+//! thread pool or for the next chunk of a body is resumed when the event loop is notified with
+//! the token of the connection. While the notify queue of the event loop is full the wake-up
+//! is retried every `NOTIFY_RETRY_MS` milliseconds.
+//!
+//! A request is in progress from its first byte until its response is sent. With a `Shutdown`
+//! the worker counts the connections with a request in progress. Once the shutdown begins it
+//! stops accepting, closes the idle connections and closes the others as soon as their last
+//! response is sent. These responses are sent with `Connection: close`.
+//!
+//! With a `Limiter` every connection holds a `ConnectionGuard`, and a `RequestGuard` while a
+//! request is in progress. The worker deregisters the listener while the limiter is saturated
+//! and checks every `RESUME_MS` milliseconds if it may accept again.
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use std::thread;
+use std::time::Duration;
+
+use mio::{EventLoop, EventSet, NotifyError, PollOpt, Sender, Token};
+use mio::tcp::{TcpListener, TcpStream};
+use netbuf::Buf;
+use rotor::async::Async;
+use rotor::transports::stream::Transport;
+
+use http1::{Client, Handler};
+use limits::{ConnectionGuard, Limiter, RequestGuard};
+use shutdown::Shutdown;
+
+const LISTENER: Token = Token(0);
+
+/// How often a worker checks if a saturated limiter allows new connections.
+const RESUME_MS: u64 = 100;
+
+/// How long a wake-up waits before it retries to notify an event loop with a full queue.
+const NOTIFY_RETRY_MS: u64 = 1;
+
+/// Notifies the event loop that a connection can be resumed.
+///
+/// A lost notification would leave the connection waiting forever, so it is retried until the
+/// event loop takes it or stops.
+fn notify(sender: &Sender<Token>, mut token: Token) {
+    loop {
+        match sender.send(token) {
+            Err(NotifyError::Full(retry)) => {
+                token = retry;
+                thread::sleep(Duration::from_millis(NOTIFY_RETRY_MS));
+            }
+            _ => return,
+        }
+    }
+}
+
+/// What a connection waits for.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Next {
+    Read,
+    Write,
+    Close,
+}
+
+struct Connection<C, H: Handler<C>> {
+    token: Token,
+    socket: TcpStream,
+    peer: SocketAddr,
+    input: Buf,
+    output: Buf,
+    /// `None` once the client stopped, the connection is closed when the output is flushed.
+    client: Option<Client<C, H>>,
+    /// The peer will not send more data.
+    eof: bool,
+    /// A request is in progress and counted by the shutdown and the limiter.
+    busy: bool,
+    /// Releases the request in progress in the limiter.
+    request: Option<RequestGuard>,
+    /// Releases the connection in the limiter when closed.
+    _guard: Option<ConnectionGuard>,
+}
+
+impl<C, H: Handler<C>> Connection<C, H> {
+    fn new(token: Token,
+           socket: TcpStream,
+           peer: SocketAddr,
+           guard: Option<ConnectionGuard>)
+           -> Connection<C, H> {
+        Connection {
+            token: token,
+            socket: socket,
+            peer: peer,
+            input: Buf::new(),
+            output: Buf::new(),
+            client: Some(Client::Initial),
+            eof: false,
+            busy: false,
+            request: None,
+            _guard: guard,
+        }
+    }
+
+    /// Counts the start or the end of a request with the shutdown and the limiter.
+    fn set_busy(&mut self, busy: bool, shutdown: Option<&Shutdown>, limiter: Option<&Limiter>) {
+        if busy == self.busy {
+            return;
+        }
+        self.busy = busy;
+        if let Some(shutdown) = shutdown {
+            if busy {
+                shutdown.request_started();
+            } else {
+                shutdown.request_finished();
+            }
+        }
+        self.request = if busy { limiter.map(Limiter::count_request) } else { None };
+    }
+
+    /// Checks if no request is in progress and nothing is left to send.
+    fn is_idle(&self) -> bool {
+        self.input.empty() && self.output.empty() &&
+        self.client.as_ref().map_or(false, Client::is_idle)
+    }
+
+    /// Reads everything the socket has.
+    fn read(&mut self) -> io::Result<()> {
+        while !self.eof {
+            match self.input.read_from(&mut self.socket) {
+                Ok(0) => self.eof = true,
+                Ok(_) => (),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the output buffer, returns `true` once it is empty.
+    fn flush(&mut self) -> io::Result<bool> {
+        while !self.output.empty() {
+            match self.output.write_to(&mut self.socket) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "connection closed")),
+                Ok(_) => (),
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Passes the input to the client and sends its responses as far as the socket allows.
+    ///
+    /// With `close` the responses announce that the connection is closed.
+    fn process(&mut self, ctx: &mut C, sender: &Sender<Token>, close: bool) -> io::Result<Next> {
+        loop {
+            if !try!(self.flush()) {
+                return Ok(Next::Write);
+            }
+            let mut client = match self.client.take() {
+                Some(client) => client,
+                None => return Ok(Next::Close),
+            };
+            if let Client::Sending(mut file, close) = client {
+                match file.send_to(&mut self.socket) {
+                    Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
+                    Err(err) => return Err(err),
+                    Ok(_) => (),
+                }
+                if !file.is_empty() {
+                    self.client = Some(Client::Sending(file, close));
+                    return Ok(Next::Write);
+                }
+                client = if close { Client::Closing } else { Client::KeepAlive };
+            }
+            if let Client::Streaming(mut stream, close) = client {
+                if !try!(stream.poll_to(&mut self.output)) {
+                    if self.output.empty() {
+                        let (sender, token) = (sender.clone(), self.token);
+                        stream.on_ready(move || notify(&sender, token));
+                        self.client = Some(Client::Streaming(stream, close));
+                        return Ok(Next::Read);
+                    }
+                    // The chunks are flushed before more are taken.
+                    self.client = Some(Client::Streaming(stream, close));
+                    continue;
+                }
+                client = if close { Client::Closing } else { Client::KeepAlive };
+            }
+            // A closing client is resumed to stop once its output is flushed, a waiting client
+            // to take its response.
+            let resume = match client {
+                Client::Closing | Client::Waiting(..) => true,
+                _ => !self.input.empty(),
+            };
+            if !resume {
+                self.client = Some(client);
+                return Ok(if self.eof { Next::Close } else { Next::Read });
+            }
+            let result = {
+                let mut transport = Transport::new(&mut self.input, &mut self.output);
+                client.data_received_with(&mut transport, ctx, Some(self.peer), close)
+            };
+            match result {
+                Async::Continue(client, ()) => {
+                    // Sending a body continues once the head is flushed, closing once all
+                    // output is.
+                    let again = match client {
+                        Client::Sending(..) | Client::Streaming(..) | Client::Closing => true,
+                        _ => false,
+                    };
+                    // The response is sent even if the client finished sending.
+                    let waiting = match client {
+                        Client::Waiting(ref pending, _) => {
+                            let (sender, token) = (sender.clone(), self.token);
+                            pending.on_ready(move || notify(&sender, token));
+                            true
+                        }
+                        _ => false,
+                    };
+                    self.client = Some(client);
+                    if !again {
+                        if !try!(self.flush()) {
+                            return Ok(Next::Write);
+                        }
+                        return Ok(if self.eof && !waiting { Next::Close } else { Next::Read });
+                    }
+                }
+                _ => self.client = None,
+            }
+        }
+    }
+}
+
+/// A mio handler serving HTTP/1 connections accepted from a listener.
+pub struct Worker<C, H: Handler<C>> {
+    listener: TcpListener,
+    context: C,
+    connections: HashMap<usize, Connection<C, H>>,
+    next_token: usize,
+    /// Notifies the event loop that the response of a connection is ready.
+    sender: Sender<Token>,
+    shutdown: Option<Shutdown>,
+    /// The listener is deregistered and idle connections are closed.
+    draining: bool,
+    limiter: Option<Limiter>,
+    /// The listener is deregistered until the limiter allows new connections.
+    paused: bool,
+}
+
+impl<C, H: Handler<C>> Worker<C, H> {
+    pub fn new(listener: TcpListener,
+               context: C,
+               shutdown: Option<Shutdown>,
+               limiter: Option<Limiter>,
+               event_loop: &mut EventLoop<Self>)
+               -> io::Result<Worker<C, H>> {
+        try!(event_loop.register(&listener, LISTENER, EventSet::readable(), PollOpt::level()));
+        Ok(Worker {
+            listener: listener,
+            context: context,
+            connections: HashMap::new(),
+            next_token: 1,
+            sender: event_loop.channel(),
+            shutdown: shutdown,
+            draining: false,
+            limiter: limiter,
+            paused: false,
+        })
+    }
+
+    fn accept(&mut self, event_loop: &mut EventLoop<Self>) {
+        loop {
+            if self.limiter.as_ref().map_or(false, Limiter::is_saturated) {
+                self.pause(event_loop);
+                return;
+            }
+            let (socket, peer) = match self.listener.accept() {
+                Ok(Some(accepted)) => accepted,
+                // Errors like a full file table are not fatal, retry on the next event.
+                Ok(None) | Err(_) => return,
+            };
+            // Connections over the limit are closed by dropping the socket.
+            let guard = match self.limiter {
+                Some(ref limiter) => {
+                    match limiter.connect(Some(peer.ip())) {
+                        Ok(guard) => Some(guard),
+                        Err(_) => continue,
+                    }
+                }
+                None => None,
+            };
+            let token = Token(self.next_token);
+            self.next_token += 1;
+            let interest = EventSet::readable() | EventSet::hup();
+            if event_loop.register(&socket, token, interest, PollOpt::level()).is_ok() {
+                self.connections.insert(token.0, Connection::new(token, socket, peer, guard));
+            }
+        }
+    }
+
+    /// Processes a connection and registers it for the events it waits for.
+    fn update(&mut self, event_loop: &mut EventLoop<Self>, token: Token, read: bool) {
+        let draining = self.shutdown.as_ref().map_or(false, Shutdown::is_draining);
+        let next = match self.connections.get_mut(&token.0) {
+            Some(conn) => {
+                let read = if read { conn.read() } else { Ok(()) };
+                match read {
+                    Ok(()) => {
+                        // The request is counted before it is dispatched.
+                        if !conn.input.empty() {
+                            conn.set_busy(true, self.shutdown.as_ref(), self.limiter.as_ref());
+                        }
+                        conn.process(&mut self.context, &self.sender, draining)
+                            .unwrap_or(Next::Close)
+                    }
+                    Err(_) => Next::Close,
+                }
+            }
+            None => return,
+        };
+        let idle = {
+            let conn = self.connections.get_mut(&token.0).unwrap();
+            let idle = conn.is_idle();
+            conn.set_busy(!idle, self.shutdown.as_ref(), self.limiter.as_ref());
+            idle
+        };
+        if next == Next::Close || (self.draining && idle) {
+            self.close(event_loop, token);
+            return;
+        }
+        let registered = {
+            let conn = &self.connections[&token.0];
+            // Hang-ups are level-triggered as well, so they are only watched until the end of
+            // the input.
+            let mut interest = EventSet::none();
+            if !conn.eof {
+                interest = interest | EventSet::readable() | EventSet::hup();
+            }
+            if next == Next::Write {
+                interest = interest | EventSet::writable();
+            }
+            event_loop.reregister(&conn.socket, token, interest, PollOpt::level())
+        };
+        if registered.is_err() {
+            self.close(event_loop, token);
+        }
+    }
+
+    fn close(&mut self, event_loop: &mut EventLoop<Self>, token: Token) {
+        if let Some(conn) = self.connections.remove(&token.0) {
+            let _ = event_loop.deregister(&conn.socket);
+            if conn.busy {
+                if let Some(ref shutdown) = self.shutdown {
+                    shutdown.request_finished();
+                }
+            }
+        }
+        self.resume(event_loop);
+    }
+
+    /// Stops accepting until the limiter allows new connections.
+    fn pause(&mut self, event_loop: &mut EventLoop<Self>) {
+        if !self.paused {
+            self.paused = true;
+            let _ = event_loop.deregister(&self.listener);
+            let _ = event_loop.timeout_ms((), RESUME_MS);
+        }
+    }
+
+    /// Accepts again if the limiter allows it, returns `false` if the worker is still paused.
+    fn resume(&mut self, event_loop: &mut EventLoop<Self>) -> bool {
+        if !self.paused || self.draining {
+            return true;
+        }
+        if self.limiter.as_ref().map_or(false, Limiter::is_saturated) {
+            return false;
+        }
+        let interest = EventSet::readable();
+        if event_loop.register(&self.listener, LISTENER, interest, PollOpt::level()).is_err() {
+            return false;
+        }
+        self.paused = false;
+        true
+    }
+
+    /// Stops accepting and closes the idle connections once the shutdown begins.
+    fn drain(&mut self, event_loop: &mut EventLoop<Self>) {
+        let draining = self.shutdown.as_ref().map_or(false, Shutdown::is_draining);
+        if self.draining || !draining {
+            return;
+        }
+        self.draining = true;
+        let _ = event_loop.deregister(&self.listener);
+        let idle: Vec<usize> = self.connections
+                                   .iter()
+                                   .filter(|&(_, conn)| conn.is_idle())
+                                   .map(|(&token, _)| token)
+                                   .collect();
+        for token in idle {
+            self.close(event_loop, Token(token));
+        }
+    }
+}
+
+impl<C, H: Handler<C>> ::mio::Handler for Worker<C, H> {
+    type Timeout = ();
+    type Message = Token;
+
+    fn ready(&mut self, event_loop: &mut EventLoop<Self>, token: Token, events: EventSet) {
+        if token == LISTENER {
+            self.accept(event_loop);
+        } else if events.is_error() {
+            self.close(event_loop, token);
+        } else {
+            self.update(event_loop, token, events.is_readable() || events.is_hup());
+        }
+    }
+
+    fn timeout(&mut self, event_loop: &mut EventLoop<Self>, _: ()) {
+        if !self.resume(event_loop) {
+            let _ = event_loop.timeout_ms((), RESUME_MS);
+        }
+    }
+
+    fn notify(&mut self, event_loop: &mut EventLoop<Self>, token: Token) {
+        self.update(event_loop, token, false);
+    }
+
+    fn tick(&mut self, event_loop: &mut EventLoop<Self>) {
+        self.drain(event_loop);
+    }
+}
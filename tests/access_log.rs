@@ -0,0 +1,137 @@
+extern crate httparse;
+extern crate kinglet;
+extern crate netbuf;
+extern crate rotor;
+extern crate time;
+
+mod common;
+
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use kinglet::{Handler, Request, Response};
+use kinglet::http1::Client;
+use netbuf::Buf;
+use rotor::async::Async;
+use rotor::transports::stream::Transport;
+use kinglet::access_log::{AccessLog, AccessLogs, Entry, Format, LogRequests, PeerAddr};
+use kinglet::middleware::Chain;
+use common::request;
+
+/// A sink shared with the test.
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn entry() -> Entry {
+    let mut req = request(b"GET /a?b=\"c\" HTTP/1.1\r\nReferer: http://example.com/\r\n\r\n");
+    req.extensions_mut().insert(PeerAddr("192.0.2.1:4321".parse().unwrap()));
+    let mut entry = Entry::new(&req);
+    let mut res = Response::new(req.version);
+    res.put_body("Hello World!");
+    entry.finish(&res);
+    entry.time = time::at_utc(time::Timespec::new(971211336, 0));
+    entry.latency = Duration::from_millis(3);
+    entry
+}
+
+#[test]
+fn common_format() {
+    assert_eq!(entry().format(Format::Common),
+               "192.0.2.1 - - [10/Oct/2000:20:55:36 +0000] \"GET /a?b=\\\"c\\\" HTTP/1.1\" 200 12\n");
+}
+
+#[test]
+fn combined_format() {
+    assert_eq!(entry().format(Format::Combined),
+               "192.0.2.1 - - [10/Oct/2000:20:55:36 +0000] \"GET /a?b=\\\"c\\\" HTTP/1.1\" 200 12 \
+                \"http://example.com/\" \"-\"\n");
+}
+
+#[test]
+fn json_format() {
+    assert_eq!(entry().format(Format::Json),
+               "{\"time\":\"2000-10-10T20:55:36Z\",\"peer\":\"192.0.2.1\",\"method\":\"GET\",\
+                \"target\":\"/a?b=\\\"c\\\"\",\"version\":\"HTTP/1.1\",\"status\":200,\
+                \"bytes\":12,\"referer\":\"http://example.com/\",\"user_agent\":null,\
+                \"latency_us\":3000}\n");
+}
+
+struct Context {
+    log: AccessLog,
+}
+
+impl AccessLogs for Context {
+    fn access_log(&self) -> &AccessLog {
+        &self.log
+    }
+}
+
+struct App;
+
+impl Handler<Context> for App {
+    fn request(req: Request, _: &mut Context) -> Response {
+        let mut res = Response::new(req.version);
+        res.put_body("");
+        res
+    }
+}
+
+#[test]
+fn log_requests() {
+    let sink = Shared::default();
+    let mut ctx = Context { log: AccessLog::new(sink.clone(), Format::Combined) };
+    let req = request(b"POST /form HTTP/1.0\r\nUser-Agent: test\r\n\r\n");
+    Chain::<LogRequests, App>::request(req, &mut ctx);
+    drop(ctx);
+    let log = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+    assert!(log.starts_with("- - - ["));
+    assert!(log.ends_with("] \"POST /form HTTP/1.0\" 200 - \"-\" \"test\"\n"));
+}
+
+#[test]
+fn flush_under_steady_traffic() {
+    let sink = Shared::default();
+    let log = AccessLog::new(sink.clone(), Format::Common);
+    // An entry every 200 ms never leaves the writer idle for a whole flush interval.
+    for _ in 0..10 {
+        log.log(&entry());
+        thread::sleep(Duration::from_millis(200));
+    }
+    assert!(!sink.0.lock().unwrap().is_empty());
+    assert_eq!(log.dropped(), 0);
+}
+
+#[test]
+fn peer_addr() {
+    struct Peer;
+    impl Handler<()> for Peer {
+        fn request(req: Request, _: &mut ()) -> Response {
+            assert_eq!(req.extensions().get::<PeerAddr>(),
+                       Some(&PeerAddr("192.0.2.1:4321".parse().unwrap())));
+            let mut res = Response::new(req.version);
+            res.put_body("");
+            res
+        }
+    }
+    let mut inbuf = Buf::new();
+    let mut outbuf = Buf::new();
+    inbuf.extend(b"GET / HTTP/1.1\r\n\r\n");
+    let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+    let client = Client::Initial::<(), Peer>;
+    assert_eq!(client.data_received_from(&mut transport,
+                                         &mut (),
+                                         Some("192.0.2.1:4321".parse().unwrap())),
+               Async::Continue(Client::KeepAlive, ()));
+}
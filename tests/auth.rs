@@ -0,0 +1,124 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use kinglet::{Handler, Request, Response, StatusCode};
+use kinglet::auth::{Auth, Authenticate, Authenticates, Credentials, Scheme};
+use kinglet::middleware::Chain;
+
+fn request(authorization: Option<&str>) -> Request {
+    let raw = match authorization {
+        Some(value) => format!("GET / HTTP/1.1\r\nAuthorization: {}\r\n\r\n", value),
+        None => "GET / HTTP/1.1\r\n\r\n".to_owned(),
+    };
+    common::request(raw.as_bytes())
+}
+
+#[test]
+fn basic() {
+    let req = request(Some("Basic QWxhZGRpbjpvcGVuIHNlc2FtZQ=="));
+    let credentials = Credentials::from_request(&req);
+    assert_eq!(credentials,
+               Some(Credentials::Basic {
+                   username: "Aladdin".to_owned(),
+                   password: "open sesame".to_owned(),
+               }));
+    // "test:123£" from RFC 7617, section 2.1.
+    let credentials = Credentials::from_request(&request(Some("basic  dGVzdDoxMjPCow")));
+    assert_eq!(credentials,
+               Some(Credentials::Basic {
+                   username: "test".to_owned(),
+                   password: "123£".to_owned(),
+               }));
+    // Latin-1 is rejected.
+    assert_eq!(Credentials::from_request(&request(Some("Basic dGVzdDoxMjOj"))), None);
+    // Missing colon.
+    assert_eq!(Credentials::from_request(&request(Some("Basic dGVzdA=="))), None);
+    assert_eq!(Credentials::from_request(&request(Some("Basic !!!!"))), None);
+    assert_eq!(Credentials::from_request(&request(Some("Basic"))), None);
+    assert_eq!(Credentials::from_request(&request(None)), None);
+}
+
+#[test]
+fn bearer() {
+    assert_eq!(Credentials::from_request(&request(Some("Bearer mF_9.B5f-4.1JqM"))),
+               Some(Credentials::Bearer("mF_9.B5f-4.1JqM".to_owned())));
+    assert_eq!(Credentials::from_request(&request(Some("Bearer a=b"))), None);
+    assert_eq!(Credentials::from_request(&request(Some("Bearer"))), None);
+    assert_eq!(Credentials::from_request(&request(Some("Negotiate abc"))), None);
+}
+
+#[derive(Debug, PartialEq)]
+struct User(String);
+
+fn verify(credentials: &Credentials) -> Option<User> {
+    match *credentials {
+        Credentials::Basic { ref username, ref password } if password == "secret" => {
+            Some(User(username.clone()))
+        }
+        Credentials::Bearer(ref token) if token == "t0ken" => Some(User("bot".to_owned())),
+        _ => None,
+    }
+}
+
+struct Context {
+    auth: Auth<fn(&Credentials) -> Option<User>>,
+}
+
+impl Authenticates for Context {
+    type Verifier = fn(&Credentials) -> Option<User>;
+
+    fn auth(&self) -> &Auth<Self::Verifier> {
+        &self.auth
+    }
+}
+
+struct App;
+
+impl Handler<Context> for App {
+    fn request(req: Request, _: &mut Context) -> Response {
+        let mut res = Response::new(req.version);
+        let name = req.extensions().get::<User>().unwrap().0.clone();
+        res.put_body(name);
+        res
+    }
+}
+
+fn challenges(res: &Response) -> Vec<String> {
+    res.headers()
+       .get_vec("WWW-Authenticate")
+       .unwrap()
+       .iter()
+       .map(|value| String::from_utf8(value.clone()).unwrap())
+       .collect()
+}
+
+#[test]
+fn authenticate() {
+    let verifier = verify as fn(&Credentials) -> Option<User>;
+    let mut ctx = Context { auth: Auth::new(Scheme::Basic, "Admin \"area\"", verifier) };
+    // "alice:secret"
+    let res = Chain::<Authenticate, App>::request(request(Some("Basic YWxpY2U6c2VjcmV0")),
+                                                  &mut ctx);
+    assert_eq!(res.status, StatusCode::Ok);
+    // "alice:wrong"
+    let res = Chain::<Authenticate, App>::request(request(Some("Basic YWxpY2U6d3Jvbmc=")),
+                                                  &mut ctx);
+    assert_eq!(res.status, StatusCode::Unauthorized);
+    assert_eq!(challenges(&res),
+               vec!["Basic realm=\"Admin \\\"area\\\"\", charset=\"UTF-8\"".to_owned()]);
+    // Bearer is not accepted yet.
+    let res = Chain::<Authenticate, App>::request(request(Some("Bearer t0ken")), &mut ctx);
+    assert_eq!(res.status, StatusCode::Unauthorized);
+
+    ctx.auth = Auth::new(Scheme::Bearer, "api", verifier).scheme(Scheme::Basic);
+    let res = Chain::<Authenticate, App>::request(request(Some("Bearer t0ken")), &mut ctx);
+    assert_eq!(res.status, StatusCode::Ok);
+    let res = Chain::<Authenticate, App>::request(request(Some("Bearer expired")), &mut ctx);
+    assert_eq!(challenges(&res),
+               vec!["Bearer realm=\"api\", error=\"invalid_token\"".to_owned(),
+                    "Basic realm=\"api\", charset=\"UTF-8\"".to_owned()]);
+    let res = Chain::<Authenticate, App>::request(request(None), &mut ctx);
+    assert_eq!(challenges(&res)[0], "Bearer realm=\"api\"");
+}
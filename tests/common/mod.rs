@@ -0,0 +1,20 @@
+//! Helpers shared by the integration tests.
+#![allow(dead_code)]
+
+use httparse;
+use kinglet::Request;
+
+/// Parses the head of a request.
+pub fn request(head: &[u8]) -> Request {
+    let mut headers = [httparse::EMPTY_HEADER; 16];
+    let mut raw = httparse::Request::new(&mut headers);
+    raw.parse(head).unwrap();
+    Request::from_http1(raw, false).unwrap()
+}
+
+/// Parses the head of a request and attaches the body.
+pub fn request_with_body(head: &[u8], body: &[u8]) -> Request {
+    let mut req = request(head);
+    req.body = body.to_vec();
+    req
+}
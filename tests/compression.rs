@@ -0,0 +1,264 @@
+#![cfg(feature = "compression")]
+extern crate flate2;
+extern crate httparse;
+extern crate kinglet;
+extern crate netbuf;
+extern crate rotor;
+
+mod common;
+
+use std::io::{Read, Write};
+
+use flate2::read::{GzDecoder, ZlibDecoder};
+use netbuf::Buf;
+use rotor::async::Async;
+use rotor::transports::stream::{Protocol, Transport};
+use kinglet::{Body, Handler, HttpVersion, Message, Request, Response, StatusCode};
+use kinglet::compression::{self, Compress, Decompress, Decoding, Encoding};
+use kinglet::http1::Client;
+use common::request;
+
+fn response(content_type: &[u8], body: &[u8]) -> Response {
+    let mut res = Response::new(HttpVersion::Http11);
+    res.headers_mut().set("Content-Type", content_type.to_vec());
+    res.put_body(body);
+    res
+}
+
+fn body(res: &Response) -> &[u8] {
+    match res.body() {
+        Some(&Body::Bytes(ref bytes)) => bytes,
+        _ => panic!("no fixed size body"),
+    }
+}
+
+#[test]
+fn preferred_encoding() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: deflate, gzip;q=0.5\r\n\r\n");
+    assert_eq!(compression::preferred_encoding(&req), Some(Encoding::Deflate));
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: br\r\n\r\n");
+    assert_eq!(compression::preferred_encoding(&req), None);
+    let req = request(b"GET / HTTP/1.1\r\n\r\n");
+    assert_eq!(compression::preferred_encoding(&req), None);
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: *\r\n\r\n");
+    assert_eq!(compression::preferred_encoding(&req), Some(Encoding::Gzip));
+}
+
+#[test]
+fn compressible() {
+    assert!(compression::is_compressible(b"text/html"));
+    assert!(compression::is_compressible(b"application/JSON"));
+    assert!(compression::is_compressible(b"image/svg+xml"));
+    assert!(!compression::is_compressible(b"image/png"));
+    assert!(!compression::is_compressible(b"text/"));
+}
+
+#[test]
+fn compress_gzip() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    let text = vec![b'a'; 4096];
+    let mut res = response(b"text/plain; charset=utf-8", &text);
+    compression::compress(&req, &mut res).unwrap();
+    assert_eq!(res.get_value_header("Content-Encoding"), Some(&b"gzip"[..]));
+    assert_eq!(res.get_value_header("Vary"), Some(&b"Accept-Encoding"[..]));
+    let mut decoded = Vec::new();
+    GzDecoder::new(body(&res)).unwrap().read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, text);
+}
+
+#[test]
+fn skip_small_and_binary() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    let mut res = response(b"text/plain", b"small");
+    compression::compress(&req, &mut res).unwrap();
+    assert!(!res.contains_header("Content-Encoding"));
+    assert!(!res.contains_header("Vary"));
+    let mut res = response(b"image/png", &[0; 4096]);
+    compression::compress(&req, &mut res).unwrap();
+    assert!(!res.contains_header("Content-Encoding"));
+}
+
+#[test]
+fn skip_partial_content() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    let mut res = response(b"text/plain", &[b'a'; 4096]);
+    res.status = StatusCode::PartialContent;
+    res.headers_mut().set("Content-Range", b"bytes 0-4095/8192".to_vec());
+    compression::compress(&req, &mut res).unwrap();
+    assert!(!res.contains_header("Content-Encoding"));
+    assert_eq!(body(&res).len(), 4096);
+}
+
+#[test]
+fn weaken_etag() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: gzip\r\n\r\n");
+    let mut res = response(b"text/plain", &[b'a'; 4096]);
+    res.headers_mut().set("ETag", b"\"abc\"".to_vec());
+    compression::compress(&req, &mut res).unwrap();
+    assert_eq!(res.get_value_header("ETag"), Some(&b"W/\"abc\""[..]));
+    let mut res = response(b"text/plain", &[b'a'; 4096]);
+    res.headers_mut().set("ETag", b"W/\"abc\"".to_vec());
+    compression::compress(&req, &mut res).unwrap();
+    assert_eq!(res.get_value_header("ETag"), Some(&b"W/\"abc\""[..]));
+}
+
+#[test]
+fn identity_varies() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: identity\r\n\r\n");
+    let mut res = response(b"application/json", &[b' '; 2048]);
+    res.headers_mut().append("Vary", b"Accept-Language".to_vec());
+    compression::compress(&req, &mut res).unwrap();
+    assert!(!res.contains_header("Content-Encoding"));
+    assert_eq!(res.get_header("Vary"),
+               Some(&vec![b"Accept-Language".to_vec(), b"Accept-Encoding".to_vec()]));
+    assert_eq!(body(&res).len(), 2048);
+}
+
+#[test]
+fn compress_stream() {
+    struct Hello;
+    impl Handler<()> for Hello {
+        fn request(_: Request, _: &mut ()) -> Response {
+            let mut res = Response::new(HttpVersion::Http11);
+            res.headers_mut().set("Content-Type", b"text/plain".to_vec());
+            res.put_stream(|w: &mut Write| w.write_all(b"Hello World!"));
+            res
+        }
+    }
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: deflate\r\n\r\n");
+    let mut res = <Compress<Hello> as Handler<()>>::request(req, &mut ());
+    assert_eq!(res.get_value_header("Content-Encoding"), Some(&b"deflate"[..]));
+    let compressed = res.take_body().unwrap().into_bytes().unwrap();
+    let mut decoded = Vec::new();
+    ZlibDecoder::new(&compressed[..]).read_to_end(&mut decoded).unwrap();
+    assert_eq!(decoded, b"Hello World!");
+}
+
+fn compressed_request(encoding: &[u8], body: &[u8]) -> Request {
+    let head = format!("POST / HTTP/1.1\r\nContent-Encoding: {}\r\nContent-Length: {}\r\n\r\n",
+                       String::from_utf8(encoding.to_vec()).unwrap(),
+                       body.len());
+    let mut req = request(head.as_bytes());
+    req.body = body.to_vec();
+    req
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::Default);
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn decode_gzip_body() {
+    let mut req = compressed_request(b"gzip", &gzip(b"telemetry data"));
+    compression::decode_body(&mut req, 1024).unwrap();
+    assert_eq!(req.body, b"telemetry data");
+    assert!(!req.contains_header("Content-Encoding"));
+    assert_eq!(req.content_length(), Ok(14));
+}
+
+#[test]
+fn decode_raw_and_zlib_deflate() {
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::Default);
+    encoder.write_all(b"zlib").unwrap();
+    let mut req = compressed_request(b"deflate", &encoder.finish().unwrap());
+    compression::decode_body(&mut req, 1024).unwrap();
+    assert_eq!(req.body, b"zlib");
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::Default);
+    encoder.write_all(b"raw").unwrap();
+    let mut req = compressed_request(b"deflate", &encoder.finish().unwrap());
+    compression::decode_body(&mut req, 1024).unwrap();
+    assert_eq!(req.body, b"raw");
+}
+
+#[test]
+fn decode_multiple_codings() {
+    let mut req = compressed_request(b"gzip, identity, gzip", &gzip(&gzip(b"twice")));
+    compression::decode_body(&mut req, 1024).unwrap();
+    assert_eq!(req.body, b"twice");
+}
+
+#[test]
+fn decode_errors() {
+    let mut req = compressed_request(b"gzip", &gzip(&[0; 100_000]));
+    assert!(req.body.len() < 1024);
+    assert_eq!(compression::decode_body(&mut req, 99_999), Err(kinglet::Error::TooLarge));
+    let mut req = compressed_request(b"gzip", b"not gzip");
+    assert_eq!(compression::decode_body(&mut req, 1024), Err(kinglet::Error::InvalidBody));
+    let mut req = compressed_request(b"br", b"");
+    assert_eq!(compression::decode_body(&mut req, 1024),
+               Err(kinglet::Error::UnsupportedMediaType));
+}
+
+struct Ctx;
+
+impl Decoding for Ctx {
+    fn max_decoded_size(&self) -> usize {
+        1024
+    }
+}
+
+struct Echo;
+
+impl Handler<Ctx> for Echo {
+    fn request(req: Request, _: &mut Ctx) -> Response {
+        assert!(!req.contains_header("Content-Encoding"));
+        let mut res = Response::new(req.version);
+        res.put_body(req.body);
+        res
+    }
+}
+
+#[test]
+fn decompress_handler() {
+    let req = compressed_request(b"gzip", &gzip(b"echo"));
+    let res = <Decompress<Echo> as Handler<Ctx>>::request(req, &mut Ctx);
+    assert_eq!(body(&res), b"echo");
+    let req = compressed_request(b"compress", b"");
+    let res = <Decompress<Echo> as Handler<Ctx>>::request(req, &mut Ctx);
+    assert_eq!(res.status, kinglet::StatusCode::UnsupportedMediaType);
+    assert_eq!(res.get_value_header("Accept-Encoding"), Some(&b"gzip, deflate"[..]));
+}
+
+#[test]
+fn decompress_while_received() {
+    let body = gzip(&gzip(b"echo"));
+    let mut inbuf = Buf::new();
+    let mut outbuf = Buf::new();
+    let mut client = Client::Initial::<Ctx, Decompress<Echo>>;
+    inbuf.extend(format!("POST / HTTP/1.1\r\nHost: example.com\r\nContent-Encoding: gzip, gzip\r\n\
+                          Content-Length: {}\r\n\r\n",
+                         body.len())
+                     .as_bytes());
+    for piece in body.chunks(3) {
+        inbuf.extend(piece);
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        client = match client.data_received(&mut transport, &mut Ctx) {
+            Async::Continue(client, ()) => client,
+            Async::Stop => panic!("connection stopped"),
+        };
+    }
+    assert!(client == Client::KeepAlive);
+    assert!(outbuf[..].ends_with(b"Content-Length: 4\r\n\r\necho"));
+}
+
+#[test]
+fn decompress_too_large_while_received() {
+    let body = gzip(&[0; 100_000]);
+    let mut inbuf = Buf::new();
+    let mut outbuf = Buf::new();
+    inbuf.extend(format!("POST / HTTP/1.1\r\nHost: example.com\r\nContent-Encoding: gzip\r\n\
+                          Content-Length: {}\r\n\r\n",
+                         body.len())
+                     .as_bytes());
+    inbuf.extend(&body);
+    {
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        match Client::Initial::<Ctx, Decompress<Echo>>.data_received(&mut transport, &mut Ctx) {
+            Async::Continue(Client::Closing, ()) => (),
+            _ => panic!("body not rejected"),
+        }
+    }
+    assert!(outbuf[..].starts_with(b"HTTP/1.1 413 Payload Too Large\r\n"));
+}
@@ -0,0 +1,83 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use kinglet::{Body, Message, Response, StatusCode};
+use kinglet::conditional::{self, EntityTag};
+use kinglet::date;
+use common::request;
+
+fn response(head: &[u8]) -> Response {
+    let req = request(head);
+    let mut res = Response::new(req.version);
+    res.headers_mut().set("Content-Type", b"text/plain".to_vec());
+    res.headers_mut().set("ETag", b"\"v1\"".to_vec());
+    res.headers_mut().set("Last-Modified", b"Sun, 06 Nov 1994 08:49:37 GMT".to_vec());
+    res.put_body("Hello World!");
+    conditional::apply(&req, &mut res);
+    res
+}
+
+#[test]
+fn parse_dates() {
+    let expected = Some(UNIX_EPOCH + Duration::from_secs(784111777));
+    assert_eq!(date::parse(b"Sun, 06 Nov 1994 08:49:37 GMT"), expected);
+    assert_eq!(date::parse(b"Sunday, 06-Nov-94 08:49:37 GMT"), expected);
+    assert_eq!(date::parse(b"Sun Nov  6 08:49:37 1994"), expected);
+    assert_eq!(date::parse(b"yesterday"), None);
+    assert_eq!(date::format(expected.unwrap()), "Sun, 06 Nov 1994 08:49:37 GMT");
+}
+
+#[test]
+fn entity_tags() {
+    let strong = EntityTag::parse(b"\"a\"").unwrap();
+    let weak = EntityTag::parse(b"W/\"a\"").unwrap();
+    assert!(weak.weak && weak.tag == b"\"a\"");
+    assert!(strong.weak_eq(&weak) && !strong.strong_eq(&weak));
+    assert!(EntityTag::parse(b"a").is_none());
+}
+
+#[test]
+fn if_none_match() {
+    let res = response(b"GET / HTTP/1.1\r\nIf-None-Match: \"v0\", W/\"v1\"\r\n\r\n");
+    assert_eq!(res.status, StatusCode::NotModified);
+    assert!(res.body().is_none());
+    assert!(!res.contains_header("Content-Type"));
+    assert_eq!(res.get_value_header("ETag"), Some(&b"\"v1\""[..]));
+    let res = response(b"GET / HTTP/1.1\r\nIf-None-Match: \"v0\"\r\n\r\n");
+    assert_eq!(res.status, StatusCode::Ok);
+    let res = response(b"PUT / HTTP/1.1\r\nIf-None-Match: *\r\n\r\n");
+    assert_eq!(res.status, StatusCode::PreconditionFailed);
+}
+
+#[test]
+fn if_match() {
+    let res = response(b"PUT / HTTP/1.1\r\nIf-Match: \"v0\"\r\n\r\n");
+    assert_eq!(res.status, StatusCode::PreconditionFailed);
+    match res.body() {
+        Some(&Body::Bytes(ref bytes)) => assert!(bytes.is_empty()),
+        _ => panic!("no empty body"),
+    }
+    let res = response(b"PUT / HTTP/1.1\r\nIf-Match: W/\"v1\"\r\n\r\n");
+    assert_eq!(res.status, StatusCode::PreconditionFailed);
+    let res = response(b"PUT / HTTP/1.1\r\nIf-Match: \"v1\"\r\n\r\n");
+    assert_eq!(res.status, StatusCode::Ok);
+}
+
+#[test]
+fn modification_dates() {
+    let res = response(b"GET / HTTP/1.1\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n");
+    assert_eq!(res.status, StatusCode::NotModified);
+    let res = response(b"GET / HTTP/1.1\r\nIf-Modified-Since: Sat, 05 Nov 1994 08:49:37 GMT\r\n\r\n");
+    assert_eq!(res.status, StatusCode::Ok);
+    // If-None-Match takes precedence over If-Modified-Since.
+    let res = response(b"GET / HTTP/1.1\r\nIf-None-Match: \"v0\"\r\n\
+                         If-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n\r\n");
+    assert_eq!(res.status, StatusCode::Ok);
+    let res = response(b"DELETE / HTTP/1.1\r\n\
+                         If-Unmodified-Since: Sat, 05 Nov 1994 08:49:37 GMT\r\n\r\n");
+    assert_eq!(res.status, StatusCode::PreconditionFailed);
+}
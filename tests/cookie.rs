@@ -0,0 +1,83 @@
+extern crate httparse;
+extern crate kinglet;
+extern crate time;
+
+mod common;
+
+use kinglet::{Error, HttpVersion, Message, Response, SetCookie};
+use kinglet::cookie::SameSite;
+use common::request;
+
+#[test]
+fn parse_cookie_header() {
+    let req = request(b"GET / HTTP/1.1\r\nCookie: SID=31d4d96e407aad42; lang=\"en-US\"\r\n\
+                        Cookie: theme=dark;;bad\r\n\r\n");
+    let jar = req.cookies();
+    assert_eq!(jar.len(), 3);
+    assert_eq!(jar.get("SID"), Some("31d4d96e407aad42"));
+    assert_eq!(jar.get("lang"), Some("en-US"));
+    assert_eq!(jar.get("theme"), Some("dark"));
+    assert_eq!(jar.get("sid"), None);
+}
+
+#[test]
+fn no_cookies() {
+    let req = request(b"GET / HTTP/1.1\r\n\r\n");
+    assert!(req.cookies().is_empty());
+}
+
+#[test]
+fn set_cookie_attributes() {
+    let cookie = SetCookie::new("SID", "31d4d96e407aad42")
+                     .expires(time::at_utc(time::Timespec::new(1445412480, 0)))
+                     .max_age(3600)
+                     .domain("example.com")
+                     .path("/")
+                     .secure(true)
+                     .http_only(true)
+                     .same_site(SameSite::Lax);
+    assert_eq!(cookie.to_string(),
+               "SID=31d4d96e407aad42; Expires=Wed, 21 Oct 2015 07:28:00 GMT; Max-Age=3600; \
+                Domain=example.com; Path=/; Secure; HttpOnly; SameSite=Lax");
+}
+
+#[test]
+fn response_set_cookie() {
+    let mut res = Response::new(HttpVersion::Http11);
+    res.set_cookie(&SetCookie::new("a", "1")).unwrap();
+    res.set_cookie(&SetCookie::removal("b")).unwrap();
+    assert_eq!(res.get_header("Set-Cookie"),
+               Some(&vec![b"a=1".to_vec(),
+                          b"b=; Expires=Thu, 01 Jan 1970 00:00:00 GMT; Max-Age=0".to_vec()]));
+    assert_eq!(res.set_cookie(&SetCookie::new("a", "x; Domain=evil.com")),
+               Err(Error::InvalidHeaderValue));
+    assert_eq!(res.set_cookie(&SetCookie::new("a", "1").path("/\r\nX: y")),
+               Err(Error::InvalidHeaderValue));
+}
+
+#[cfg(feature = "secure-cookies")]
+#[test]
+fn signed_cookie() {
+    use kinglet::cookie::Key;
+    let key = Key::generate();
+    let cookie = SetCookie::new("user", "alice").signed(&key);
+    assert!(cookie.validate().is_ok());
+    let req = request(format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", cookie).as_bytes());
+    assert_eq!(req.cookies().get_signed("user", &key), Some("alice".to_owned()));
+    assert_eq!(req.cookies().get_signed("user", &Key::generate()), None);
+    let tampered = format!("GET / HTTP/1.1\r\nCookie: {}x\r\n\r\n", cookie);
+    assert_eq!(request(tampered.as_bytes()).cookies().get_signed("user", &key), None);
+}
+
+#[cfg(feature = "secure-cookies")]
+#[test]
+fn encrypted_cookie() {
+    use kinglet::cookie::Key;
+    let key = Key::from_master(&[7; 64]);
+    let cookie = SetCookie::new("session", "secret data").encrypted(&key);
+    assert!(cookie.validate().is_ok());
+    assert!(!cookie.value().contains("secret"));
+    let req = request(format!("GET / HTTP/1.1\r\nCookie: {}\r\n\r\n", cookie).as_bytes());
+    assert_eq!(req.cookies().get_encrypted("session", &key), Some("secret data".to_owned()));
+    assert_eq!(req.cookies().get_encrypted("other", &key), None);
+}
@@ -0,0 +1,196 @@
+#![cfg(feature = "digest-auth")]
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use std::thread;
+use std::time::Duration;
+
+use kinglet::{Handler, Message, Request, Response, StatusCode};
+use kinglet::digest::{Algorithm, AuthenticateDigest, DigestAuth, DigestAuthenticates,
+                      DigestUser, Failure};
+use kinglet::middleware::Chain;
+
+type Passwords = fn(&str) -> Option<String>;
+
+fn passwords(username: &str) -> Option<String> {
+    if username == "Mufasa" {
+        Some("Circle of Life".to_owned())
+    } else {
+        None
+    }
+}
+
+fn request(authorization: Option<&str>) -> Request {
+    let raw = match authorization {
+        Some(value) => {
+            format!("GET /dir/index.html HTTP/1.1\r\nAuthorization: {}\r\n\r\n", value)
+        }
+        None => "GET /dir/index.html HTTP/1.1\r\n\r\n".to_owned(),
+    };
+    common::request(raw.as_bytes())
+}
+
+/// Returns the value of a quoted parameter in a challenge.
+fn param<'a>(challenge: &'a str, name: &str) -> &'a str {
+    let start = challenge.find(&format!("{}=\"", name)).unwrap() + name.len() + 2;
+    let len = challenge[start..].find('"').unwrap();
+    &challenge[start..start + len]
+}
+
+fn authorization(challenge: &str, algorithm: Algorithm, password: &str, nc: &str) -> String {
+    let (realm, nonce) = (param(challenge, "realm"), param(challenge, "nonce"));
+    let ha1 = algorithm.hash(&format!("Mufasa:{}:{}", realm, password));
+    let ha2 = algorithm.hash("GET:/dir/index.html");
+    let response = algorithm.hash(&format!("{}:{}:{}:f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ:\
+                                            auth:{}",
+                                           ha1,
+                                           nonce,
+                                           nc,
+                                           ha2));
+    format!("Digest username=\"Mufasa\", realm=\"{}\", uri=\"/dir/index.html\", \
+             algorithm={}, nonce=\"{}\", nc={}, \
+             cnonce=\"f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ\", qop=auth, \
+             response=\"{}\", opaque=\"{}\"",
+            realm,
+            algorithm.name(),
+            nonce,
+            nc,
+            response,
+            param(challenge, "opaque"))
+}
+
+#[test]
+fn hash() {
+    // The responses of the examples in RFC 7616, section 3.9.1.
+    let response = |algorithm: Algorithm| {
+        let ha1 = algorithm.hash("Mufasa:http-auth@example.org:Circle of Life");
+        let ha2 = algorithm.hash("GET:/dir/index.html");
+        algorithm.hash(&format!("{}:7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v:00000001:\
+                                 f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ:auth:{}",
+                                ha1,
+                                ha2))
+    };
+    assert_eq!(response(Algorithm::Md5), "8ca523f5e9506fed4657c9700eebdbec");
+    assert_eq!(response(Algorithm::Sha256),
+               "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1");
+}
+
+#[test]
+fn verify() {
+    let auth = DigestAuth::new("http-auth@example.org", passwords as Passwords);
+    let challenges = auth.challenges(false);
+    assert_eq!(challenges.len(), 2);
+    assert!(challenges[0].starts_with("Digest realm=\"http-auth@example.org\", qop=\"auth\", \
+                                       algorithm=SHA-256, nonce=\""));
+    assert!(challenges[1].contains("algorithm=MD5"));
+    assert_eq!(param(&challenges[0], "nonce"), param(&challenges[1], "nonce"));
+
+    let value = authorization(&challenges[0], Algorithm::Sha256, "Circle of Life", "00000001");
+    let authorized = auth.verify(&request(Some(&value))).unwrap();
+    assert_eq!(authorized.username, "Mufasa");
+    assert!(authorized.info.starts_with("qop=auth, rspauth=\""));
+    // The same nonce count is a replay, a higher one is accepted.
+    assert_eq!(auth.verify(&request(Some(&value))), Err(Failure::Replayed));
+    let value = authorization(&challenges[1], Algorithm::Md5, "Circle of Life", "00000002");
+    assert!(auth.verify(&request(Some(&value))).is_ok());
+
+    let value = authorization(&challenges[0], Algorithm::Sha256, "wrong", "00000003");
+    assert_eq!(auth.verify(&request(Some(&value))), Err(Failure::Invalid));
+    assert_eq!(auth.verify(&request(None)), Err(Failure::Missing));
+    assert_eq!(auth.verify(&request(Some("Basic TXVmYXNhOkNpcmNsZSBvZiBMaWZl"))),
+               Err(Failure::Missing));
+
+    // MD5 is not accepted once it is no longer offered.
+    let auth = auth.algorithms(&[Algorithm::Sha256]);
+    let challenges = auth.challenges(false);
+    assert_eq!(challenges.len(), 1);
+    let value = authorization(&challenges[0], Algorithm::Md5, "Circle of Life", "00000001");
+    assert_eq!(auth.verify(&request(Some(&value))), Err(Failure::Missing));
+}
+
+#[test]
+fn stale_nonce() {
+    let auth = DigestAuth::new("test", passwords as Passwords)
+                   .nonce_lifetime(Duration::from_millis(10));
+    let challenge = auth.challenges(false).remove(0);
+    let value = authorization(&challenge, Algorithm::Sha256, "Circle of Life", "00000001");
+    thread::sleep(Duration::from_millis(20));
+    assert_eq!(auth.verify(&request(Some(&value))), Err(Failure::Stale));
+    let res = auth.unauthorized(&request(Some(&value)), Failure::Stale);
+    assert_eq!(res.status, StatusCode::Unauthorized);
+    let challenges = res.headers().get_vec("WWW-Authenticate").unwrap();
+    assert_eq!(challenges.len(), 2);
+    assert!(challenges.iter().all(|challenge| challenge.ends_with(b", stale=true")));
+}
+
+#[test]
+fn max_nonces() {
+    let auth = DigestAuth::new("test", passwords as Passwords).max_nonces(1);
+    let first = auth.challenges(false).remove(0);
+    let second = auth.challenges(false).remove(0);
+    let value = authorization(&first, Algorithm::Sha256, "Circle of Life", "00000001");
+    assert!(auth.verify(&request(Some(&value))).is_ok());
+    // The first nonce is still in use, so the second is not remembered.
+    let value = authorization(&second, Algorithm::Sha256, "Circle of Life", "00000001");
+    assert_eq!(auth.verify(&request(Some(&value))), Err(Failure::Stale));
+    let value = authorization(&first, Algorithm::Sha256, "Circle of Life", "00000002");
+    assert!(auth.verify(&request(Some(&value))).is_ok());
+}
+
+#[test]
+fn forged_nonce() {
+    let auth = DigestAuth::new("test", passwords as Passwords);
+    let challenge = auth.challenges(false).remove(0);
+    let nonce = param(&challenge, "nonce");
+    // A later expiry time invalidates the signature.
+    let forged = challenge.replace(nonce, &format!("ffffffffffffffff{}", &nonce[16..]));
+    let value = authorization(&forged, Algorithm::Sha256, "Circle of Life", "00000001");
+    assert_eq!(auth.verify(&request(Some(&value))), Err(Failure::Stale));
+}
+
+struct Context {
+    auth: DigestAuth<Passwords>,
+}
+
+impl DigestAuthenticates for Context {
+    type Passwords = Passwords;
+
+    fn digest_auth(&self) -> &DigestAuth<Passwords> {
+        &self.auth
+    }
+}
+
+struct App;
+
+impl Handler<Context> for App {
+    fn request(req: Request, _: &mut Context) -> Response {
+        let mut res = Response::new(req.version);
+        let user = req.extensions().get::<DigestUser>().unwrap().0.clone();
+        res.put_body(user);
+        res
+    }
+}
+
+#[test]
+fn middleware() {
+    let mut ctx = Context { auth: DigestAuth::new("test", passwords as Passwords) };
+    let res = Chain::<AuthenticateDigest, App>::request(request(None), &mut ctx);
+    assert_eq!(res.status, StatusCode::Unauthorized);
+    let challenge = res.headers().get_vec("WWW-Authenticate").unwrap()[0].clone();
+    let challenge = String::from_utf8(challenge).unwrap();
+    let value = authorization(&challenge, Algorithm::Sha256, "Circle of Life", "00000001");
+    let res = Chain::<AuthenticateDigest, App>::request(request(Some(&value)), &mut ctx);
+    assert_eq!(res.status, StatusCode::Ok);
+    let info = String::from_utf8(res.get_value_header("Authentication-Info").unwrap().to_vec())
+                   .unwrap();
+    let ha1 = Algorithm::Sha256.hash("Mufasa:test:Circle of Life");
+    let rspauth = Algorithm::Sha256.hash(&format!("{}:{}:00000001:\
+                                                   f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ:\
+                                                   auth:{}",
+                                                  ha1,
+                                                  param(&challenge, "nonce"),
+                                                  Algorithm::Sha256.hash(":/dir/index.html")));
+    assert_eq!(param(&info, "rspauth"), rspauth);
+}
@@ -0,0 +1,74 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use kinglet::{Body, Extensions, Handler, Request, Response};
+use kinglet::middleware::{Chain, Middleware};
+use common::request;
+
+#[derive(Debug, PartialEq)]
+struct RequestId(u32);
+
+#[derive(Debug, PartialEq)]
+struct Handled(bool);
+
+#[test]
+fn type_map() {
+    let mut extensions = Extensions::new();
+    assert!(extensions.is_empty());
+    assert_eq!(extensions.insert(RequestId(1)), None);
+    assert_eq!(extensions.insert(RequestId(2)), Some(RequestId(1)));
+    extensions.insert(Handled(false));
+    assert_eq!(extensions.len(), 2);
+    extensions.get_mut::<Handled>().unwrap().0 = true;
+    assert_eq!(extensions.get::<Handled>(), Some(&Handled(true)));
+    assert_eq!(extensions.remove::<RequestId>(), Some(RequestId(2)));
+    assert!(!extensions.contains::<RequestId>());
+    assert!(extensions.get::<String>().is_none());
+}
+
+#[test]
+fn requests_compare_without_extensions() {
+    let mut a = request(b"GET / HTTP/1.1\r\n\r\n");
+    a.extensions_mut().insert(RequestId(1));
+    assert_eq!(a, request(b"GET / HTTP/1.1\r\n\r\n"));
+    assert!(a != request(b"GET /other HTTP/1.1\r\n\r\n"));
+}
+
+struct Ids;
+
+impl Middleware<u32> for Ids {
+    type State = ();
+
+    fn before(req: &mut Request, next_id: &mut u32) -> Result<(), Response> {
+        *next_id += 1;
+        req.extensions_mut().insert(RequestId(*next_id));
+        Ok(())
+    }
+
+    fn after(_: (), res: &mut Response, _: &mut u32) {
+        assert_eq!(res.extensions().get::<Handled>(), Some(&Handled(true)));
+    }
+}
+
+struct App;
+
+impl Handler<u32> for App {
+    fn request(req: Request, _: &mut u32) -> Response {
+        let mut res = Response::new(req.version);
+        res.put_body(format!("{}", req.extensions().get::<RequestId>().unwrap().0));
+        res.extensions_mut().insert(Handled(true));
+        res
+    }
+}
+
+#[test]
+fn pass_data_downstream() {
+    let mut next_id = 41;
+    let res = Chain::<Ids, App>::request(request(b"GET / HTTP/1.1\r\n\r\n"), &mut next_id);
+    match res.body() {
+        Some(&Body::Bytes(ref bytes)) => assert_eq!(bytes, b"42"),
+        _ => panic!("no fixed size body"),
+    }
+}
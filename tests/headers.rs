@@ -0,0 +1,92 @@
+extern crate kinglet;
+
+use kinglet::{Error, Headers, HttpVersion, Response};
+
+#[test]
+fn append_and_get() {
+    let mut headers = Headers::new();
+    headers.append("Accept", b"text/html".to_vec());
+    headers.append("accept", b"text/plain".to_vec());
+    assert_eq!(headers.get("ACCEPT"), Some(&b"text/html"[..]));
+    assert_eq!(headers.get_vec("Accept").unwrap().len(), 2);
+    assert_eq!(headers.get("Host"), None);
+}
+
+#[test]
+fn set_replaces() {
+    let mut headers = Headers::new();
+    headers.append("X-Foo", b"1".to_vec());
+    headers.append("X-Foo", b"2".to_vec());
+    headers.set("x-foo", b"3".to_vec());
+    assert_eq!(headers.get_vec("X-Foo"), Some(&vec![b"3".to_vec()]));
+    assert_eq!(headers.iter().collect::<Vec<_>>(), vec![("x-foo", &b"3"[..])]);
+}
+
+#[test]
+fn remove() {
+    let mut headers = Headers::new();
+    headers.append("Server", b"kinglet".to_vec());
+    assert_eq!(headers.remove("server"), Some(vec![b"kinglet".to_vec()]));
+    assert_eq!(headers.remove("server"), None);
+    assert!(headers.is_empty());
+    assert!(headers.iter().next().is_none());
+}
+
+#[test]
+fn insertion_order() {
+    let mut headers = Headers::new();
+    headers.append("Content-Type", b"text/plain".to_vec());
+    headers.append("Set-Cookie", b"a=1".to_vec());
+    headers.append("X-Request-Id", b"42".to_vec());
+    headers.append("set-cookie", b"b=2".to_vec());
+    assert_eq!(headers.iter().collect::<Vec<_>>(),
+               vec![("Content-Type", &b"text/plain"[..]),
+                    ("Set-Cookie", &b"a=1"[..]),
+                    ("X-Request-Id", &b"42"[..]),
+                    ("Set-Cookie", &b"b=2"[..])]);
+}
+
+#[test]
+fn drain() {
+    let mut headers = Headers::new();
+    headers.append("B", b"1".to_vec());
+    headers.append("A", b"2".to_vec());
+    let drained: Vec<_> = headers.drain().map(|(n, v)| (n.into_owned(), v)).collect();
+    assert_eq!(drained, vec![("B".to_owned(), b"1".to_vec()), ("A".to_owned(), b"2".to_vec())]);
+    assert!(headers.is_empty());
+    assert!(!headers.contains_key("A"));
+}
+
+#[test]
+fn serialize_response_headers() {
+    let mut res = Response::new(HttpVersion::Http11);
+    res.headers_mut().append("Date", b"Thu, 01 Jan 1970 00:00:00 GMT".to_vec());
+    res.headers_mut().append("X-Second", b"2".to_vec());
+    res.headers_mut().append("X-First", b"1".to_vec());
+    let mut out = Vec::new();
+    res.serialize(&mut out).unwrap();
+    assert_eq!(&out[..],
+               &b"HTTP/1.1 200 OK\r\nDate: Thu, 01 Jan 1970 00:00:00 GMT\r\nX-Second: 2\r\n\
+                  X-First: 1\r\n\r\n"[..]);
+}
+
+#[test]
+fn validate_names_and_values() {
+    let mut headers = Headers::new();
+    assert_eq!(headers.try_append("X-Ok", b"some value\twith tab".to_vec()), Ok(()));
+    assert_eq!(headers.try_append("Bad Name", b"x".to_vec()), Err(Error::InvalidHeaderName));
+    assert_eq!(headers.try_append("", b"x".to_vec()), Err(Error::InvalidHeaderName));
+    assert_eq!(headers.try_set("X-Split", b"a\r\nSet-Cookie: evil".to_vec()),
+               Err(Error::InvalidHeaderValue));
+    assert!(!headers.contains_key("X-Split"));
+    assert_eq!(headers.validate(), Ok(()));
+}
+
+#[test]
+fn serialize_rejects_response_splitting() {
+    let mut res = Response::new(HttpVersion::Http11);
+    res.headers_mut().append("Location", b"/\r\n\r\n<html>".to_vec());
+    let mut out = Vec::new();
+    assert!(res.serialize(&mut out).is_err());
+    assert!(out.is_empty());
+}
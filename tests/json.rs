@@ -0,0 +1,50 @@
+#![cfg(feature = "json")]
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use std::collections::BTreeMap;
+
+use kinglet::{Error, HttpVersion, Message, Response};
+use common::request_with_body;
+
+#[test]
+fn request_json() {
+    let req = request_with_body(b"POST / HTTP/1.1\r\nContent-Type: application/json; charset=utf-8\r\n\r\n",
+                                b"{\"a\": 1, \"b\": 2}");
+    let value: BTreeMap<String, u32> = req.json().unwrap();
+    assert_eq!(value.get("b"), Some(&2));
+    let req = request_with_body(b"POST / HTTP/1.1\r\nContent-Type: application/problem+json\r\n\r\n",
+                                b"[1, 2]");
+    assert_eq!(req.json::<Vec<u32>>(), Ok(vec![1, 2]));
+}
+
+#[test]
+fn request_json_errors() {
+    let req = request_with_body(b"POST / HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n", b"[]");
+    assert_eq!(req.json::<Vec<u32>>(), Err(Error::UnsupportedMediaType));
+    let req = request_with_body(b"POST / HTTP/1.1\r\nContent-Type: application/json\r\n\r\n", b"[1,");
+    let err = req.json::<Vec<u32>>().unwrap_err();
+    assert_eq!(err, Error::InvalidBody);
+    assert_eq!(err.status(), kinglet::StatusCode::BadRequest);
+}
+
+#[test]
+fn response_json() {
+    let mut res = Response::new(HttpVersion::Http11);
+    res.json(&vec![1, 2, 3]).unwrap();
+    assert_eq!(res.get_value_header("Content-Type"), Some(&b"application/json"[..]));
+    let mut out = Vec::new();
+    res.serialize(&mut out).unwrap();
+    assert!(out.ends_with(b"Content-Length: 7\r\n\r\n[1,2,3]"));
+}
+
+#[test]
+fn response_json_stream() {
+    let mut res = Response::new(HttpVersion::Http11);
+    res.json_stream(vec!["x"; 3]);
+    let mut out = Vec::new();
+    res.write_to(&mut out).unwrap();
+    assert!(out.ends_with(b"Transfer-Encoding: chunked\r\n\r\nd\r\n[\"x\",\"x\",\"x\"]\r\n0\r\n\r\n"));
+}
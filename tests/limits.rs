@@ -0,0 +1,88 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use std::net::IpAddr;
+
+use kinglet::{Handler, Message, Request, Response, StatusCode};
+use kinglet::limits::{Exceeded, Limited, Limiter, Limits, Shed};
+use kinglet::middleware::Chain;
+
+fn request() -> Request {
+    common::request(b"GET / HTTP/1.1\r\n\r\n")
+}
+
+#[test]
+fn connections() {
+    let limiter = Limiter::new(Limits {
+        max_connections: Some(3),
+        max_connections_per_ip: Some(2),
+        ..Limits::default()
+    });
+    let a: IpAddr = "192.0.2.1".parse().unwrap();
+    let b: IpAddr = "192.0.2.2".parse().unwrap();
+    let first = limiter.connect(Some(a)).unwrap();
+    let _second = limiter.connect(Some(a)).unwrap();
+    assert_eq!(limiter.connect(Some(a)).err(), Some(Exceeded::ConnectionsPerIp));
+    let _third = limiter.connect(Some(b)).unwrap();
+    assert!(limiter.is_saturated());
+    assert_eq!(limiter.connect(None).err(), Some(Exceeded::Connections));
+    drop(first);
+    assert_eq!(limiter.connections(), 2);
+    assert!(limiter.connect(Some(a)).is_ok());
+}
+
+#[test]
+fn no_connections_per_ip() {
+    let limiter = Limiter::new(Limits { max_connections_per_ip: Some(0), ..Limits::default() });
+    for i in 0..3 {
+        let ip = IpAddr::from([192, 0, 2, i]);
+        assert_eq!(limiter.connect(Some(ip)).err(), Some(Exceeded::ConnectionsPerIp));
+    }
+    assert_eq!(format!("{:?}", limiter).matches("192.0.2.").count(), 0);
+}
+
+struct Context {
+    limiter: Limiter,
+}
+
+impl Limited for Context {
+    fn limiter(&self) -> &Limiter {
+        &self.limiter
+    }
+}
+
+struct App;
+
+impl Handler<Context> for App {
+    fn request(req: Request, ctx: &mut Context) -> Response {
+        assert_eq!(ctx.limiter.in_flight(), 2);
+        let mut res = Response::new(req.version);
+        res.put_body("");
+        res
+    }
+}
+
+#[test]
+fn shed_requests() {
+    let limits = Limits {
+        max_in_flight: Some(2),
+        retry_after: 5,
+        ..Limits::default()
+    };
+    let mut ctx = Context { limiter: Limiter::new(limits) };
+    // Another worker sharing the limiter is handling a request.
+    let other = ctx.limiter.clone().begin_request().unwrap();
+    // The worker counts the request before it is dispatched.
+    let current = ctx.limiter.count_request();
+    assert_eq!(Chain::<Shed, App>::request(request(), &mut ctx).status, StatusCode::Ok);
+    assert_eq!(ctx.limiter.begin_request().err(), Some(Exceeded::InFlight));
+    let _third = ctx.limiter.count_request();
+    let res = Chain::<Shed, App>::request(request(), &mut ctx);
+    assert_eq!(res.status, StatusCode::ServiceUnavailable);
+    assert_eq!(res.get_value_header("Retry-After"), Some(&b"5"[..]));
+    drop(other);
+    drop(current);
+    assert_eq!(ctx.limiter.in_flight(), 1);
+}
@@ -0,0 +1,96 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use kinglet::{Handler, Message, Request, Response, StatusCode};
+use kinglet::middleware::{Chain, Middleware};
+use common::request;
+
+#[derive(Default)]
+struct Context {
+    trace: Vec<&'static str>,
+}
+
+struct App;
+
+impl Handler<Context> for App {
+    fn request(req: Request, ctx: &mut Context) -> Response {
+        ctx.trace.push("handler");
+        let mut res = Response::new(req.version);
+        res.put_body(req.get_value_header("X-User").unwrap_or(b"nobody"));
+        res
+    }
+}
+
+/// Rejects requests without an `Authorization` header.
+struct Auth;
+
+impl Middleware<Context> for Auth {
+    type State = ();
+
+    fn before(req: &mut Request, ctx: &mut Context) -> Result<(), Response> {
+        ctx.trace.push("auth before");
+        if req.contains_header("Authorization") {
+            req.headers_mut().set("X-User", b"alice".to_vec());
+            Ok(())
+        } else {
+            let mut res = Response::new(req.version);
+            res.status = StatusCode::Unauthorized;
+            res.put_body("");
+            Err(res)
+        }
+    }
+
+    fn after(_: (), _: &mut Response, ctx: &mut Context) {
+        ctx.trace.push("auth after");
+    }
+}
+
+/// Adds a header with the request method to the response.
+struct Method;
+
+impl Middleware<Context> for Method {
+    type State = String;
+
+    fn before(req: &mut Request, ctx: &mut Context) -> Result<String, Response> {
+        ctx.trace.push("method before");
+        Ok(req.method.to_string())
+    }
+
+    fn after(method: String, res: &mut Response, ctx: &mut Context) {
+        ctx.trace.push("method after");
+        res.headers_mut().set("X-Method", method.into_bytes());
+    }
+}
+
+type Stack = Chain<(Method, Auth), App>;
+
+#[test]
+fn order() {
+    let mut ctx = Context::default();
+    let res = Stack::request(request(b"GET / HTTP/1.1\r\nAuthorization: x\r\n\r\n"), &mut ctx);
+    assert_eq!(res.status, StatusCode::Ok);
+    assert_eq!(res.get_value_header("X-Method"), Some(&b"GET"[..]));
+    assert_eq!(ctx.trace,
+               vec!["method before", "auth before", "handler", "auth after", "method after"]);
+}
+
+#[test]
+fn short_circuit() {
+    let mut ctx = Context::default();
+    let res = Stack::request(request(b"POST / HTTP/1.1\r\n\r\n"), &mut ctx);
+    assert_eq!(res.status, StatusCode::Unauthorized);
+    assert_eq!(res.get_value_header("X-Method"), Some(&b"POST"[..]));
+    assert_eq!(ctx.trace, vec!["method before", "auth before", "method after"]);
+}
+
+#[test]
+fn nested_chains() {
+    let mut ctx = Context::default();
+    let req = request(b"GET / HTTP/1.1\r\nAuthorization: x\r\n\r\n");
+    Chain::<(Method, Method, Auth), Chain<Method, App>>::request(req, &mut ctx);
+    assert_eq!(ctx.trace,
+               vec!["method before", "method before", "auth before", "method before",
+                    "handler", "method after", "auth after", "method after", "method after"]);
+}
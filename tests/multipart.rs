@@ -0,0 +1,162 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use std::fs::File;
+use std::io::Read;
+
+use kinglet::{Error, Headers};
+use kinglet::multipart::{self, Limits, Parser, PartData, Sink};
+use common::request_with_body;
+
+const BODY: &'static [u8] = b"preamble\r\n--AaB03x\r\n\
+    Content-Disposition: form-data; name=\"submit-name\"\r\n\r\n\
+    Larry\r\n\
+    --AaB03x\r\n\
+    Content-Disposition: form-data; name=\"files\"; filename=\"file1.txt\"\r\n\
+    Content-Type: text/plain\r\n\r\n\
+    ... contents of file1.txt ...\r\n--AaB03\r\n\
+    --AaB03x--\r\nepilogue";
+
+#[derive(Debug, Default, PartialEq)]
+struct Events(Vec<String>);
+
+impl Sink for Events {
+    fn part_begin(&mut self, headers: Headers) -> kinglet::Result<()> {
+        self.0.push(format!("begin {}", headers.iter().count()));
+        Ok(())
+    }
+
+    fn part_data(&mut self, data: &[u8]) -> kinglet::Result<()> {
+        self.0.push(String::from_utf8(data.to_vec()).unwrap());
+        Ok(())
+    }
+
+    fn part_end(&mut self) -> kinglet::Result<()> {
+        self.0.push("end".to_owned());
+        Ok(())
+    }
+}
+
+fn joined(events: Events) -> Vec<String> {
+    let mut joined: Vec<String> = Vec::new();
+    for event in events.0 {
+        let is_data = !event.starts_with("begin") && event != "end";
+        match joined.last_mut() {
+            Some(last) if is_data && !last.starts_with("begin") && last != "end" => {
+                last.push_str(&event);
+                continue;
+            }
+            _ => (),
+        }
+        joined.push(event);
+    }
+    joined
+}
+
+#[test]
+fn parse_in_one_piece() {
+    let mut parser = Parser::new(b"AaB03x", Limits::default());
+    let mut events = Events::default();
+    parser.feed(BODY, &mut events).unwrap();
+    parser.finish().unwrap();
+    assert_eq!(joined(events),
+               vec!["begin 1", "Larry", "end", "begin 2",
+                    "... contents of file1.txt ...\r\n--AaB03", "end"]);
+}
+
+#[test]
+fn parse_byte_by_byte() {
+    let mut parser = Parser::new(b"AaB03x", Limits::default());
+    let mut events = Events::default();
+    for byte in BODY.chunks(1) {
+        parser.feed(byte, &mut events).unwrap();
+    }
+    parser.finish().unwrap();
+    assert_eq!(joined(events),
+               vec!["begin 1", "Larry", "end", "begin 2",
+                    "... contents of file1.txt ...\r\n--AaB03", "end"]);
+}
+
+#[test]
+fn boundary_after_line_break() {
+    let body = b"x--AaB03x\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nno\r\n\
+                 --AaB03x\r\nContent-Disposition: form-data; name=\"b\"\r\n\r\nyes\r\n\
+                 --AaB03x--";
+    for size in &[1, body.len()] {
+        let mut parser = Parser::new(b"AaB03x", Limits::default());
+        let mut events = Events::default();
+        for piece in body.chunks(*size) {
+            parser.feed(piece, &mut events).unwrap();
+        }
+        parser.finish().unwrap();
+        assert_eq!(joined(events), vec!["begin 1", "yes", "end"]);
+    }
+}
+
+#[test]
+fn incomplete_body() {
+    let mut parser = Parser::new(b"AaB03x", Limits::default());
+    let mut events = Events::default();
+    parser.feed(&BODY[..60], &mut events).unwrap();
+    assert_eq!(parser.finish(), Err(Error::InvalidMessage));
+}
+
+#[test]
+fn limits() {
+    let mut limits = Limits::default();
+    limits.max_part_size = 10;
+    let mut parser = Parser::new(b"AaB03x", limits);
+    assert_eq!(parser.feed(BODY, &mut Events::default()), Err(Error::TooLarge));
+    let mut limits = Limits::default();
+    limits.max_parts = 1;
+    let mut parser = Parser::new(b"AaB03x", limits);
+    assert_eq!(parser.feed(BODY, &mut Events::default()), Err(Error::TooLarge));
+    let mut limits = Limits::default();
+    limits.max_total_size = 100;
+    let mut parser = Parser::new(b"AaB03x", limits);
+    assert_eq!(parser.feed(BODY, &mut Events::default()), Err(Error::TooLarge));
+}
+
+#[test]
+fn boundary() {
+    let req = request_with_body(b"POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=\"a b\"\r\n\r\n",
+                                b"");
+    assert_eq!(multipart::boundary(&req), Ok(b"a b".to_vec()));
+    let req = request_with_body(b"POST / HTTP/1.1\r\nContent-Type: multipart/form-data\r\n\r\n", b"");
+    assert_eq!(multipart::boundary(&req), Err(Error::InvalidHeader));
+    let req = request_with_body(b"POST / HTTP/1.1\r\nContent-Type: multipart/mixed; boundary=x\r\n\r\n",
+                                b"");
+    assert_eq!(multipart::boundary(&req), Err(Error::UnsupportedMediaType));
+}
+
+#[test]
+fn request_multipart() {
+    let req = request_with_body(b"POST / HTTP/1.1\r\nContent-Type: multipart/form-data; boundary=AaB03x\r\n\r\n",
+                                BODY);
+    let mut parts = req.multipart().unwrap();
+    assert_eq!(parts.len(), 2);
+    let file = parts.pop().unwrap();
+    let field = parts.pop().unwrap();
+    assert_eq!(field.name, Some("submit-name".to_owned()));
+    assert_eq!(field.filename, None);
+    match field.data {
+        PartData::Memory(ref value) => assert_eq!(value, b"Larry"),
+        PartData::File(_) => panic!("field stored in a file"),
+    }
+    assert_eq!(file.name, Some("files".to_owned()));
+    assert_eq!(file.filename, Some("file1.txt".to_owned()));
+    assert_eq!(file.headers.get("Content-Type"), Some(&b"text/plain"[..]));
+    let path = match file.data {
+        PartData::File(ref temp_file) => {
+            let mut contents = Vec::new();
+            File::open(temp_file.path()).unwrap().read_to_end(&mut contents).unwrap();
+            assert_eq!(contents, b"... contents of file1.txt ...\r\n--AaB03");
+            temp_file.path().to_owned()
+        }
+        PartData::Memory(_) => panic!("file kept in memory"),
+    };
+    drop(file);
+    assert!(!path.exists());
+}
@@ -0,0 +1,69 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use kinglet::Error;
+use kinglet::negotiation;
+use common::request;
+
+#[test]
+fn media_type_quality() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept: text/html;q=0.5, application/json\r\n\r\n");
+    assert_eq!(negotiation::media_type(&req, &["text/html", "application/json"]),
+               Ok("application/json"));
+}
+
+#[test]
+fn media_type_specificity() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept: text/*;q=0.3, text/html;q=0.7, \
+                        text/html;level=1, */*;q=0.5\r\n\r\n");
+    assert_eq!(negotiation::media_type(&req, &["text/plain", "image/png"]), Ok("image/png"));
+    assert_eq!(negotiation::media_type(&req, &["text/html", "image/png"]), Ok("text/html"));
+    assert_eq!(negotiation::media_type(&req, &["image/png", "text/html; level=1"]),
+               Ok("text/html; level=1"));
+}
+
+#[test]
+fn media_type_not_acceptable() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept: text/html, */*;q=0\r\n\r\n");
+    let err = negotiation::media_type(&req, &["application/json"]).unwrap_err();
+    assert_eq!(err, Error::NotAcceptable);
+    assert_eq!(err.status(), kinglet::StatusCode::NotAcceptable);
+}
+
+#[test]
+fn missing_header() {
+    let req = request(b"GET / HTTP/1.1\r\n\r\n");
+    assert_eq!(negotiation::media_type(&req, &["text/plain", "text/html"]), Ok("text/plain"));
+    assert_eq!(negotiation::language(&req, &[]), Err(Error::NotAcceptable));
+}
+
+#[test]
+fn language_prefix() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Language: de-CH, en;q=0.8, *;q=0.1\r\n\r\n");
+    assert_eq!(negotiation::language(&req, &["fr", "en-US", "de"]), Ok("en-US"));
+    assert_eq!(negotiation::language(&req, &["fr", "de-ch"]), Ok("de-ch"));
+    assert_eq!(negotiation::language(&req, &["fr"]), Ok("fr"));
+}
+
+#[test]
+fn encoding_identity() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: gzip;q=0.5, br\r\n\r\n");
+    assert_eq!(negotiation::encoding(&req, &["deflate", "identity"]), Ok("identity"));
+    assert_eq!(negotiation::encoding(&req, &["identity", "gzip"]), Ok("identity"));
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: gzip, identity;q=0\r\n\r\n");
+    assert_eq!(negotiation::encoding(&req, &["identity"]), Err(Error::NotAcceptable));
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: *;q=0\r\n\r\n");
+    assert_eq!(negotiation::encoding(&req, &["identity", "gzip"]), Err(Error::NotAcceptable));
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Encoding: \r\n\r\n");
+    assert_eq!(negotiation::encoding(&req, &["gzip", "identity"]), Ok("identity"));
+    assert_eq!(negotiation::encoding(&req, &["gzip"]), Err(Error::NotAcceptable));
+}
+
+#[test]
+fn charset() {
+    let req = request(b"GET / HTTP/1.1\r\nAccept-Charset: iso-8859-5, UTF-8;q=0.8\r\n\r\n");
+    assert_eq!(negotiation::charset(&req, &["utf-8", "ISO-8859-5"]), Ok("ISO-8859-5"));
+    assert_eq!(negotiation::charset(&req, &["us-ascii"]), Err(Error::NotAcceptable));
+}
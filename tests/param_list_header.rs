@@ -0,0 +1,64 @@
+extern crate kinglet;
+
+use kinglet::{IterParamListHeader, ListItem};
+
+fn item(value: &[u8], params: &[(&[u8], &[u8])]) -> ListItem {
+    ListItem {
+        value: value.to_vec(),
+        params: params.iter().map(|&(n, v)| (n.to_vec(), v.to_vec())).collect(),
+    }
+}
+
+#[test]
+fn test1() {
+    let values = vec![b"foo, bar".to_vec()];
+    let mut iter = IterParamListHeader::new(&values);
+    assert_eq!(iter.next(), Some(item(b"foo", &[])));
+    assert_eq!(iter.next(), Some(item(b"bar", &[])));
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test2() {
+    let values = vec![b"text/html;q=\"0.5,x\", text/plain".to_vec()];
+    let mut iter = IterParamListHeader::new(&values);
+    assert_eq!(iter.next(), Some(item(b"text/html", &[(b"q", b"0.5,x")])));
+    assert_eq!(iter.next(), Some(item(b"text/plain", &[])));
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test3() {
+    let values = vec![b"199 - \"a, b\"".to_vec(), b"214 - \"c\"".to_vec()];
+    let mut iter = IterParamListHeader::new(&values);
+    assert_eq!(iter.next(), Some(item(b"199 - \"a, b\"", &[])));
+    assert_eq!(iter.next(), Some(item(b"214 - \"c\"", &[])));
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test4() {
+    let values = vec![b"text/*; level = 1 ;q=0.3;Charset=\"utf-8\" ,, */*;q=0".to_vec()];
+    let mut iter = IterParamListHeader::new(&values);
+    let first = iter.next().unwrap();
+    assert_eq!(first, item(b"text/*", &[(b"level", b"1"), (b"q", b"0.3"), (b"Charset", b"utf-8")]));
+    assert_eq!(first.param("charset"), Some(&b"utf-8"[..]));
+    assert_eq!(first.param("format"), None);
+    assert_eq!(iter.next(), Some(item(b"*/*", &[(b"q", b"0")])));
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test5() {
+    let values = vec![b"\"quoted \\\"value\\\"\"; a=\"x\\\\y\"; flag".to_vec()];
+    let mut iter = IterParamListHeader::new(&values);
+    assert_eq!(iter.next(), Some(item(b"quoted \"value\"", &[(b"a", b"x\\y"), (b"flag", b"")])));
+    assert!(iter.next().is_none());
+}
+
+#[test]
+fn test6() {
+    let values = vec![b" , ;q=1, ".to_vec(), b"\t".to_vec()];
+    let mut iter = IterParamListHeader::new(&values);
+    assert!(iter.next().is_none());
+}
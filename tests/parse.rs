@@ -182,3 +182,195 @@ fn trailing_header_fields() {
     }
     assert!(inbuf.empty());
 }
+
+#[test]
+fn file_body_waits_for_head() {
+    use std::env;
+    use std::fs::File;
+    use std::io::Write;
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct FileHandler;
+    impl Handler<()> for FileHandler {
+        fn request(_: Request, _: &mut ()) -> Response {
+            let path = env::temp_dir().join("kinglet-parse-file-body");
+            File::create(&path).unwrap().write_all(b"Hello World!").unwrap();
+            let mut res = Response::new(HttpVersion::Http11);
+            res.put_file(File::open(&path).unwrap()).unwrap();
+            res
+        }
+    }
+    let mut inbuf = Buf::new();
+    let mut outbuf = Buf::new();
+    let client = Client::Initial::<(), FileHandler>;
+    inbuf.extend(b"GET /a HTTP/1.1\r\nHost: example.com\r\n\r\nGET /b HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    let client = {
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        match client.data_received_from(&mut transport, &mut (), None) {
+            Async::Continue(client @ Client::Sending(..), ()) => client,
+            _ => panic!("not sending the file"),
+        }
+    };
+    assert!(outbuf[..].ends_with(b"Content-Length: 12\r\n\r\n"));
+    // The second request is parsed after the file was sent.
+    assert_eq!(&inbuf[..], &b"GET /b HTTP/1.1\r\nHost: example.com\r\n\r\n"[..]);
+    {
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        match client.data_received_from(&mut transport, &mut (), None) {
+            Async::Continue(Client::Sending(ref file, false), ()) => assert_eq!(file.len(), 12),
+            _ => panic!("not sending the file"),
+        }
+    }
+
+    // Without the socket the file is copied to the output buffer.
+    let len = outbuf.len();
+    outbuf.consume(len);
+    inbuf.extend(b"GET /c HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    {
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        assert_eq!(Client::Initial::<(), FileHandler>.data_received(&mut transport, &mut ()),
+                   Async::Continue(Client::KeepAlive, ()));
+    }
+    assert!(outbuf[..].ends_with(b"Content-Length: 12\r\n\r\nHello World!"));
+}
+
+#[test]
+fn close_after_flush() {
+    #[derive(Debug, Eq, PartialEq)]
+    struct ClosingHandler;
+    impl Handler<()> for ClosingHandler {
+        fn request(_: Request, _: &mut ()) -> Response {
+            let mut res = Response::new(HttpVersion::Http11);
+            res.headers_mut().set("Connection", b"close".to_vec());
+            res.put_body("");
+            res
+        }
+    }
+    let mut inbuf = Buf::new();
+    let mut outbuf = Buf::new();
+    let client = Client::Initial::<(), ClosingHandler>;
+    inbuf.extend(b"GET /a HTTP/1.1\r\nHost: example.com\r\n\r\nGET /b HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    let client = {
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        match client.data_received(&mut transport, &mut ()) {
+            Async::Continue(client, ()) => client,
+            Async::Stop => panic!("stopped before the response was sent"),
+        }
+    };
+    assert_eq!(client, Client::Closing);
+    assert!(outbuf[..].starts_with(b"HTTP/1.1 200 OK\r\n"));
+    // Requests after the close are discarded.
+    assert!(inbuf.empty());
+    let len = outbuf.len();
+    outbuf.consume(len);
+    let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+    assert_eq!(client.data_received(&mut transport, &mut ()), Async::Stop);
+}
+
+#[test]
+fn multipart_while_received() {
+    use kinglet::multipart::{Multipart, PartData, Parts};
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct FormHandler;
+    impl Handler<()> for FormHandler {
+        fn request(mut req: Request, _: &mut ()) -> Response {
+            assert!(req.body.is_empty());
+            let Parts(parts) = req.extensions_mut().remove::<Parts>().unwrap();
+            assert_eq!(parts.len(), 1);
+            assert_eq!(parts[0].name, Some("a".to_owned()));
+            match parts[0].data {
+                PartData::Memory(ref value) => assert_eq!(value, b"Hello"),
+                PartData::File(_) => panic!("field stored in a file"),
+            }
+            let mut res = Response::new(HttpVersion::Http11);
+            res.put_body("");
+            res
+        }
+    }
+    let mut inbuf = Buf::new();
+    let mut outbuf = Buf::new();
+    let mut client = Client::Initial::<(), Multipart<FormHandler>>;
+    inbuf.extend(b"POST /form HTTP/1.1\r\nHost: example.com\r\n\
+                   Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                   Transfer-Encoding: chunked\r\n\r\n");
+    let body = b"--AaB03x\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nHello\r\n--AaB03x--";
+    for piece in body.chunks(7) {
+        inbuf.extend(format!("{:x}\r\n", piece.len()).as_bytes());
+        inbuf.extend(piece);
+        inbuf.extend(b"\r\n");
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        client = match client.data_received(&mut transport, &mut ()) {
+            Async::Continue(client @ Client::ReadChunked(..), ()) => client,
+            _ => panic!("body not read in pieces"),
+        };
+    }
+    inbuf.extend(b"0\r\n\r\n");
+    let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+    assert!(client.data_received(&mut transport, &mut ()) == Async::Continue(Client::KeepAlive, ()));
+}
+
+#[test]
+fn multipart_rejected_while_received() {
+    use kinglet::multipart::Multipart;
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct FormHandler;
+    impl Handler<()> for FormHandler {
+        fn request(_: Request, _: &mut ()) -> Response {
+            panic!("handler called for a malformed body");
+        }
+    }
+    let mut inbuf = Buf::new();
+    let mut outbuf = Buf::new();
+    let client = Client::Initial::<(), Multipart<FormHandler>>;
+    inbuf.extend(b"POST /form HTTP/1.1\r\nHost: example.com\r\n\
+                   Content-Type: multipart/form-data; boundary=AaB03x\r\n\
+                   Content-Length: 1000\r\n\r\n--AaB03x\r\n\r\n");
+    {
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        assert!(client.data_received(&mut transport, &mut ()) == Async::Continue(Client::Closing, ()));
+    }
+    assert!(outbuf[..].starts_with(b"HTTP/1.1 400 Bad Request\r\n"));
+    assert!(inbuf.empty());
+}
+
+#[test]
+fn stream_body_waits_for_connection() {
+    use std::io::Write;
+
+    #[derive(Debug, Eq, PartialEq)]
+    struct StreamHandler;
+    impl Handler<()> for StreamHandler {
+        fn request(_: Request, _: &mut ()) -> Response {
+            let mut res = Response::new(HttpVersion::Http11);
+            res.put_stream(|w: &mut Write| w.write_all(b"Hello World!"));
+            res
+        }
+    }
+    let mut inbuf = Buf::new();
+    let mut outbuf = Buf::new();
+    inbuf.extend(b"GET /a HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    let mut stream = {
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        match Client::Initial::<(), StreamHandler>.data_received_from(&mut transport, &mut (), None) {
+            Async::Continue(Client::Streaming(stream, false), ()) => stream,
+            _ => panic!("not streaming the body"),
+        }
+    };
+    assert!(outbuf[..].ends_with(b"Transfer-Encoding: chunked\r\n\r\n"));
+    let mut body = Vec::new();
+    while !stream.poll_to(&mut body).unwrap() {}
+    assert_eq!(body, b"c\r\nHello World!\r\n0\r\n\r\n");
+
+    // Without the socket the body is written to the output buffer at once.
+    let len = outbuf.len();
+    outbuf.consume(len);
+    inbuf.extend(b"GET /b HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    {
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        assert_eq!(Client::Initial::<(), StreamHandler>.data_received(&mut transport, &mut ()),
+                   Async::Continue(Client::KeepAlive, ()));
+    }
+    assert!(outbuf[..].ends_with(b"\r\n\r\nc\r\nHello World!\r\n0\r\n\r\n"));
+}
@@ -0,0 +1,141 @@
+extern crate httparse;
+extern crate kinglet;
+extern crate netbuf;
+extern crate rotor;
+
+mod common;
+
+use std::sync::mpsc;
+
+use netbuf::Buf;
+use rotor::async::Async;
+use rotor::transports::stream::{Protocol, Transport};
+
+use kinglet::{Body, Message, Reply, Request, Response, StatusCode};
+use kinglet::http1::{Client, Handler};
+use kinglet::middleware::{Chain, Middleware};
+use kinglet::pool::ThreadPool;
+
+fn request() -> Request {
+    common::request(b"GET /work HTTP/1.1\r\n\r\n")
+}
+
+fn work(req: Request) -> Response {
+    let mut res = Response::new(req.version);
+    res.put_body(req.path());
+    res
+}
+
+#[test]
+fn offload() {
+    let pool = ThreadPool::new(2, 4);
+    let (sender, receiver) = mpsc::channel();
+    let pending = pool.offload_with(request(), work, move || sender.send(()).unwrap()).unwrap();
+    receiver.recv().unwrap();
+    let res = pending.poll().unwrap();
+    match res.body() {
+        Some(&Body::Bytes(ref bytes)) => assert_eq!(bytes, b"/work"),
+        _ => panic!("no fixed size body"),
+    }
+}
+
+#[test]
+fn saturated() {
+    let pool = ThreadPool::new(1, 1);
+    let (sender, receiver) = mpsc::channel::<()>();
+    let (started_sender, started) = mpsc::channel();
+    let blocked = pool.offload(request(), move |req| {
+                          started_sender.send(()).unwrap();
+                          receiver.recv().unwrap();
+                          work(req)
+                      })
+                      .unwrap();
+    // Wait until the thread took the first job from the queue.
+    started.recv().unwrap();
+    let queued = pool.offload(request(), work).unwrap();
+    let res = pool.offload(request(), work).err().unwrap();
+    assert_eq!(res.status, StatusCode::ServiceUnavailable);
+    assert_eq!(res.get_value_header("Retry-After"), Some(&b"1"[..]));
+    assert!(blocked.poll().is_none());
+    sender.send(()).unwrap();
+    assert_eq!(blocked.wait().status, StatusCode::Ok);
+    assert_eq!(queued.wait().status, StatusCode::Ok);
+}
+
+#[test]
+fn panicking_handler() {
+    let pool = ThreadPool::new(1, 1);
+    let pending = pool.offload(request(), |_| panic!("handler failed")).unwrap();
+    assert_eq!(pending.wait().status, StatusCode::InternalServerError);
+}
+
+struct Offload;
+
+impl Handler<ThreadPool> for Offload {
+    fn request(req: Request, pool: &mut ThreadPool) -> Response {
+        Self::reply(req, pool).wait(pool)
+    }
+
+    fn reply(req: Request, pool: &mut ThreadPool) -> Reply<ThreadPool> {
+        match pool.offload(req, work) {
+            Ok(pending) => Reply::Pending(pending),
+            Err(res) => Reply::Ready(res),
+        }
+    }
+}
+
+#[test]
+fn connection_waits() {
+    let mut pool = ThreadPool::new(1, 1);
+    let mut inbuf = Buf::new();
+    let mut outbuf = Buf::new();
+    inbuf.extend(b"GET /work HTTP/1.1\r\nHost: example.com\r\n\r\n");
+    let client = Client::Initial::<ThreadPool, Offload>;
+    let client = {
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        match client.data_received(&mut transport, &mut pool) {
+            Async::Continue(client @ Client::Waiting(..), ()) => client,
+            _ => panic!("not waiting for the pool"),
+        }
+    };
+    assert!(outbuf.empty());
+    let (sender, receiver) = mpsc::channel();
+    if let Client::Waiting(ref pending, _) = client {
+        pending.on_ready(move || sender.send(()).unwrap());
+    }
+    receiver.recv().unwrap();
+    {
+        let mut transport = Transport::new(&mut inbuf, &mut outbuf);
+        match client.data_received(&mut transport, &mut pool) {
+            Async::Continue(Client::KeepAlive, ()) => (),
+            _ => panic!("the response was not sent"),
+        }
+    }
+    assert!(outbuf[..].ends_with(b"\r\n\r\n/work"));
+}
+
+struct Served;
+
+impl Middleware<ThreadPool> for Served {
+    type State = ();
+
+    fn before(_: &mut Request, _: &mut ThreadPool) -> Result<(), Response> {
+        Ok(())
+    }
+
+    fn after(_: (), res: &mut Response, _: &mut ThreadPool) {
+        res.headers_mut().set("X-Served", b"pool".to_vec());
+    }
+}
+
+#[test]
+fn chain_waits() {
+    let mut pool = ThreadPool::new(1, 1);
+    let reply = Chain::<Served, Offload>::reply(request(), &mut pool);
+    let reply = match reply {
+        reply @ Reply::Mapped(..) => reply,
+        _ => panic!("the reply is not pending"),
+    };
+    let res = reply.wait(&mut pool);
+    assert_eq!(res.get_value_header("X-Served"), Some(&b"pool"[..]));
+}
@@ -0,0 +1,95 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use kinglet::{Body, Message, Request, Response, StatusCode};
+use kinglet::range::{self, ByteRange};
+use common::request;
+
+fn response(req: &Request) -> Response {
+    let mut res = Response::new(req.version);
+    res.headers_mut().set("Content-Type", b"text/plain".to_vec());
+    res.headers_mut().set("ETag", b"\"abc\"".to_vec());
+    res.put_body("0123456789");
+    range::apply(req, &mut res).unwrap();
+    res
+}
+
+fn body(res: &Response) -> &[u8] {
+    match res.body() {
+        Some(&Body::Bytes(ref bytes)) => bytes,
+        _ => panic!("no fixed size body"),
+    }
+}
+
+#[test]
+fn parse_ranges() {
+    assert_eq!(range::parse(b"bytes=0-499, 500-, -200"),
+               Some(vec![ByteRange::FromTo(0, 499), ByteRange::From(500), ByteRange::Last(200)]));
+    assert_eq!(range::parse(b"bytes=5-1"), None);
+    assert_eq!(range::parse(b"items=0-1"), None);
+    assert_eq!(range::parse(b"Bytes=1-2"), Some(vec![ByteRange::FromTo(1, 2)]));
+    assert_eq!(range::parse(b"bytes=-"), None);
+    assert_eq!(ByteRange::Last(20).resolve(10), Some((0, 9)));
+    assert_eq!(ByteRange::FromTo(5, 100).resolve(10), Some((5, 9)));
+    assert_eq!(ByteRange::From(10).resolve(10), None);
+}
+
+#[test]
+fn single_range() {
+    let res = response(&request(b"GET / HTTP/1.1\r\nRange: bytes=2-4\r\n\r\n"));
+    assert_eq!(res.status, StatusCode::PartialContent);
+    assert_eq!(res.get_value_header("Content-Range"), Some(&b"bytes 2-4/10"[..]));
+    assert_eq!(body(&res), b"234");
+}
+
+#[test]
+fn multiple_ranges() {
+    let res = response(&request(b"GET / HTTP/1.1\r\nRange: bytes=0-1,-2\r\n\r\n"));
+    assert_eq!(res.status, StatusCode::PartialContent);
+    let content_type = res.content_type().unwrap();
+    assert_eq!(content_type.value, b"multipart/byteranges");
+    let boundary = String::from_utf8(content_type.param("boundary").unwrap().to_vec()).unwrap();
+    let expected = format!("\r\n--{0}\r\nContent-Type: text/plain\r\n\
+                            Content-Range: bytes 0-1/10\r\n\r\n01\
+                            \r\n--{0}\r\nContent-Type: text/plain\r\n\
+                            Content-Range: bytes 8-9/10\r\n\r\n89\r\n--{0}--\r\n",
+                           boundary);
+    assert_eq!(body(&res), expected.as_bytes());
+}
+
+#[test]
+fn coalesce_ranges() {
+    let mut header = b"GET / HTTP/1.1\r\nRange: bytes=0-".to_vec();
+    for _ in 0..15 {
+        header.extend(b",0-");
+    }
+    header.extend(b"\r\n\r\n");
+    let res = response(&request(&header));
+    assert_eq!(res.status, StatusCode::PartialContent);
+    assert_eq!(res.get_value_header("Content-Range"), Some(&b"bytes 0-9/10"[..]));
+    assert_eq!(body(&res), b"0123456789");
+    let res = response(&request(b"GET / HTTP/1.1\r\nRange: bytes=8-9,2-3,0-1\r\n\r\n"));
+    let body = String::from_utf8(body(&res).to_vec()).unwrap();
+    assert!(body.contains("Content-Range: bytes 0-3/10\r\n\r\n0123"));
+    assert!(body.contains("Content-Range: bytes 8-9/10\r\n\r\n89"));
+    assert_eq!(body.matches("Content-Range").count(), 2);
+}
+
+#[test]
+fn unsatisfiable_range() {
+    let res = response(&request(b"GET / HTTP/1.1\r\nRange: bytes=10-\r\n\r\n"));
+    assert_eq!(res.status, StatusCode::RangeNotSatisfiable);
+    assert_eq!(res.get_value_header("Content-Range"), Some(&b"bytes */10"[..]));
+}
+
+#[test]
+fn if_range() {
+    let res = response(&request(b"GET / HTTP/1.1\r\nRange: bytes=0-0\r\nIf-Range: \"abc\"\r\n\r\n"));
+    assert_eq!(res.status, StatusCode::PartialContent);
+    let res = response(&request(b"GET / HTTP/1.1\r\nRange: bytes=0-0\r\nIf-Range: \"xyz\"\r\n\r\n"));
+    assert_eq!(res.status, StatusCode::Ok);
+    assert_eq!(body(&res), b"0123456789");
+    assert_eq!(res.get_value_header("Accept-Ranges"), Some(&b"bytes"[..]));
+}
@@ -0,0 +1,124 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use std::time::{Duration, Instant};
+
+use kinglet::{Handler, Message, Request, Response, StatusCode};
+use kinglet::access_log::PeerAddr;
+use kinglet::middleware::Chain;
+use kinglet::rate_limit::{Algorithm, Key, Keyless, Quota, RateLimited, RateLimiter, Throttle};
+use common::request;
+
+#[test]
+fn token_bucket() {
+    let limiter = RateLimiter::new(Quota::per_second(2));
+    let start = Instant::now();
+    let first = limiter.check_key("a", start);
+    assert!(first.allowed);
+    assert_eq!(first.remaining, 1);
+    assert!(limiter.check_key("a", start).allowed);
+    let denied = limiter.check_key("a", start);
+    assert!(!denied.allowed);
+    assert_eq!(denied.remaining, 0);
+    assert_eq!(denied.retry_after, Duration::from_millis(500));
+    // Other keys have their own bucket.
+    assert!(limiter.check_key("b", start).allowed);
+    assert!(limiter.check_key("a", start + Duration::from_millis(500)).allowed);
+    assert!(!limiter.check_key("a", start + Duration::from_millis(600)).allowed);
+}
+
+#[test]
+fn sliding_window() {
+    let limiter = RateLimiter::new(Quota::per_minute(4)).algorithm(Algorithm::SlidingWindow);
+    let start = Instant::now();
+    for _ in 0..4 {
+        assert!(limiter.check_key("a", start).allowed);
+    }
+    let denied = limiter.check_key("a", start + Duration::from_secs(30));
+    assert!(!denied.allowed);
+    assert_eq!(denied.retry_after, Duration::from_secs(30));
+    // A sixth into the next window five sixths of the previous requests count.
+    let next = start + Duration::from_secs(70);
+    let decision = limiter.check_key("a", next);
+    assert!(decision.allowed);
+    assert_eq!(decision.remaining, 0);
+    let denied = limiter.check_key("a", next);
+    assert!(!denied.allowed);
+    assert_eq!(denied.retry_after, Duration::from_secs(5));
+    assert!(limiter.check_key("a", start + Duration::from_secs(180)).allowed);
+}
+
+#[test]
+fn header_key() {
+    fn user(req: &Request) -> Option<String> {
+        req.params().get("user").map(|user| user.to_owned())
+    }
+    let limiter = RateLimiter::new(Quota::per_minute(1)).key(Key::Header("X-Api-Key"));
+    let a = request(b"GET / HTTP/1.1\r\nX-Api-Key: a\r\n\r\n");
+    let b = request(b"GET / HTTP/1.1\r\nX-Api-Key: b\r\n\r\n");
+    assert_eq!(Key::Header("X-Api-Key").of(&a), Some("a".to_owned()));
+    assert_eq!(Key::Custom(user).of(&a), None);
+    assert!(limiter.check(&a).unwrap().allowed);
+    assert!(limiter.check(&b).unwrap().allowed);
+    assert!(!limiter.check(&a).unwrap().allowed);
+    // Requests without a key and a client address are rejected unless they are allowed.
+    let mut none = request(b"GET / HTTP/1.1\r\n\r\n");
+    assert!(!limiter.check(&none).unwrap().allowed);
+    assert_eq!(limiter.clone().keyless(Keyless::Allow).check(&none), None);
+    assert!(!limiter.clone().keyless(Keyless::Reject).check(&none).unwrap().allowed);
+    // The client address is counted instead of the missing key.
+    none.extensions_mut().insert(PeerAddr("192.0.2.1:4000".parse().unwrap()));
+    assert!(limiter.check(&none).unwrap().allowed);
+    assert!(!limiter.check(&none).unwrap().allowed);
+}
+
+struct Context {
+    limiter: RateLimiter,
+}
+
+impl RateLimited for Context {
+    fn rate_limiter(&self) -> &RateLimiter {
+        &self.limiter
+    }
+}
+
+struct App;
+
+impl Handler<Context> for App {
+    fn request(req: Request, _: &mut Context) -> Response {
+        let mut res = Response::new(req.version);
+        res.put_body("ok");
+        res
+    }
+}
+
+fn from_peer() -> Request {
+    let mut req = request(b"GET / HTTP/1.1\r\n\r\n");
+    req.extensions_mut().insert(PeerAddr("192.0.2.1:4000".parse().unwrap()));
+    req
+}
+
+#[test]
+fn throttle() {
+    let mut ctx = Context { limiter: RateLimiter::new(Quota::per_minute(2)) };
+    let res = Chain::<Throttle, App>::request(from_peer(), &mut ctx);
+    assert_eq!(res.status, StatusCode::Ok);
+    assert_eq!(res.get_value_header("RateLimit-Limit"), Some(&b"2"[..]));
+    assert_eq!(res.get_value_header("RateLimit-Remaining"), Some(&b"1"[..]));
+    assert_eq!(res.get_value_header("RateLimit-Reset"), Some(&b"30"[..]));
+    assert_eq!(res.get_value_header("Retry-After"), None);
+    Chain::<Throttle, App>::request(from_peer(), &mut ctx);
+    let res = Chain::<Throttle, App>::request(from_peer(), &mut ctx);
+    assert_eq!(res.status, StatusCode::TooManyRequests);
+    assert_eq!(res.get_value_header("RateLimit-Remaining"), Some(&b"0"[..]));
+    assert_eq!(res.get_value_header("Retry-After"), Some(&b"30"[..]));
+    // Requests without a peer address are rejected.
+    let res = Chain::<Throttle, App>::request(request(b"GET / HTTP/1.1\r\n\r\n"), &mut ctx);
+    assert_eq!(res.status, StatusCode::TooManyRequests);
+    ctx.limiter = ctx.limiter.clone().keyless(Keyless::Allow);
+    let res = Chain::<Throttle, App>::request(request(b"GET / HTTP/1.1\r\n\r\n"), &mut ctx);
+    assert_eq!(res.status, StatusCode::Ok);
+    assert_eq!(res.get_value_header("RateLimit-Limit"), None);
+}
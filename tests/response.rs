@@ -0,0 +1,127 @@
+extern crate kinglet;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+
+use std::sync::mpsc;
+
+use kinglet::{FileBody, HttpVersion, Response};
+use kinglet::body::{BodyStream, MAX_QUEUED_CHUNKS};
+
+fn serialize(res: Response) -> Vec<u8> {
+    let mut out = Vec::new();
+    res.write_to(&mut out).unwrap();
+    let body_start = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+    let date_start = out.windows(6).position(|w| w == b"Date: ").unwrap();
+    let date_end = date_start + out[date_start..].iter().position(|b| *b == b'\n').unwrap() + 1;
+    assert!(date_end < body_start);
+    out.drain(date_start..date_end);
+    out
+}
+
+#[test]
+fn fixed_body() {
+    let mut res = Response::new(HttpVersion::Http11);
+    res.put_body("Hello World!");
+    assert_eq!(serialize(res),
+               b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nHello World!".to_vec());
+}
+
+#[test]
+fn no_body() {
+    let res = Response::new(HttpVersion::Http11);
+    assert_eq!(serialize(res), b"HTTP/1.1 200 OK\r\n\r\n".to_vec());
+}
+
+#[test]
+fn stream_body() {
+    let mut res = Response::new(HttpVersion::Http11);
+    res.put_stream(|w: &mut Write| {
+        try!(w.write_all(b"Hello "));
+        w.write_all(b"World!")
+    });
+    assert_eq!(serialize(res),
+               b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nc\r\nHello World!\r\n0\r\n\r\n"
+                   .to_vec());
+}
+
+#[test]
+fn serialize_stream_body() {
+    let mut res = Response::new(HttpVersion::Http11);
+    res.put_stream(|w: &mut Write| w.write_all(b"Hello World!"));
+    let mut out = Vec::new();
+    assert!(res.serialize(&mut out).is_err());
+    assert!(out.is_empty());
+    res.put_body("Hello World!");
+    res.serialize(&mut out).unwrap();
+    assert!(out.ends_with(b"Content-Length: 12\r\n\r\nHello World!"));
+}
+
+#[test]
+fn stream_body_http10() {
+    let mut res = Response::new(HttpVersion::Http10);
+    res.put_stream(|w: &mut Write| w.write_all(b"Hello World!"));
+    assert_eq!(serialize(res),
+               b"HTTP/1.0 200 OK\r\nContent-Length: 12\r\n\r\nHello World!".to_vec());
+}
+
+fn temp_file(name: &str, contents: &[u8]) -> File {
+    let path = env::temp_dir().join(format!("kinglet-response-{}", name));
+    File::create(&path).unwrap().write_all(contents).unwrap();
+    File::open(&path).unwrap()
+}
+
+#[test]
+fn file_body() {
+    let mut res = Response::new(HttpVersion::Http11);
+    res.put_file(temp_file("file_body", b"Hello World!")).unwrap();
+    assert_eq!(serialize(res),
+               b"HTTP/1.1 200 OK\r\nContent-Length: 12\r\n\r\nHello World!".to_vec());
+}
+
+#[cfg(unix)]
+#[test]
+fn send_file() {
+    use std::os::unix::net::UnixStream;
+    let (mut sender, mut receiver) = UnixStream::pair().unwrap();
+    let mut body = FileBody::range(temp_file("send_file", b"Hello World!"), 6, 5);
+    assert_eq!(body.send_to(&mut sender).unwrap(), 5);
+    assert!(body.is_empty());
+    drop(sender);
+    let mut received = Vec::new();
+    receiver.read_to_end(&mut received).unwrap();
+    assert_eq!(received, b"World");
+}
+
+#[test]
+fn body_stream() {
+    let (written, chunks) = mpsc::channel();
+    let mut stream = BodyStream::spawn(Box::new(move |w: &mut Write| {
+        for i in 0..10 {
+            try!(w.write_all(&[b'x'; 8192]));
+            written.send(i).unwrap();
+        }
+        Ok(())
+    }))
+                         .unwrap();
+    // The thread stops once the queue is full.
+    for _ in 0..MAX_QUEUED_CHUNKS {
+        chunks.recv().unwrap();
+    }
+    let (ready, wake) = mpsc::channel();
+    let mut body = Vec::new();
+    loop {
+        if stream.poll_to(&mut body).unwrap() {
+            break;
+        }
+        let ready = ready.clone();
+        stream.on_ready(move || {
+            let _ = ready.send(());
+        });
+        wake.recv().unwrap();
+    }
+    assert_eq!(chunks.iter().count(), 10 - MAX_QUEUED_CHUNKS);
+    assert_eq!(body.len(), 10 * (8192 + 8) + 5);
+    assert!(body.ends_with(b"\r\n0\r\n\r\n"));
+}
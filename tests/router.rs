@@ -0,0 +1,113 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use kinglet::{Body, Handler, Message, Method, Reply, Request, Response, StatusCode};
+use kinglet::router::{Router, Routing};
+use common::request;
+
+struct Context {
+    router: Router<Context>,
+}
+
+impl Routing for Context {
+    fn router(&self) -> &Router<Context> {
+        &self.router
+    }
+}
+
+fn echo(req: Request, _: &mut Context) -> Response {
+    let mut res = Response::new(req.version);
+    let params: Vec<String> = req.params()
+                                 .iter()
+                                 .map(|&(ref name, ref value)| format!("{}={}", name, value))
+                                 .collect();
+    res.put_body(format!("{} {}", req.method, params.join("&")));
+    res
+}
+
+fn reply(req: Request, ctx: &mut Context) -> Reply<Context> {
+    Reply::Ready(echo(req, ctx))
+}
+
+fn context() -> Context {
+    let users = Router::new().get("/", echo).get("/:id", echo).delete("/:id", echo);
+    Context {
+        router: Router::new()
+                    .get("/", echo)
+                    .route(Method::Post, "/upload", echo)
+                    .get("/static/*path", echo)
+                    .route_reply(Method::Get, "/reply/:id", reply)
+                    .mount("/orgs/:org/users", users),
+    }
+}
+
+fn handle(head: &[u8]) -> Response {
+    Router::request(request(head), &mut context())
+}
+
+fn body(res: &Response) -> &[u8] {
+    match res.body() {
+        Some(&Body::Bytes(ref bytes)) => bytes,
+        _ => panic!("no fixed size body"),
+    }
+}
+
+#[test]
+fn path_params() {
+    assert_eq!(body(&handle(b"GET / HTTP/1.1\r\n\r\n")), b"GET ");
+    let res = handle(b"GET /orgs/rust/users/caf%C3%A9 HTTP/1.1\r\n\r\n");
+    assert_eq!(body(&res), "GET org=rust&id=café".as_bytes());
+    let res = handle(b"DELETE /orgs/rust/users/1?force HTTP/1.1\r\n\r\n");
+    assert_eq!(body(&res), b"DELETE org=rust&id=1");
+    assert_eq!(body(&handle(b"GET /orgs/rust/users/ HTTP/1.1\r\n\r\n")), b"GET org=rust");
+}
+
+#[test]
+fn wildcard() {
+    let res = handle(b"GET /static/css/main.css HTTP/1.1\r\n\r\n");
+    assert_eq!(body(&res), b"GET path=css/main.css");
+    assert_eq!(body(&handle(b"GET /static HTTP/1.1\r\n\r\n")), b"GET path=");
+}
+
+#[test]
+fn not_found() {
+    assert_eq!(handle(b"GET /upload/more HTTP/1.1\r\n\r\n").status, StatusCode::NotFound);
+    assert_eq!(handle(b"GET /orgs/rust HTTP/1.1\r\n\r\n").status, StatusCode::NotFound);
+}
+
+#[test]
+fn method_not_allowed() {
+    let res = handle(b"PUT /orgs/rust/users/1 HTTP/1.1\r\n\r\n");
+    assert_eq!(res.status, StatusCode::MethodNotAllowed);
+    assert_eq!(res.get_value_header("Allow"), Some(&b"GET, HEAD, DELETE"[..]));
+    let res = handle(b"GET /upload HTTP/1.1\r\n\r\n");
+    assert_eq!(res.get_value_header("Allow"), Some(&b"POST"[..]));
+}
+
+#[test]
+fn head() {
+    let res = handle(b"HEAD /orgs/rust/users/1 HTTP/1.1\r\n\r\n");
+    assert_eq!(res.status, StatusCode::Ok);
+    assert!(res.body().is_none());
+    assert_eq!(res.get_value_header("Content-Length"), Some(&b"18"[..]));
+}
+
+#[test]
+#[should_panic]
+fn wildcard_not_last() {
+    Router::<Context>::new().get("/*path/edit", echo);
+}
+
+#[test]
+fn reply_route() {
+    let res = Router::reply(request(b"GET /reply/1 HTTP/1.1\r\n\r\n"), &mut context());
+    match res {
+        Reply::Ready(res) => assert_eq!(body(&res), b"GET id=1"),
+        _ => panic!("no ready response"),
+    }
+    let res = handle(b"HEAD /reply/1 HTTP/1.1\r\n\r\n");
+    assert!(res.body().is_none());
+    assert_eq!(res.get_value_header("Content-Length"), Some(&b"9"[..]));
+}
@@ -0,0 +1,224 @@
+extern crate kinglet;
+
+use std::env;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use kinglet::{HttpVersion, Request, Response};
+use kinglet::access_log::PeerAddr;
+use kinglet::http1::Handler;
+use kinglet::limits::{Limiter, Limits};
+use kinglet::server::Server;
+use kinglet::shutdown::Shutdown;
+
+struct App;
+
+impl Handler<usize> for App {
+    fn request(req: Request, worker: &mut usize) -> Response {
+        let mut res = Response::new(HttpVersion::Http11);
+        if req.path() != "/keep-alive" {
+            res.headers_mut().set("Connection", b"close".to_vec());
+        }
+        res.headers_mut().set("X-Worker", format!("{}", worker).into_bytes());
+        if req.path() == "/file" {
+            let path = env::temp_dir().join("kinglet-server-file-body");
+            File::create(&path).unwrap().write_all(b"Hello World!").unwrap();
+            res.put_file(File::open(&path).unwrap()).unwrap();
+        } else {
+            let peer = req.extensions().get::<PeerAddr>().unwrap().0;
+            res.put_body(format!("{}", peer));
+        }
+        res
+    }
+}
+
+struct Counter;
+
+impl Handler<Arc<AtomicUsize>> for Counter {
+    fn request(_req: Request, count: &mut Arc<AtomicUsize>) -> Response {
+        let mut res = Response::new(HttpVersion::Http11);
+        res.headers_mut().set("Connection", b"close".to_vec());
+        res.put_body(format!("{}", count.fetch_add(1, Ordering::SeqCst) + 1));
+        res
+    }
+}
+
+fn get(addr: SocketAddr, path: &str) -> (SocketAddr, String) {
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    (stream.local_addr().unwrap(), response)
+}
+
+#[test]
+fn bind_port_zero() {
+    let bound = Server::new("127.0.0.1:0".parse().unwrap())
+                    .workers(2)
+                    .reuse_port(false)
+                    .bind()
+                    .unwrap();
+    assert!(bound.local_addr().port() != 0);
+}
+
+#[test]
+#[should_panic]
+fn no_workers() {
+    Server::new("127.0.0.1:0".parse().unwrap()).workers(0);
+}
+
+#[test]
+fn serve_and_drain() {
+    let shutdown = Shutdown::new();
+    let bound = Server::new("127.0.0.1:0".parse().unwrap())
+                    .workers(2)
+                    .reuse_port(cfg!(unix))
+                    .shutdown(shutdown.clone())
+                    .bind()
+                    .unwrap();
+    let addr = bound.local_addr();
+    let server = thread::spawn(move || bound.run::<_, App, _>(|i| i));
+
+    let (local, response) = get(addr, "/peer");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.ends_with(&format!("\r\n\r\n{}", local)));
+
+    let (_, response) = get(addr, "/file");
+    assert!(response.contains("Content-Length: 12\r\n"));
+    assert!(response.ends_with("\r\n\r\nHello World!"));
+
+    shutdown.begin(Duration::from_secs(5));
+    server.join().unwrap().unwrap();
+    assert_eq!(shutdown.in_flight(), 0);
+}
+
+#[test]
+fn drain_after_request() {
+    let shutdown = Shutdown::new();
+    let bound = Server::new("127.0.0.1:0".parse().unwrap())
+                    .workers(1)
+                    .shutdown(shutdown.clone())
+                    .bind()
+                    .unwrap();
+    let addr = bound.local_addr();
+    let server = thread::spawn(move || bound.run::<_, App, _>(|i| i));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "POST /keep-alive HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nab")
+        .unwrap();
+    while shutdown.in_flight() == 0 {
+        thread::yield_now();
+    }
+    shutdown.begin(Duration::from_secs(5));
+    assert!(!shutdown.is_finished());
+    stream.write_all(b"cde").unwrap();
+    // The response announces the close and the server closes the connection after it.
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("Connection: close\r\n"));
+    server.join().unwrap().unwrap();
+    assert_eq!(shutdown.in_flight(), 0);
+}
+
+#[test]
+fn limit_connections_per_ip() {
+    let shutdown = Shutdown::new();
+    let limiter = Limiter::new(Limits { max_connections_per_ip: Some(1), ..Limits::default() });
+    let bound = Server::new("127.0.0.1:0".parse().unwrap())
+                    .workers(1)
+                    .shutdown(shutdown.clone())
+                    .limiter(limiter.clone())
+                    .bind()
+                    .unwrap();
+    let addr = bound.local_addr();
+    let server = thread::spawn(move || bound.run::<_, App, _>(|i| i));
+
+    let mut first = TcpStream::connect(addr).unwrap();
+    let mut second = TcpStream::connect(addr).unwrap();
+    second.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    // The second connection is closed without a response.
+    let mut response = String::new();
+    let _ = second.read_to_string(&mut response);
+    assert_eq!(response, "");
+    assert_eq!(limiter.connections(), 1);
+
+    first.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(first, "GET / HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+    first.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+
+    shutdown.begin(Duration::from_secs(5));
+    server.join().unwrap().unwrap();
+    assert_eq!(limiter.connections(), 0);
+}
+
+#[test]
+fn count_requests_in_flight() {
+    let shutdown = Shutdown::new();
+    let limiter = Limiter::new(Limits::default());
+    let bound = Server::new("127.0.0.1:0".parse().unwrap())
+                    .workers(1)
+                    .shutdown(shutdown.clone())
+                    .limiter(limiter.clone())
+                    .bind()
+                    .unwrap();
+    let addr = bound.local_addr();
+    let server = thread::spawn(move || bound.run::<_, App, _>(|i| i));
+
+    let mut stream = TcpStream::connect(addr).unwrap();
+    stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    write!(stream, "POST / HTTP/1.1\r\nHost: localhost\r\nContent-Length: 5\r\n\r\nab").unwrap();
+    // The request is counted while its body is read.
+    while limiter.in_flight() == 0 {
+        thread::yield_now();
+    }
+    stream.write_all(b"cde").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    shutdown.begin(Duration::from_secs(5));
+    server.join().unwrap().unwrap();
+    assert_eq!(limiter.in_flight(), 0);
+}
+
+#[test]
+fn shared_context() {
+    let shutdown = Shutdown::new();
+    let bound = Server::new("127.0.0.1:0".parse().unwrap())
+                    .workers(2)
+                    .reuse_port(false)
+                    .shutdown(shutdown.clone())
+                    .bind()
+                    .unwrap();
+    let addr = bound.local_addr();
+    let server = thread::spawn(move || bound.run_shared::<_, Counter>(AtomicUsize::new(0)));
+
+    // All workers count in the same context.
+    for i in 1..5 {
+        let (_, response) = get(addr, "/");
+        assert!(response.ends_with(&format!("\r\n\r\n{}", i)));
+    }
+
+    shutdown.begin(Duration::from_secs(5));
+    server.join().unwrap().unwrap();
+}
+
+#[test]
+fn failed_worker_stops_server() {
+    let bound = Server::new("127.0.0.1:0".parse().unwrap())
+                    .workers(2)
+                    .reuse_port(false)
+                    .bind()
+                    .unwrap();
+    // The second worker panics while the first one would serve forever.
+    let result = bound.run::<_, App, _>(|i| if i == 1 { panic!("no context") } else { i });
+    assert!(result.is_err());
+}
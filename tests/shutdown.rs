@@ -0,0 +1,54 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use std::time::Duration;
+
+use kinglet::{Handler, Message, Request, Response};
+use kinglet::middleware::Chain;
+use kinglet::shutdown::{Drain, Draining, Shutdown};
+
+struct Context {
+    shutdown: Shutdown,
+}
+
+impl Draining for Context {
+    fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+}
+
+struct App;
+
+impl Handler<Context> for App {
+    fn request(req: Request, ctx: &mut Context) -> Response {
+        let mut res = Response::new(req.version);
+        res.put_body("");
+        res
+    }
+}
+
+fn request() -> Request {
+    common::request(b"GET / HTTP/1.1\r\n\r\n")
+}
+
+#[test]
+fn drain() {
+    let mut ctx = Context { shutdown: Shutdown::new() };
+    let res = Chain::<Drain, App>::request(request(), &mut ctx);
+    assert!(!res.contains_header("Connection"));
+    assert!(!ctx.shutdown.is_finished());
+    ctx.shutdown.clone().begin(Duration::from_secs(60));
+    assert!(ctx.shutdown.is_draining());
+    let res = Chain::<Drain, App>::request(request(), &mut ctx);
+    assert_eq!(res.get_value_header("Connection"), Some(&b"close"[..]));
+}
+
+#[test]
+fn deadline() {
+    let shutdown = Shutdown::new();
+    shutdown.begin(Duration::from_secs(0));
+    shutdown.begin(Duration::from_secs(60));
+    assert!(shutdown.is_finished());
+}
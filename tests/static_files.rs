@@ -0,0 +1,179 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::PathBuf;
+
+use kinglet::{Body, Handler, Message, Response, StatusCode};
+use kinglet::static_files::{self, ServeFiles, StaticFiles, StaticRoot, Symlinks};
+use common::request;
+
+fn get(files: &StaticFiles, path: &str) -> Response {
+    files.serve(&request(format!("GET {} HTTP/1.1\r\n\r\n", path).as_bytes()))
+}
+
+fn body(res: &Response) -> Vec<u8> {
+    match res.body() {
+        Some(&Body::Bytes(ref bytes)) => bytes.clone(),
+        Some(&Body::File(ref file)) => {
+            let mut bytes = Vec::new();
+            file.copy_to(&mut bytes).unwrap();
+            bytes
+        }
+        _ => panic!("no fixed size body"),
+    }
+}
+
+/// Creates a fresh directory tree for a test.
+fn root(name: &str) -> PathBuf {
+    let root = env::temp_dir().join(format!("kinglet-static-{}", name));
+    let _ = fs::remove_dir_all(&root);
+    fs::create_dir_all(root.join("docs")).unwrap();
+    fs::create_dir_all(root.join("empty")).unwrap();
+    File::create(root.join("hello.txt")).unwrap().write_all(b"Hello World!").unwrap();
+    File::create(root.join("docs/index.html")).unwrap().write_all(b"<h1>Docs</h1>").unwrap();
+    File::create(root.join("empty/a b.css")).unwrap();
+    File::create(root.join(".secret")).unwrap().write_all(b"secret").unwrap();
+    root
+}
+
+#[test]
+fn serve_file() {
+    let files = StaticFiles::new(root("serve_file"));
+    let res = get(&files, "/hello.txt?x=1");
+    assert_eq!(res.status, StatusCode::Ok);
+    assert_eq!(body(&res), b"Hello World!");
+    assert_eq!(res.get_value_header("Content-Type"), Some(&b"text/plain; charset=utf-8"[..]));
+    assert!(res.get_value_header("Last-Modified").unwrap().ends_with(b" GMT"));
+    let etag = res.get_value_header("ETag").unwrap();
+    assert!(etag.starts_with(b"\"") && etag.ends_with(b"-c\""));
+}
+
+#[test]
+fn head_request() {
+    let files = StaticFiles::new(root("head_request"));
+    let res = files.serve(&request(b"HEAD /hello.txt HTTP/1.1\r\n\r\n"));
+    assert_eq!(res.status, StatusCode::Ok);
+    assert!(res.body().is_none());
+    assert_eq!(res.get_value_header("Content-Length"), Some(&b"12"[..]));
+}
+
+#[test]
+fn method_not_allowed() {
+    let files = StaticFiles::new(root("method_not_allowed"));
+    let res = files.serve(&request(b"POST /hello.txt HTTP/1.1\r\n\r\n"));
+    assert_eq!(res.status, StatusCode::MethodNotAllowed);
+    assert_eq!(res.get_value_header("Allow"), Some(&b"GET, HEAD"[..]));
+}
+
+#[test]
+fn traversal() {
+    let files = StaticFiles::new(root("traversal").join("docs"));
+    assert_eq!(get(&files, "/../hello.txt").status, StatusCode::NotFound);
+    assert_eq!(get(&files, "/%2e%2e/hello.txt").status, StatusCode::NotFound);
+    assert_eq!(get(&files, "/..%2fhello.txt").status, StatusCode::NotFound);
+    assert_eq!(get(&files, "/./index.html").status, StatusCode::Ok);
+    assert_eq!(files.resolve("/a/%2E%2E"), None);
+}
+
+#[test]
+fn hidden_files() {
+    let files = StaticFiles::new(root("hidden_files"));
+    assert_eq!(get(&files, "/.secret").status, StatusCode::NotFound);
+    let files = files.hidden(true);
+    assert_eq!(body(&get(&files, "/.secret")), b"secret");
+}
+
+#[test]
+fn directories() {
+    let files = StaticFiles::new(root("directories"));
+    let res = get(&files, "/docs");
+    assert_eq!(res.status, StatusCode::MovedPermanently);
+    assert_eq!(res.get_value_header("Location"), Some(&b"/docs/"[..]));
+    let res = get(&files, "//docs");
+    assert_eq!(res.get_value_header("Location"), Some(&b"/docs/"[..]));
+    let res = get(&files, "/docs/");
+    assert_eq!(body(&res), b"<h1>Docs</h1>");
+    assert_eq!(res.get_value_header("Content-Type"), Some(&b"text/html; charset=utf-8"[..]));
+    assert_eq!(get(&files, "/empty/").status, StatusCode::NotFound);
+}
+
+#[test]
+fn listings() {
+    let files = StaticFiles::new(root("listings")).listings(true);
+    let res = get(&files, "/empty/");
+    assert_eq!(res.status, StatusCode::Ok);
+    let html = String::from_utf8(body(&res).to_vec()).unwrap();
+    assert!(html.contains("<title>Index of /empty/</title>"));
+    assert!(html.contains("<a href=\"a%20b.css\">a b.css</a>"));
+    let html = String::from_utf8(body(&get(&files, "/")).to_vec()).unwrap();
+    assert!(html.contains("<a href=\"docs/\">docs/</a>"));
+    assert!(!html.contains(".secret"));
+}
+
+#[test]
+fn content_types() {
+    assert_eq!(static_files::content_type("a/b.PNG".as_ref()), "image/png");
+    assert_eq!(static_files::content_type("a.tar.gz".as_ref()), "application/gzip");
+    assert_eq!(static_files::content_type("Makefile".as_ref()), "application/octet-stream");
+}
+
+#[cfg(unix)]
+#[test]
+fn symlinks() {
+    use std::os::unix::fs::symlink;
+    let root = root("symlinks");
+    let outside = env::temp_dir().join("kinglet-static-symlinks-outside.txt");
+    File::create(&outside).unwrap().write_all(b"outside").unwrap();
+    symlink(&outside, root.join("outside.txt")).unwrap();
+    symlink(root.join("hello.txt"), root.join("inside.txt")).unwrap();
+    let files = StaticFiles::new(root);
+    assert_eq!(get(&files, "/outside.txt").status, StatusCode::NotFound);
+    assert_eq!(body(&get(&files, "/inside.txt")), b"Hello World!");
+    let files = files.symlinks(Symlinks::Deny);
+    assert_eq!(get(&files, "/inside.txt").status, StatusCode::NotFound);
+    assert_eq!(get(&files, "/hello.txt").status, StatusCode::Ok);
+    let files = files.symlinks(Symlinks::Follow);
+    assert_eq!(body(&get(&files, "/outside.txt")), b"outside");
+}
+
+#[test]
+fn handler() {
+    struct Context {
+        files: StaticFiles,
+    }
+    impl StaticRoot for Context {
+        fn static_files(&self) -> &StaticFiles {
+            &self.files
+        }
+    }
+    let mut ctx = Context { files: StaticFiles::new(root("handler")) };
+    let req = request(b"GET /hello.txt HTTP/1.1\r\n\r\n");
+    let res = <ServeFiles<Context> as Handler<Context>>::request(req, &mut ctx);
+    assert_eq!(body(&res), b"Hello World!");
+}
+
+#[test]
+fn file_range() {
+    let files = StaticFiles::new(root("file_range"));
+    let res = files.serve(&request(b"GET /hello.txt HTTP/1.1\r\nRange: bytes=-6\r\n\r\n"));
+    assert_eq!(res.status, StatusCode::PartialContent);
+    assert_eq!(res.get_value_header("Content-Range"), Some(&b"bytes 6-11/12"[..]));
+    assert_eq!(body(&res), b"World!");
+}
+
+#[test]
+fn not_modified() {
+    let files = StaticFiles::new(root("not_modified"));
+    let etag = get(&files, "/hello.txt").get_value_header("ETag").unwrap().to_vec();
+    let mut head = b"GET /hello.txt HTTP/1.1\r\nIf-None-Match: ".to_vec();
+    head.extend(etag);
+    head.extend(b"\r\n\r\n");
+    let res = files.serve(&request(&head));
+    assert_eq!(res.status, StatusCode::NotModified);
+    assert!(res.body().is_none());
+}
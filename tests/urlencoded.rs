@@ -0,0 +1,66 @@
+extern crate httparse;
+extern crate kinglet;
+
+mod common;
+
+use kinglet::Error;
+use kinglet::urlencoded;
+use common::request_with_body;
+
+#[test]
+fn decode() {
+    assert_eq!(urlencoded::decode(b"a+b%20c%2Fd"), Ok("a b c/d".to_owned()));
+    assert_eq!(urlencoded::decode(b"100%"), Ok("100%".to_owned()));
+    assert_eq!(urlencoded::decode(b"%zz%4"), Ok("%zz%4".to_owned()));
+    assert_eq!(urlencoded::decode(b"%C3%BC"), Ok("\u{fc}".to_owned()));
+    assert!(urlencoded::decode(b"%FF").is_err());
+}
+
+#[test]
+fn query() {
+    let req = request_with_body(b"GET /search?q=rust+http&tag=a&tag=b&&empty&x=1%3D2#frag HTTP/1.1\r\n\r\n",
+                                b"");
+    assert_eq!(req.query_string(), Some("q=rust+http&tag=a&tag=b&&empty&x=1%3D2"));
+    let query = req.query().unwrap();
+    assert_eq!(query.len(), 5);
+    assert_eq!(query.get("q"), Some("rust http"));
+    assert_eq!(query.get_all("tag"), vec!["a", "b"]);
+    assert_eq!(query.get("empty"), Some(""));
+    assert_eq!(query.get("x"), Some("1=2"));
+    assert_eq!(query.get("missing"), None);
+}
+
+#[test]
+fn no_query() {
+    let req = request_with_body(b"GET /search HTTP/1.1\r\n\r\n", b"");
+    assert_eq!(req.query_string(), None);
+    assert!(req.query().unwrap().is_empty());
+}
+
+#[test]
+fn form() {
+    let req = request_with_body(b"POST /login HTTP/1.1\r\n\
+                                  Content-Type: application/x-www-form-urlencoded; charset=utf-8\r\n\r\n",
+                                b"user=alice&password=p%40ss+word");
+    let form = req.form().unwrap();
+    assert_eq!(form.get("user"), Some("alice"));
+    assert_eq!(form.get("password"), Some("p@ss word"));
+}
+
+#[test]
+fn form_content_type() {
+    let req = request_with_body(b"POST / HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n", b"a=b");
+    assert_eq!(req.form(), Err(Error::UnsupportedMediaType));
+    assert_eq!(Error::UnsupportedMediaType.status(),
+               kinglet::StatusCode::UnsupportedMediaType);
+}
+
+#[test]
+fn form_limits() {
+    let req = request_with_body(b"POST / HTTP/1.1\r\n\
+                                  Content-Type: application/x-www-form-urlencoded\r\n\r\n",
+                                b"a=1&b=2&c=3");
+    assert_eq!(req.form_with_limits(10, 10), Err(Error::TooLarge));
+    assert_eq!(req.form_with_limits(100, 2), Err(Error::TooLarge));
+    assert_eq!(req.form_with_limits(100, 3).unwrap().len(), 3);
+}